@@ -0,0 +1,91 @@
+// 基本操作のマイクロベンチマーク (性能に関する変更の比較の基準)
+// cargo bench --bench core
+// 環境変数 STM_BENCH_ITERS で 1 回の計測の反復回数を指定できる (デフォルト 200000)
+//
+// 計測する操作:
+//   read/1       1 ストライプを読む read_transaction
+//   write/1      1 ストライプに書き込む write_transaction (commit を含む)
+//   write/N      N ストライプを読み書きする競合のない write_transaction
+//   contended/2  2 スレッドが同じストライプに書き込む write_transaction (1 commit あたり)
+// 各操作は 1 回の試行の後に SAMPLES 回計測し、1 操作あたりの時間の最小値と中央値を表示する
+//
+// cargo test では (--bench 引数なしで起動されるため) 反復回数を SMOKE_ITERS にして、全ての計測が完走することだけを確認する
+
+use std::env;
+use std::hint::black_box;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use stm_rust::tl2::{self, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const SAMPLES: usize = 5;
+const SMOKE_ITERS: usize = 100;
+
+// f を iterations 回実行する計測を SAMPLES 回行い、1 回あたりの (最小, 中央値) を返す
+fn measure(iterations: usize, mut f: impl FnMut(usize)) -> (Duration, Duration) {
+    f(iterations);     // 試行 (cache と分岐予測を温める)
+    let mut samples: Vec<Duration> = (0..SAMPLES).map(|_| {
+        let start = Instant::now();
+        f(iterations);
+        start.elapsed() / iterations as u32
+    }).collect();
+    samples.sort_unstable();
+    (samples[0], samples[SAMPLES / 2])
+}
+
+fn report(name: &str, iterations: usize, (min, median): (Duration, Duration)) {
+    println!("{:>14} {:>12} {:>12} {:>12}", name, iterations, min.as_nanos(), median.as_nanos());
+}
+
+fn read_one(stm: &STM, iterations: usize) {
+    for _ in 0..iterations {
+        black_box(stm.read_transaction(|tr| tl2::STMResult::Ok(load!(tr, 0))).unwrap());
+    }
+}
+
+fn write_stripes(stm: &STM, stripes: usize, iterations: usize) {
+    for _ in 0..iterations {
+        stm.write_transaction(|tr| {
+            for i in 0..stripes {
+                let v = u64::from_le_bytes(load!(tr, i * STRIPE_SIZE));
+                store!(tr, i * STRIPE_SIZE, (v + 1).to_le_bytes());
+            }
+            tl2::STMResult::Ok(())
+        }).unwrap();
+    }
+}
+
+fn main() {
+    let bench = env::args().any(|arg| arg == "--bench");
+    let iterations: usize = if bench {
+        env::var("STM_BENCH_ITERS")
+            .map(|v| v.parse().expect("STM_BENCH_ITERS must be a number"))
+            .unwrap_or(200000)
+    } else {
+        SMOKE_ITERS
+    };
+
+    println!("{:>14} {:>12} {:>12} {:>12}", "operation", "iterations", "min [ns]", "median [ns]");
+    let stm = STM::new();
+    report("read/1", iterations, measure(iterations, |n| read_one(&stm, n)));
+    report("write/1", iterations, measure(iterations, |n| write_stripes(&stm, 1, n)));
+    for stripes in [8, 32] {
+        let stm = STM::new();
+        report(&format!("write/{}", stripes), iterations, measure(iterations, |n| write_stripes(&stm, stripes, n)));
+    }
+
+    let stm = STM::new();
+    let contended = measure(iterations, |n| {
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| write_stripes(&stm, 1, n / 2));
+            }
+        });
+    });
+    report("contended/2", iterations, contended);
+
+    // 全ての書き込みが失われずに commit されている
+    let runs = (SAMPLES + 1) * (iterations / 2) * 2;
+    assert_eq!(u64::from_le_bytes(stm.read_raw(0)), runs as u64);
+}
@@ -0,0 +1,60 @@
+// 隣接するバイトを 2 スレッドが更新する場合の、ストライプの大きさ (S = 8 と S = 1) ごとの競合の比較
+// cargo bench --bench fine_grained
+// 環境変数 STM_BENCH_ITERS で各スレッドの更新回数を指定できる (デフォルト 200000)
+//
+// スレッド t はバイト t だけを +1 し続ける。S = 8 では 2 つのバイトが同じストライプに載るため、
+// 論理的には独立した更新が互いに競合する (false conflict)。S = 1 ではバイトごとに別のストライプとなり競合しない
+// (Memory::set_fine_grained による範囲ごとの細粒度化の代わりに、細粒度にしたいデータを STM<1> に置く; tl2.rs の冒頭を参照)
+
+use std::env;
+use std::hint;
+use std::thread;
+use std::time::Instant;
+
+use stm_rust::tl2::{self, STM};
+use stm_rust::{load, store};
+
+const THREADS: usize = 2;
+const WORK: usize = 64;     // 読み込みと書き込みの間の処理 (spin の回数)
+
+fn run<const S: usize>(iterations: u64) {
+    let stm = STM::<S>::new_sized().with_stats();
+    let start = Instant::now();
+    thread::scope(|s| {
+        for byte in 0..THREADS {
+            let stm = &stm;
+            s.spawn(move || {
+                let stripe = byte & !(S - 1);
+                for _ in 0..iterations {
+                    stm.write_transaction(|tr| {
+                        let mut v = load!(tr, stripe);
+                        for _ in 0..WORK {
+                            hint::spin_loop();
+                        }
+                        v[byte - stripe] = v[byte - stripe].wrapping_add(1);
+                        store!(tr, stripe, v);
+                        tl2::STMResult::Ok(())
+                    }).unwrap();
+                }
+            });
+        }
+    });
+    let elapsed = start.elapsed();
+    for byte in 0..THREADS {
+        let stripe = byte & !(S - 1);
+        assert_eq!(stm.read_raw(stripe)[byte - stripe], iterations as u8, "lost update");
+    }
+    let stats = stm.stats().unwrap();
+    println!("{:>6} {:>12} {:>12} {:>12} {:>14.5}", S, stats.commits, stats.conflict_aborts, elapsed.as_millis(),
+        stats.conflict_aborts as f64 / stats.commits as f64);
+}
+
+fn main() {
+    let iterations = env::var("STM_BENCH_ITERS")
+        .map(|v| v.parse().expect("STM_BENCH_ITERS must be a number"))
+        .unwrap_or(200000);
+
+    println!("{:>6} {:>12} {:>12} {:>12} {:>14}", "S", "commits", "aborts", "time [ms]", "aborts/commit");
+    run::<8>(iterations);
+    run::<1>(iterations);
+}
@@ -0,0 +1,49 @@
+// group commit (STM::with_group_commit) の有無による global_clock への書き込み回数と処理時間の比較
+// cargo bench --bench group_commit
+// 環境変数 STM_BENCH_ITERS で各スレッドの反復回数を指定できる (デフォルト 20000)
+//
+// 各スレッドは自分のストライプのカウンタを increment し続ける (互いに競合しない書き込み)。
+// clock は global_clock が進んだ回数 (= global_clock への書き込み回数)
+
+use std::env;
+use std::time::{Duration, Instant};
+
+use stm_rust::tl2::{self, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+fn main() {
+    let iterations: usize = env::var("STM_BENCH_ITERS")
+        .map(|v| v.parse().expect("STM_BENCH_ITERS must be a number"))
+        .unwrap_or(20000);
+
+    println!("{:>12} {:>12} {:>12} {:>12} {:>12}", "window [us]", "threads", "commits", "clock", "time [ms]");
+    for window in [None, Some(10), Some(100)] {
+        for threads in [2, 8] {
+            let mut builder = STM::builder();
+            if let Some(us) = window {
+                builder = builder.group_commit(Duration::from_micros(us));
+            }
+            let stm = builder.build();
+
+            let start = Instant::now();
+            stm.scope(|s| {
+                for t in 0..threads {
+                    s.spawn(move |stm| {
+                        let mine = t * STRIPE_SIZE;
+                        for _ in 0..iterations {
+                            stm.write_transaction(|tr| {
+                                let val = u64::from_le_bytes(load!(tr, mine));
+                                store!(tr, mine, (val + 1).to_le_bytes());
+                                tl2::STMResult::Ok(())
+                            }).unwrap();
+                        }
+                    });
+                }
+            });
+            let elapsed = start.elapsed();
+
+            let window = window.map_or("-".to_string(), |us| us.to_string());
+            println!("{:>12} {:>12} {:>12} {:>12} {:>12}", window, threads, threads * iterations, stm.global_version(), elapsed.as_millis());
+        }
+    }
+}
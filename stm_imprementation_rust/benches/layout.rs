@@ -0,0 +1,61 @@
+// Memory::with_layout (同時にアクセスするアドレスの物理的な隣接配置) の有無による処理時間の比較
+// cargo bench --bench layout
+// 環境変数 STM_BENCH_ITERS で各スレッドの反復回数を指定できる (デフォルト 200000)
+//
+// 各トランザクションは 64 byte ずつ離れた (= 別々の cache line にある) グループのストライプを全て読み書きする。
+// with_layout を指定した場合、グループのデータ本体と lock_ver はそれぞれ連続したストライプに配置される。
+// スレッドごとに別のグループを用いるため、トランザクション同士は競合しない
+
+use std::env;
+use std::time::Instant;
+
+use stm_rust::tl2::{self, LayoutHint, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const CACHE_LINE: usize = 64;
+const GROUP_LEN: usize = 8;
+
+// スレッド t のグループ: t * STRIPE_SIZE から CACHE_LINE ごとのアドレス
+fn group(t: usize) -> Vec<usize> {
+    (0..GROUP_LEN).map(|i| t * STRIPE_SIZE + i * CACHE_LINE).collect()
+}
+
+fn main() {
+    let iterations: usize = env::var("STM_BENCH_ITERS")
+        .map(|v| v.parse().expect("STM_BENCH_ITERS must be a number"))
+        .unwrap_or(200000);
+
+    println!("{:>12} {:>12} {:>12} {:>12}", "layout", "threads", "commits", "time [ms]");
+    for layout in [false, true] {
+        for threads in [1, 2, 4] {
+            let mut builder = STM::builder();
+            if layout {
+                let hint = (0..threads).fold(LayoutHint::new(), |hint, t| hint.group(&group(t)));
+                builder = builder.layout(hint);
+            }
+            let stm = builder.build();
+
+            let start = Instant::now();
+            stm.scope(|s| {
+                for t in 0..threads {
+                    s.spawn(move |stm| {
+                        let addrs = group(t);
+                        for _ in 0..iterations {
+                            stm.write_transaction(|tr| {
+                                for addr in addrs.iter() {
+                                    let val = u64::from_le_bytes(load!(tr, *addr));
+                                    store!(tr, *addr, (val + 1).to_le_bytes());
+                                }
+                                tl2::STMResult::Ok(())
+                            }).unwrap();
+                        }
+                    });
+                }
+            });
+            let elapsed = start.elapsed();
+
+            let label = if layout { "grouped" } else { "-" };
+            println!("{:>12} {:>12} {:>12} {:>12}", label, threads, threads * iterations, elapsed.as_millis());
+        }
+    }
+}
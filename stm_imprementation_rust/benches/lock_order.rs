@@ -0,0 +1,30 @@
+// commit 時の lock の獲得順序 (LockOrder) ごとの、食事する哲学者問題の retry の比較
+// cargo bench --bench lock_order
+// 環境変数 STM_BENCH_ITERS で各哲学者の反復回数を指定できる (デフォルト 100000)
+// retries は closure の再実行回数 (競合と箸待ちの合計; PhilosophersStats を参照)
+
+use std::env;
+
+use stm_rust::scenarios::Philosophers;
+use stm_rust::tl2::LockOrder;
+
+fn main() {
+    let iterations = env::var("STM_BENCH_ITERS")
+        .map(|v| v.parse().expect("STM_BENCH_ITERS must be a number"))
+        .unwrap_or(100000);
+
+    let orders = [
+        ("hash", LockOrder::HashOrder),
+        ("ascending", LockOrder::AddressAscending),
+        ("contention", LockOrder::ContentionDescending),
+    ];
+    println!("{:>12} {:>12} {:>12} {:>12} {:>12} {:>14}", "order", "philosophers", "commits", "retries", "time [ms]", "retries/commit");
+    for (name, lock_order) in orders {
+        for philosophers in [2, 4, 8, 16] {
+            let stats = Philosophers { philosophers, iterations, lock_order, ..Philosophers::default() }.run();
+            assert_eq!(stats.inconsistencies, 0);
+            println!("{:>12} {:>12} {:>12} {:>12} {:>12} {:>14.3}", name, philosophers, stats.commits, stats.retries,
+                stats.elapsed.as_millis(), stats.retries as f64 / stats.commits as f64);
+        }
+    }
+}
@@ -0,0 +1,24 @@
+// 食事する哲学者問題のベンチマーク
+// cargo bench --bench philosophers
+// 環境変数 STM_BENCH_ITERS で各哲学者の反復回数を指定できる (デフォルト 100000)
+// read_set / write_set のハッシュ関数ごと (SipHash / Fast) に計測する
+
+use std::env;
+
+use stm_rust::scenarios::Philosophers;
+use stm_rust::tl2::SetHasher;
+
+fn main() {
+    let iterations = env::var("STM_BENCH_ITERS")
+        .map(|v| v.parse().expect("STM_BENCH_ITERS must be a number"))
+        .unwrap_or(100000);
+
+    println!("{:>8} {:>12} {:>12} {:>12} {:>12}", "hasher", "philosophers", "commits", "retries", "time [ms]");
+    for (name, hasher) in [("sip", SetHasher::default()), ("fast", SetHasher::Fast)] {
+        for philosophers in [2, 4, 8, 16, 32] {
+            let stats = Philosophers { philosophers, iterations, hasher: hasher.clone(), ..Philosophers::default() }.run();
+            assert_eq!(stats.inconsistencies, 0);
+            println!("{:>8} {:>12} {:>12} {:>12} {:>12}", name, philosophers, stats.commits, stats.retries, stats.elapsed.as_millis());
+        }
+    }
+}
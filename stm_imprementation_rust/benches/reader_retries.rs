@@ -0,0 +1,82 @@
+// 多数のストライプに書き込む長い commit と並行して読む reader の retry の計測
+// (ストライプ単位の read/write lock を採用しない根拠; tl2.rs の冒頭を参照)
+// cargo bench --bench reader_retries
+// 環境変数 STM_BENCH_ITERS で各 reader の読み込みトランザクションの回数を指定できる (デフォルト 100000)
+//
+// writer は全ストライプを書き換える commit を繰り返し、reader は READ_STRIPES 個のストライプを読む read_transaction を繰り返す。
+// 楽観的な読み込みでは、reader が retry するのは読んだストライプが commit 中 (lock 中) か読み込みの後に更新された場合に限られる。
+// writer の有無ごとに、reader の 1 回の読み込みあたりの closure の実行回数と throughput を表示する
+
+use std::env;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::atomic::Ordering::Relaxed;
+use std::thread;
+use std::time::Instant;
+
+use stm_rust::tl2::{self, MEM_SIZE, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const STRIPES: usize = MEM_SIZE / STRIPE_SIZE;
+const READ_STRIPES: usize = 4;
+
+fn run(readers: usize, writer: bool, iterations: u64) {
+    let stm = STM::new();
+    let runs = AtomicU64::new(0);
+    let commits = AtomicU64::new(0);
+    let done = AtomicBool::new(false);
+    let start = Instant::now();
+    thread::scope(|s| {
+        if writer {
+            s.spawn(|| {
+                let mut round = 0u64;
+                while !done.load(Relaxed) {
+                    round += 1;
+                    stm.write_transaction(|tr| {
+                        for i in 0..STRIPES {
+                            store!(tr, i * STRIPE_SIZE, round.to_le_bytes());
+                        }
+                        tl2::STMResult::Ok(())
+                    });
+                    commits.fetch_add(1, Relaxed);
+                }
+            });
+        }
+        let handles: Vec<_> = (0..readers).map(|r| {
+            let (stm, runs) = (&stm, &runs);
+            s.spawn(move || {
+                for i in 0..iterations as usize {
+                    let first = (i * 7 + r) % (STRIPES - READ_STRIPES);
+                    let values = stm.read_transaction(|tr| {
+                        runs.fetch_add(1, Relaxed);
+                        let mut values = [0u64; READ_STRIPES];
+                        for (k, value) in values.iter_mut().enumerate() {
+                            *value = u64::from_le_bytes(load!(tr, (first + k) * STRIPE_SIZE));
+                        }
+                        tl2::STMResult::Ok(values)
+                    }).unwrap();
+                    assert!(values.iter().all(|v| *v == values[0]), "torn snapshot");
+                }
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        done.store(true, Relaxed);
+    });
+    let elapsed = start.elapsed();
+    let reads = readers as u64 * iterations;
+    println!("{:>8} {:>8} {:>10} {:>12} {:>12.4} {:>14.0}", readers, if writer { "yes" } else { "no" }, commits.load(Relaxed),
+        reads, runs.load(Relaxed) as f64 / reads as f64, reads as f64 / elapsed.as_secs_f64());
+}
+
+fn main() {
+    let iterations = env::var("STM_BENCH_ITERS")
+        .map(|v| v.parse().expect("STM_BENCH_ITERS must be a number"))
+        .unwrap_or(100000);
+
+    println!("{:>8} {:>8} {:>10} {:>12} {:>12} {:>14}", "readers", "writer", "commits", "reads", "runs/read", "reads/s");
+    for readers in [1, 2, 4] {
+        run(readers, false, iterations);
+        run(readers, true, iterations);
+    }
+}
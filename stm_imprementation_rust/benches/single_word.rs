@@ -0,0 +1,75 @@
+// 観測側の読み込み (全ストライプを 1 つずつ読む read_transaction) の処理時間の比較
+// cargo bench --bench single_word
+// 環境変数 STM_BENCH_ITERS で観測の回数を指定できる (デフォルト 100000)
+//
+// load (copy + fence(SeqCst) + cache への記録) と load_single_word (1 回の atomic な load) で比較する。
+// writers > 0 の場合は、観測中に別のスレッドがストライプへの書き込みを続ける
+
+use std::env;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::Instant;
+
+use stm_rust::tl2::{self, MEM_SIZE, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const STRIPES: usize = MEM_SIZE / STRIPE_SIZE;
+
+fn observe(stm: &STM, single_word: bool) -> u64 {
+    stm.read_transaction(|tr| {
+        let mut sum = 0u64;
+        for i in 0..STRIPES {
+            let addr = i * STRIPE_SIZE;
+            let val = if single_word {
+                match tr.load_single_word(addr) {
+                    Some(v) => v,
+                    None => return tl2::STMResult::Retry,
+                }
+            } else {
+                u64::from_le_bytes(load!(tr, addr))
+            };
+            sum = sum.wrapping_add(val);
+        }
+        tl2::STMResult::Ok(sum)
+    }).unwrap()
+}
+
+fn main() {
+    let iterations: usize = env::var("STM_BENCH_ITERS")
+        .map(|v| v.parse().expect("STM_BENCH_ITERS must be a number"))
+        .unwrap_or(100000);
+
+    println!("{:>12} {:>12} {:>12} {:>12}", "load", "writers", "reads", "time [ms]");
+    for writers in [0, 1] {
+        for single_word in [false, true] {
+            let stm = STM::new();
+            let done = AtomicBool::new(false);
+            let elapsed = std::thread::scope(|s| {
+                for w in 0..writers {
+                    let (stm, done) = (&stm, &done);
+                    s.spawn(move || {
+                        let mut i = 0;
+                        while !done.load(Relaxed) {
+                            i += 1;
+                            stm.write_transaction(|tr| {
+                                store!(tr, (i + w) % STRIPES * STRIPE_SIZE, (i as u64).to_le_bytes());
+                                tl2::STMResult::Ok(())
+                            });
+                        }
+                    });
+                }
+
+                let start = Instant::now();
+                for _ in 0..iterations {
+                    std::hint::black_box(observe(&stm, single_word));
+                }
+                let elapsed = start.elapsed();
+                done.store(true, Relaxed);
+                elapsed
+            });
+
+            let label = if single_word { "single_word" } else { "load" };
+            println!("{:>12} {:>12} {:>12} {:>12}", label, writers, iterations * STRIPES, elapsed.as_millis());
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// TxContext の再利用 (STM::write_transaction_in) の有無による heap の確保回数と処理時間の比較
+// cargo bench --bench tx_context
+// 環境変数 STM_BENCH_ITERS で反復回数を指定できる (デフォルト 500000)
+//
+// 各トランザクションは 2 つのストライプを読み書きする (哲学者の食事の 1 回の食事に相当)。
+// global allocator を確保回数を数えるものに差し替えて、トランザクションあたりの確保回数を表示する
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::Instant;
+
+use stm_rust::tl2::{self, TxContext, STM};
+use stm_rust::{load, store};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn eat(tr: &mut tl2::WriteTrans<'_>) -> tl2::STMResult<()> {
+    let left = u64::from_le_bytes(load!(tr, 0));
+    let right = u64::from_le_bytes(load!(tr, 8));
+    store!(tr, 0, (left + 1).to_le_bytes());
+    store!(tr, 8, (right + 1).to_le_bytes());
+    tl2::STMResult::Ok(())
+}
+
+fn main() {
+    let iterations: usize = env::var("STM_BENCH_ITERS")
+        .map(|v| v.parse().expect("STM_BENCH_ITERS must be a number"))
+        .unwrap_or(500000);
+
+    println!("{:>12} {:>12} {:>12} {:>12}", "context", "commits", "allocs/tx", "time [ms]");
+    for reuse in [false, true] {
+        let stm = STM::new();
+        let mut ctx = TxContext::new();
+        let before = ALLOCATIONS.load(Relaxed);
+        let start = Instant::now();
+        for _ in 0..iterations {
+            if reuse {
+                stm.write_transaction_in(&mut ctx, eat).unwrap();
+            } else {
+                stm.write_transaction(eat).unwrap();
+            }
+        }
+        let elapsed = start.elapsed();
+        let allocations = ALLOCATIONS.load(Relaxed) - before;
+
+        let label = if reuse { "reused" } else { "-" };
+        println!("{:>12} {:>12} {:>12.2} {:>12}", label, iterations, allocations as f64 / iterations as f64, elapsed.as_millis());
+    }
+}
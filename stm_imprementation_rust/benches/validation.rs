@@ -0,0 +1,73 @@
+// 大きな read_set を持つトランザクションの競合下でのベンチマーク
+// cargo bench --bench validation
+// 環境変数 STM_BENCH_ITERS で各スレッドの反復回数を指定できる (デフォルト 100000)
+//
+// 各スレッドは全ストライプを読み込み、値が 1 のストライプがなければ自分のストライプを 1 にし、
+// 自分のストライプが 1 ならば 0 に戻す。不変条件: 値が 1 のストライプは常に高々 1 つ
+// read_set の検証 (差分検証を含む) が誤っていれば、複数のスレッドが同時に 1 を書き込みうる。
+
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Instant;
+
+use stm_rust::tl2::{self, ReadTrans, WriteTrans, MEM_SIZE, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const NUM_STRIPES: usize = MEM_SIZE / STRIPE_SIZE;
+
+fn main() {
+    let iterations = env::var("STM_BENCH_ITERS")
+        .map(|v| v.parse().expect("STM_BENCH_ITERS must be a number"))
+        .unwrap_or(100000);
+
+    println!("{:>12} {:>12} {:>12}", "threads", "commits", "time [ms]");
+    for threads in [1, 2, 4, 8] {
+        let stm = STM::new();
+        let done = AtomicBool::new(false);
+        let start = Instant::now();
+        stm.scope(|s| {
+            let workers: Vec<_> = (0..threads).map(|t| s.spawn(move |stm| {
+                let mine = t * STRIPE_SIZE;
+                let toggle = |tr: &mut WriteTrans<'_>| {
+                    let mut ones = 0;
+                    for i in 0..NUM_STRIPES {
+                        ones += load!(tr, i * STRIPE_SIZE)[0] as usize;
+                    }
+                    let mut stripe = load!(tr, mine);
+                    if stripe[0] == 1 {
+                        stripe[0] = 0;
+                    } else if ones == 0 {
+                        stripe[0] = 1;
+                    }
+                    store!(tr, mine, stripe);
+                    tl2::STMResult::Ok(())
+                };
+                for _ in 0..iterations {
+                    stm.write_transaction(toggle).unwrap();
+                }
+            })).collect();
+
+            let observer = s.spawn(|stm| {
+                let count_ones = |tr: &mut ReadTrans<'_>| {
+                    let mut ones = 0;
+                    for i in 0..NUM_STRIPES {
+                        ones += load!(tr, i * STRIPE_SIZE)[0] as usize;
+                    }
+                    tl2::STMResult::Ok(ones)
+                };
+                while !done.load(Ordering::Relaxed) {
+                    assert!(stm.read_transaction(count_ones).unwrap() <= 1, "invariant violated");
+                    thread::yield_now();
+                }
+            });
+
+            for w in workers {
+                w.join().unwrap();
+            }
+            done.store(true, Ordering::Relaxed);
+            observer.join().unwrap();
+        });
+        println!("{:>12} {:>12} {:>12}", threads, threads * iterations, start.elapsed().as_millis());
+    }
+}
@@ -0,0 +1,201 @@
+// 記録したスケジュールに従ってトランザクションを決定的な順序で実行する (デバッグ用)
+// スケジュールはスレッド番号の列で、先頭の番号のスレッドだけが次の 1 ステップを実行できる。
+// 各スレッドのステップは「closure の実行」と「commit の試行」が交互に続く
+// (closure が競合で retry / RetryOk になった場合は、次のステップも closure の実行)。
+// 例: 2 つのスレッドが同じアドレスを更新する場合、[0, 1, 0, 1, 1, 1] は
+//     0 が実行, 1 が実行, 0 が commit, 1 が commit (競合して失敗), 1 が再実行, 1 が commit
+// という interleaving を再現する。
+// with_split_commit を指定した場合、commit の試行は「lock の獲得」「version の割り当て」「検証と書き込みの公開」の 3 ステップになる
+// (lock の獲得に失敗した場合は 1 ステップで終わる)。
+// スケジュールを使い切った後のステップは順序付けずに (通常の STM と同様に) 実行する。
+// スケジュールには各スレッドが実際に行うステップのみを並べること (終了したスレッドの番号が先頭に残ると他のスレッドは進めない)。
+
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+use crate::tl2::{ReadTrans, STMResult, WriteTrans, STM};
+
+pub struct DeterministicSTM {
+    stm: STM,
+    schedule: Mutex<VecDeque<usize>>,
+    turn: Condvar,      // スケジュールの先頭が進んだことを通知する
+    split_commit: bool,
+}
+
+impl DeterministicSTM {
+    pub fn new(stm: STM, schedule: Vec<usize>) -> Self {
+        DeterministicSTM { stm, schedule: Mutex::new(schedule.into()), turn: Condvar::new(), split_commit: false }
+    }
+
+    // commit の各段階の間に他のスレッドのステップを割り込ませる (STM::lock_for_commit などを参照)
+    pub fn with_split_commit(mut self) -> Self {
+        self.split_commit = true;
+        self
+    }
+
+    pub fn stm(&self) -> &STM {
+        &self.stm
+    }
+
+    // 残りのスケジュールのステップ数
+    pub fn remaining(&self) -> usize {
+        self.schedule.lock().unwrap().len()
+    }
+
+    // スレッド thread の番が来るまで待ってから step を実行し、スケジュールを 1 つ進める
+    fn step<T>(&self, thread: usize, step: impl FnOnce() -> T) -> T {
+        let mut schedule = self.schedule.lock().unwrap();
+        loop {
+            match schedule.front() {
+                None => {       // スケジュールを使い切った
+                    drop(schedule);
+                    return step();
+                }
+                Some(&front) if front == thread => break,
+                Some(_) => schedule = self.turn.wait(schedule).unwrap(),
+            }
+        }
+        // 番が来たスレッドは lock を保持したまま実行する (他のスレッドはその間進めない)
+        let result = step();
+        schedule.pop_front();
+        self.turn.notify_all();
+        result
+    }
+
+    // 1 回の実行 (read_version の取得を含む) を 1 ステップとする
+    pub fn read_transaction<F, R>(&self, thread: usize, f: F) -> Option<R>
+    where F: Fn(&mut ReadTrans) -> STMResult<R> {
+        loop {
+            if self.stm.is_poisoned() {
+                return None;
+            }
+            let (read_trans, result) = self.step(thread, || {
+                let mut read_trans = ReadTrans::new(&self.stm.mem, 0, self.stm.hasher.clone());
+                let result = f(&mut read_trans);
+                (read_trans, result)
+            });
+            match result {
+                STMResult::Abort => return None,
+                STMResult::RetryOk => continue,
+                STMResult::Retry => {
+                    if read_trans.conflict {
+                        continue;
+                    } else {
+                        return None;
+                    }
+                }
+                STMResult::Ok(val) => {
+                    if read_trans.conflict {
+                        continue;
+                    } else {
+                        return Some(val);
+                    }
+                }
+            }
+        }
+    }
+
+    // closure の実行 (read_version の取得を含む) と commit の試行をそれぞれ 1 ステップとする
+    // retry policy は用いない (待機の有無にかかわらず順序はスケジュールで決まる)
+    pub fn write_transaction<F, R>(&self, thread: usize, f: F) -> Option<R>
+    where F: Fn(&mut WriteTrans) -> STMResult<R> {
+        loop {
+            if self.stm.is_poisoned() {
+                return None;
+            }
+            let (write_trans, outcome) = self.step(thread, || {
+                let mut write_trans = WriteTrans::new(&self.stm.mem, 0, 0, self.stm.hasher.clone());
+                let outcome = f(&mut write_trans);
+                (write_trans, outcome)
+            });
+
+            if write_trans.abort_requested {
+                return None;
+            }
+            let result;
+            match outcome {
+                STMResult::Abort => return None,
+                STMResult::RetryOk => continue,
+                STMResult::Retry => {
+                    if write_trans.conflict {
+                        continue;
+                    } else {
+                        return None;
+                    }
+                }
+                STMResult::Ok(val) => {
+                    if write_trans.conflict {
+                        continue;
+                    } else {
+                        result = val;
+                    }
+                }
+            }
+
+            if let Some(mut write_trans) = self.commit(thread, write_trans) {
+                write_trans.run_callbacks();    // スケジュールのステップには含めない
+                return Some(result);
+            }
+        }
+    }
+
+    // 失敗した場合に獲得済みの lock を解放するところまでを同じステップで行う (write_trans を closure 内で drop)
+    // commit した場合は (lock を解放済みの) write_trans を返す
+    fn commit<'a>(&'a self, thread: usize, mut write_trans: WriteTrans<'a>) -> Option<WriteTrans<'a>> {
+        if !self.split_commit || write_trans.write_set.is_empty() {
+            return self.step(thread, move || self.stm.try_commit(&mut write_trans).map(|_| write_trans));
+        }
+
+        let locked = self.step(thread, move || {
+            let locked = self.stm.lock_for_commit(&mut write_trans);
+            locked.then_some(write_trans)
+        });
+        let mut write_trans = locked?;
+        let new_version = self.step(thread, || self.stm.stamp_commit(&write_trans));
+        self.step(thread, move || self.stm.publish_commit(&mut write_trans, new_version).map(|_| write_trans))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::{load, store};
+
+    // 競合した実行の on_commit は実行せず、commit した実行の分だけ実行する (split commit の有無によらない)
+    #[test]
+    fn runs_callbacks_of_committed_runs() {
+        for split in [false, true] {
+            // 0 が実行, 1 が実行, 0 が commit, 1 が commit (失敗), 1 が再実行, 1 が commit
+            let schedule = if split { vec![0, 1, 0, 0, 0, 1, 1, 1, 1] } else { vec![0, 1, 0, 1, 1, 1] };
+            let mut det = DeterministicSTM::new(STM::new(), schedule);
+            if split {
+                det = det.with_split_commit();
+            }
+            let ran = Arc::new(AtomicUsize::new(0));
+            thread::scope(|s| {
+                for t in 0..2 {
+                    let (det, ran) = (&det, ran.clone());
+                    s.spawn(move || {
+                        det.write_transaction(t, |tr| {
+                            let v = u64::from_le_bytes(load!(tr, 0));
+                            store!(tr, 0, (v + 1).to_le_bytes());
+                            let ran = ran.clone();
+                            tr.on_commit(move || {
+                                ran.fetch_add(1, Relaxed);
+                            });
+                            STMResult::Ok(())
+                        })
+                    });
+                }
+            });
+            assert_eq!(det.remaining(), 0);
+            assert_eq!(u64::from_le_bytes(det.stm().read_raw(0)), 2);
+            assert_eq!(ran.load(Relaxed), 2, "split = {}", split);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+// group commit: commit の準備ができた (write lock を獲得した) トランザクションを短い時間窓の間まとめ、
+// global_clock の更新をまとめて 1 回で済ませる (STM::with_group_commit を参照)
+//
+// 最初に参加したトランザクション (leader) が時間窓の間待ってから締め切り、global_clock を 1 回だけ進めて
+// その version を全員に割り当てる。各メンバーは全員の lock を保持したまま read_set を検証し、
+// 全員の検証が終わってから書き込みを公開する。
+// 同じ version を持つメンバー同士は、一方が他方の書き込み先を読んでいればその検証が (lock を観測して) 失敗するため、
+// commit するメンバーの間には依存がなく、任意の順に 1 つずつ commit したものとみなせる。検証に失敗したメンバーは retry される
+// 1 回の commit の遅延 (最大で時間窓の分) と引き換えに、書き込みの競合が激しい場合の global_clock への書き込みを減らす
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::tl2::{WriteTrans, STM};
+
+pub(crate) struct GroupCommit {
+    window: Duration,
+    open: Mutex<Option<Arc<Batch>>>,    // 参加を受け付けている batch
+}
+
+struct Batch {
+    state: Mutex<BatchState>,
+    cond: Condvar,      // version の割り当てと、各メンバーの検証の終了を通知する
+}
+
+#[derive(Default)]
+struct BatchState {
+    members: usize,
+    written: u64,           // メンバーの書き込み先のストライプ (stripe_bit の和; 差分検証の記録用)
+    version: Option<u64>,   // 締め切り後に leader が割り当てる
+    validated: usize,       // 検証を終えたメンバーの数 (成否を問わない)
+}
+
+impl GroupCommit {
+    pub(crate) fn new(window: Duration) -> Self {
+        GroupCommit { window, open: Mutex::new(None) }
+    }
+
+    // lock 獲得済みの write_trans を batch に参加させて commit する (STM::try_commit と同様の結果を返す)
+    pub(crate) fn commit<const S: usize>(&self, stm: &STM<S>, write_trans: &mut WriteTrans<'_, S>) -> Option<u64> {
+        let (batch, leader) = self.join(write_trans.written_bits());
+        if leader {
+            thread::sleep(self.window);     // 他のトランザクションの参加を待つ
+            *self.open.lock().unwrap() = None;
+            let mut state = batch.state.lock().unwrap();
+            let version = write_trans.mem.inc_global_clock();
+            write_trans.mem.record_commit(version, state.written);
+            state.version = Some(version);
+            batch.cond.notify_all();
+        }
+
+        let mut state = batch.state.lock().unwrap();
+        while state.version.is_none() {
+            state = batch.cond.wait(state).unwrap();
+        }
+        let (new_version, members) = (state.version.unwrap(), state.members);
+        drop(state);
+        if members == 1 {
+            return stm.publish_commit(write_trans, new_version);     // 通常の commit と同じ
+        }
+
+        // 同じ version を割り当てられた他のメンバーの書き込みは差分検証の対象にならないため、read_set 全体を検証する
+        let validated = write_trans.validate_read_set();
+        let mut state = batch.state.lock().unwrap();
+        state.validated += 1;
+        batch.cond.notify_all();
+        while state.validated < members {
+            state = batch.cond.wait(state).unwrap();
+        }
+        drop(state);
+
+        match validated {
+            Ok(()) => stm.apply_commit(write_trans, new_version),
+            Err(addr) => {
+                write_trans.conflict_addr = Some(addr);
+                write_trans.validation_failed = true;
+                None
+            }
+        }
+    }
+
+    // 受付中の batch に参加する (なければ作成して leader となる)
+    fn join(&self, written: u64) -> (Arc<Batch>, bool) {
+        let mut open = self.open.lock().unwrap();
+        let leader = open.is_none();
+        let batch = open.get_or_insert_with(|| Arc::new(Batch { state: Mutex::new(BatchState::default()), cond: Condvar::new() })).clone();
+        let mut state = batch.state.lock().unwrap();
+        state.members += 1;
+        state.written |= written;
+        drop(state);
+        (batch, leader)
+    }
+}
@@ -0,0 +1,31 @@
+// software transactional memory based concurrent programming
+
+pub mod deterministic;
+mod group_commit;
+pub mod retry;
+pub mod scenarios;
+pub mod sharded;
+pub mod stepper;
+pub mod tl2;
+pub mod txarray;
+pub mod txcounter;
+pub mod txmap;
+pub mod txqueue;
+
+#[macro_export]
+macro_rules! load {
+    ($t: ident, $a: expr) => {
+        if let Some(v) = ($t).load($a) {
+            v
+        } else {
+            return $crate::tl2::STMResult::Retry;
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! store {
+    ($t: ident, $a: expr, $v: expr) => {
+        $t.store($a, $v)
+    };
+}
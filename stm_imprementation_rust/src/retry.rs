@@ -0,0 +1,131 @@
+// 競合による retry の待機方針
+// STM::with_retry_policy で設定する (デフォルトは Immediate)
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+pub trait RetryPolicy: Send + Sync {
+    // attempt 回目 (1 始まり) の retry の前の待ち時間
+    // None を返した場合はトランザクションを諦める (write_transaction / read_transaction は None を返す)
+    // Duration::ZERO の場合は sleep せずに spin (一定回数以上は yield) する
+    fn delay(&self, attempt: usize) -> Option<Duration>;
+}
+
+// 待たずに即座に retry する (従来の動作)
+pub struct Immediate;
+
+impl RetryPolicy for Immediate {
+    fn delay(&self, _attempt: usize) -> Option<Duration> {
+        Some(Duration::ZERO)
+    }
+}
+
+// 毎回一定時間待ってから retry する
+pub struct FixedDelay {
+    delay: Duration,
+    max_attempts: Option<usize>,    // この回数を超えて retry する場合は諦める
+}
+
+impl FixedDelay {
+    pub fn new(delay: Duration) -> Self {
+        FixedDelay { delay, max_attempts: None }
+    }
+
+    pub fn max_attempts(mut self, n: usize) -> Self {
+        self.max_attempts = Some(n);
+        self
+    }
+}
+
+impl RetryPolicy for FixedDelay {
+    fn delay(&self, attempt: usize) -> Option<Duration> {
+        if exceeds(self.max_attempts, attempt) {
+            return None;
+        }
+        Some(self.delay)
+    }
+}
+
+// 待ち時間の上限を base * 2^(attempt - 1) (max で頭打ち) とし、[0, 上限] から一様に選ぶ (full jitter)
+// 待ち時間をランダムにすることで、複数スレッドが同じ周期で retry し続ける (ライブロック) のを防ぐ
+pub struct ExponentialJitter {
+    base: Duration,
+    max: Duration,
+    max_attempts: Option<usize>,
+}
+
+impl ExponentialJitter {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        ExponentialJitter { base, max, max_attempts: None }
+    }
+
+    pub fn max_attempts(mut self, n: usize) -> Self {
+        self.max_attempts = Some(n);
+        self
+    }
+}
+
+impl RetryPolicy for ExponentialJitter {
+    fn delay(&self, attempt: usize) -> Option<Duration> {
+        if exceeds(self.max_attempts, attempt) {
+            return None;
+        }
+        let shift = attempt.saturating_sub(1).min(31) as u32;
+        let ceiling = self.base.saturating_mul(1 << shift).min(self.max);
+        let nanos = ceiling.as_nanos() as u64;
+        if nanos == 0 {
+            return Some(Duration::ZERO);
+        }
+        // RandomState は生成ごとに異なる鍵を持つため、簡易な乱数源として使える
+        let r = RandomState::new().hash_one(attempt);
+        Some(Duration::from_nanos(r % (nanos + 1)))
+    }
+}
+
+fn exceeds(max_attempts: Option<usize>, attempt: usize) -> bool {
+    match max_attempts {
+        Some(max) => attempt > max,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tl2::{STMResult, STM};
+    use crate::{load, store};
+    use std::cell::Cell;
+
+    // 同じ attempt でも呼び出しごとに異なる待ち時間を返し、上限 (base * 2^(attempt - 1)) を超えない
+    #[test]
+    fn exponential_jitter_varies_delays() {
+        let policy = ExponentialJitter::new(Duration::from_micros(100), Duration::from_millis(10));
+        let delays: Vec<Duration> = (0..64).map(|_| policy.delay(4).unwrap()).collect();
+        assert!(delays.iter().all(|d| *d <= Duration::from_micros(800)));
+        let (min, max) = (delays.iter().min().unwrap(), delays.iter().max().unwrap());
+        assert!(max > min, "no variance: {:?}", delays);
+        assert_eq!(policy.max_attempts(3).delay(4), None);
+    }
+
+    // delay が None を返すと、トランザクションは諦めて None を返す
+    // 各実行は、読み込んだストライプに他のトランザクションが commit するため必ず競合する
+    #[test]
+    fn giving_up_aborts_the_transaction() {
+        let stm = STM::new().with_retry_policy(FixedDelay::new(Duration::ZERO).max_attempts(2));
+        let runs = Cell::new(0);
+        let result = stm.write_transaction(|tr| {
+            runs.set(runs.get() + 1);
+            let v = load!(tr, 0);
+            stm.write_transaction(|other| {
+                store!(other, 0, [runs.get() as u8; 8]);
+                STMResult::Ok(())
+            });
+            store!(tr, 0, [v[0] + 100; 8]);
+            STMResult::Ok(())
+        });
+        assert_eq!(result, None);
+        assert_eq!(runs.get(), 3);      // 最初の実行と 2 回の retry
+        assert_eq!(stm.read_transaction(|tr| STMResult::Ok(load!(tr, 0))), Some([3; 8]));
+    }
+}
@@ -0,0 +1,188 @@
+// ベンチマーク・動作確認用のシナリオ
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::atomic::Ordering::Relaxed;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::tl2::{self, LockOrder, ReadTrans, SetHasher, WriteTrans, MEM_SIZE, STRIPE_SIZE};
+use crate::{load, store};
+
+// 食事する哲学者問題
+// 各哲学者は隣り合う 2 本の箸 (ストライプ) を同時に拾って置く操作を繰り返し、
+// observer は一定時間ごとに全ての箸の状態を読み込んで、拾われている箸の数が偶数であること (atomic であること) を検査する
+pub struct Philosophers {
+    pub philosophers: usize,        // 哲学者 (= 箸) の数 (2 以上、MEM_SIZE / STRIPE_SIZE 以下)
+    pub iterations: usize,          // 各哲学者が箸を拾って置く回数
+    pub observe_interval: Duration, // observer の観測間隔
+    pub verbose: bool,              // 観測した箸の状態を表示するかどうか
+    pub hasher: SetHasher,          // read_set / write_set のハッシュ関数
+    pub lock_order: LockOrder,      // commit 時に箸を lock する順序
+    pub commit_ordering: Ordering,  // commit 時の version の store の ordering (STM::with_commit_ordering を参照)
+}
+
+#[derive(Debug, Clone)]
+pub struct PhilosophersStats {
+    pub commits: u64,           // commit した write transaction の数
+    pub retries: u64,           // closure の再実行回数 (競合と箸待ちの合計)
+    pub observations: u64,      // observer の観測回数
+    pub inconsistencies: u64,   // atomic でない状態を観測した回数 (0 でなければならない)
+    pub elapsed: Duration,      // 哲学者が全員終了するまでの時間
+}
+
+impl Default for Philosophers {
+    fn default() -> Self {
+        Philosophers {
+            philosophers: 8,
+            iterations: 500000,
+            observe_interval: Duration::from_micros(100),
+            verbose: false,
+            hasher: SetHasher::default(),
+            lock_order: LockOrder::default(),
+            commit_ordering: Relaxed,
+        }
+    }
+}
+
+impl Philosophers {
+    pub fn run(&self) -> PhilosophersStats {
+        assert!(self.philosophers >= 2 && self.philosophers * STRIPE_SIZE <= MEM_SIZE);
+
+        let stm = tl2::STM::builder().hasher(self.hasher.clone()).lock_order(self.lock_order).commit_ordering(self.commit_ordering).build();
+        let commits = AtomicU64::new(0);
+        let runs = AtomicU64::new(0);
+        let done = AtomicBool::new(false);
+
+        let start = Instant::now();
+        let (observations, inconsistencies) = stm.scope(|s| {
+            let philosophers: Vec<_> = (0..self.philosophers)
+                .map(|i| {
+                    let (commits, runs) = (&commits, &runs);
+                    s.spawn(move |stm| self.philosopher(stm, i, commits, runs))
+                })
+                .collect();
+            let observer = s.spawn(|stm| self.observer(stm, &done));
+
+            for p in philosophers {
+                p.join().unwrap();
+            }
+            done.store(true, Relaxed);
+            observer.join().unwrap()
+        });
+        let elapsed = start.elapsed();
+
+        let commits = commits.load(Relaxed);
+        PhilosophersStats {
+            commits,
+            retries: runs.load(Relaxed) - commits,
+            observations,
+            inconsistencies,
+            elapsed,
+        }
+    }
+
+    fn philosopher(&self, stm: &tl2::STM, n: usize, commits: &AtomicU64, runs: &AtomicU64) {
+        // 箸用のメモリ
+        let left = STRIPE_SIZE * n;
+        let right = STRIPE_SIZE * ((n + 1) % self.philosophers);
+
+        // 箸を拾う closure
+        let pick_chopsticks = |tr: &mut WriteTrans<'_>| {
+            runs.fetch_add(1, Relaxed);
+            let mut stick_left = load!(tr, left);
+            let mut stick_right = load!(tr, right);
+            if stick_left[0] == 0 && stick_right[0] == 0 {
+                stick_left[0] = 1;
+                stick_right[0] = 1;
+                store!(tr, left, stick_left);
+                store!(tr, right, stick_right);
+                tl2::STMResult::Ok(())
+            } else {
+                tl2::STMResult::RetryOk     // 箸を拾えるまで繰り返す
+            }
+        };
+
+        // 箸を置く closure 
+        let drop_chopsticks = |tr: &mut WriteTrans<'_>| {
+            runs.fetch_add(1, Relaxed);
+            let mut stick_left = load!(tr, left);
+            let mut stick_right = load!(tr, right);
+            stick_left[0] = 0;
+            stick_right[0] = 0;
+            store!(tr, left, stick_left);
+            store!(tr, right, stick_right);
+            tl2::STMResult::Ok(())
+        };
+
+        for _ in 0..self.iterations {
+            stm.write_transaction(pick_chopsticks);
+            stm.write_transaction(drop_chopsticks);
+            commits.fetch_add(2, Relaxed);
+        }
+    }
+
+    // (観測回数, atomic でない状態を観測した回数) を返す
+    fn observer(&self, stm: &tl2::STM, done: &AtomicBool) -> (u64, u64) {
+        let mut observations = 0;
+        let mut inconsistencies = 0;
+        let chopsticks = 0..self.philosophers * STRIPE_SIZE;
+        while !done.load(Relaxed) {
+            // 拾われている箸の数を数える closure (全ての箸を同じスナップショットから読む)
+            let count_chopsticks = |tr: &mut ReadTrans<'_>| {
+                match tr.fold_range(chopsticks.clone(), STRIPE_SIZE, 0u32, |acc, s| acc + s[0] as u32) {
+                    Some(count) => tl2::STMResult::Ok(count),
+                    None => tl2::STMResult::Retry,
+                }
+            };
+
+            let picked_up_chopsticks = stm.read_transaction(count_chopsticks).unwrap();
+            if self.verbose {
+                println!("picked up: {}", picked_up_chopsticks);
+            }
+
+            // 取り上げられている箸の数が奇数ならば、atomic でない
+            if picked_up_chopsticks & 1 != 0 {
+                inconsistencies += 1;
+            }
+            observations += 1;
+
+            // 一定時間ごとに観測
+            thread::sleep(self.observe_interval);
+        }
+        (observations, inconsistencies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4 人の哲学者: どの lock の順序でも全ての操作が commit し、observer は奇数本の箸を観測しない
+    // (隣り合う 2 人が同じ箸を同時に拾うと、拾われている箸は奇数本になる)
+    #[test]
+    fn four_philosophers_stay_consistent() {
+        let iterations = if cfg!(miri) { 10 } else { 2000 };
+        for lock_order in [LockOrder::HashOrder, LockOrder::AddressAscending, LockOrder::ContentionDescending] {
+            let stats = Philosophers {
+                philosophers: 4,
+                iterations,
+                observe_interval: Duration::from_micros(10),
+                verbose: false,
+                hasher: SetHasher::Fast,
+                lock_order,
+                commit_ordering: Relaxed,
+            }.run();
+            assert_eq!(stats.inconsistencies, 0, "{:?}: {:?}", lock_order, stats);
+            assert_eq!(stats.commits, (4 * iterations * 2) as u64);
+        }
+    }
+
+    // version を SeqCst で公開しても (commit は遅くなるが) 結果は変わらない
+    #[test]
+    fn philosophers_pass_with_seqcst_commit_ordering() {
+        let iterations = if cfg!(miri) { 10 } else { 2000 };
+        let stats = Philosophers { philosophers: 4, iterations, commit_ordering: Ordering::SeqCst, ..Philosophers::default() }.run();
+        assert_eq!(stats.inconsistencies, 0, "{:?}", stats);
+        assert_eq!(stats.commits, (4 * iterations * 2) as u64);
+    }
+}
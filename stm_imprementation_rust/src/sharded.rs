@@ -0,0 +1,346 @@
+// 複数の独立した STM (shard) をまとめて 1 つのアドレス空間として扱う
+// アドレス addr は shard addr / MEM_SIZE の、ローカルアドレス addr % MEM_SIZE に対応する。
+// shard ごとに global_clock を持つため、1 つの shard で完結するトランザクション同士は他の shard の clock を奪い合わない。
+//
+// 複数の shard にまたがるトランザクションの commit:
+// 1. 触れた全ての shard の write_set を shard の番号順に lock する
+// 2. 書き込みのある shard の global_clock をそれぞれ進める
+// 3. 触れた全ての shard の read_set を検証する (read_version + 1 による検証の省略は行わない)
+// 4. 各 shard に書き込みを反映し version を公開する
+// 読み込み側は開始時に全 shard の global_clock を copy するため、commit 途中の状態 (一部の shard のみ公開済み) は
+// lock bit または read_version を超える version として必ず検出される。
+
+use crate::retry::{Immediate, RetryPolicy};
+use crate::tl2::{Backoff, ReadTrans, STMResult, StmBuilder, WriteTrans, MEM_SIZE, STM, STRIPE_SIZE};
+
+pub struct ShardedSTM {
+    shards: Vec<STM>,
+    retry_policy: Box<dyn RetryPolicy>,
+}
+
+impl ShardedSTM {
+    // num_shards * MEM_SIZE バイトのアドレス空間を作成
+    pub fn new(num_shards: usize) -> Self {
+        Self::from_builder(num_shards, StmBuilder::default)
+    }
+
+    // builder で作成した設定の STM を各 shard とする (shard ごとに builder を 1 回呼ぶ)
+    // shard の retry policy は用いない (retry は ShardedSTM::with_retry_policy に従う)
+    pub fn from_builder(num_shards: usize, builder: impl Fn() -> StmBuilder) -> Self {
+        assert!(num_shards > 0);
+        ShardedSTM {
+            shards: (0..num_shards).map(|_| builder().build()).collect(),
+            retry_policy: Box::new(Immediate),
+        }
+    }
+
+    pub fn with_retry_policy<P: RetryPolicy + 'static>(mut self, policy: P) -> Self {
+        self.retry_policy = Box::new(policy);
+        self
+    }
+
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    // 使用可能なアドレス空間の大きさ (バイト)
+    pub fn capacity(&self) -> usize {
+        self.shards.len() * MEM_SIZE
+    }
+
+    pub fn shard(&self, index: usize) -> &STM {
+        &self.shards[index]
+    }
+
+    // いずれかの shard が poison されていれば、トランザクションを実行せずに None を返す (STM::is_poisoned を参照)
+    pub fn is_poisoned(&self) -> bool {
+        self.shards.iter().any(|shard| shard.is_poisoned())
+    }
+
+    pub fn read_transaction<F, R>(&self, f: F) -> Option<R>
+    where F: Fn(&mut ShardedReadTrans) -> STMResult<R> {
+        let mut backoff = Backoff::new(&*self.retry_policy);
+        loop {
+            if !backoff.wait() {
+                return None;
+            }
+            if self.is_poisoned() {
+                return None;
+            }
+            let mut read_trans = ShardedReadTrans::new(self);
+
+            match f(&mut read_trans) {
+                STMResult::Abort => return None,
+                STMResult::RetryOk => {
+                    backoff.condition();
+                    continue;
+                }
+                STMResult::Retry => {
+                    if read_trans.conflict() {
+                        backoff.conflict();
+                        continue;
+                    } else {
+                        return None;
+                    }
+                }
+                STMResult::Ok(val) => {
+                    if read_trans.conflict() {
+                        backoff.conflict();
+                        continue;
+                    } else {
+                        return Some(val);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn write_transaction<F, R>(&self, f: F) -> Option<R>
+    where F: Fn(&mut ShardedWriteTrans) -> STMResult<R> {
+        let mut backoff = Backoff::new(&*self.retry_policy);
+        loop {
+            if !backoff.wait() {
+                return None;
+            }
+            if self.is_poisoned() {
+                return None;
+            }
+            let mut write_trans = ShardedWriteTrans::new(self);
+
+            // 投機的実行
+            let result;
+            match f(&mut write_trans) {
+                STMResult::Abort => return None,
+                STMResult::RetryOk => {
+                    backoff.condition();
+                    continue;
+                }
+                STMResult::Retry => {
+                    if write_trans.conflict() {
+                        backoff.conflict();
+                        continue;
+                    } else {
+                        return None;
+                    }
+                }
+                STMResult::Ok(val) => {
+                    if write_trans.conflict() {
+                        backoff.conflict();
+                        continue;
+                    } else {
+                        result = val;
+                    }
+                }
+            }
+
+            if self.try_commit(&mut write_trans) {
+                return Some(result);
+            }
+            backoff.conflict();
+        }
+    }
+
+    fn try_commit(&self, write_trans: &mut ShardedWriteTrans) -> bool {
+        let touched: Vec<usize> = write_trans.trans.iter().enumerate()
+            .filter(|(_, tr)| !tr.read_set.is_empty() || !tr.write_set.is_empty())
+            .map(|(i, _)| i)
+            .collect();
+
+        // 1 つの shard で完結する場合は、その shard の通常の commit を行う
+        match touched.as_slice() {
+            [] => return true,      // 何も読み書きしていない
+            [i] => return self.shards[*i].try_commit(&mut write_trans.trans[*i]).is_some(),
+            _ => {}
+        }
+
+        // 1. shard の番号順に lock
+        for &i in touched.iter() {
+            if !write_trans.trans[i].lock_write_set() {
+                return false;       // 獲得済みの lock は write_trans の drop 時に解放される
+            }
+        }
+
+        // 2. 書き込みのある shard の global_clock を進める (他のトランザクションの差分検証のため、書き込み先も記録する)
+        let versions: Vec<Option<u64>> = touched.iter()
+            .map(|&i| {
+                let tr = &write_trans.trans[i];
+                if tr.write_set.is_empty() { None } else { Some(self.shards[i].stamp_commit(tr)) }
+            })
+            .collect();
+
+        // 3. 全ての read_set を検証
+        if !touched.iter().all(|&i| write_trans.trans[i].validate_read_set().is_ok()) {
+            return false;
+        }
+
+        // 4. 書き込みを反映して version を公開
+        for (&i, version) in touched.iter().zip(versions) {
+            if let Some(version) = version {
+                let tr = &mut write_trans.trans[i];
+                if !tr.commit(version) {
+                    return false;   // shard が poison されたため、write_transaction は None を返す
+                }
+                self.shards[i].wake_waiters(&tr.write_set);
+                self.shards[i].notify(&tr.write_set, version);
+            }
+        }
+        true
+    }
+}
+
+// アドレスから (shard の番号, shard 内のアドレス) を求める
+fn route(addr: usize) -> (usize, usize) {
+    (addr / MEM_SIZE, addr & (MEM_SIZE - 1))
+}
+
+pub struct ShardedReadTrans<'a> {
+    trans: Vec<ReadTrans<'a>>,      // shard ごとの読み込みトランザクション (開始時に全ての shard の clock を copy する)
+}
+
+impl<'a> ShardedReadTrans<'a> {
+    fn new(stm: &'a ShardedSTM) -> Self {
+        ShardedReadTrans { trans: stm.shards.iter().map(|s| s.begin_read()).collect() }
+    }
+
+    fn conflict(&self) -> bool {
+        self.trans.iter().any(|tr| tr.conflict)
+    }
+
+    pub fn load(&mut self, addr: usize) -> Option<[u8; STRIPE_SIZE]> {
+        if self.conflict() {
+            return None;
+        }
+        let (shard, local) = route(addr);
+        self.trans[shard].load(local)
+    }
+}
+
+pub struct ShardedWriteTrans<'a> {
+    trans: Vec<WriteTrans<'a>>,     // shard ごとの書き込みトランザクション (開始時に全ての shard の clock を copy する)
+}
+
+impl<'a> ShardedWriteTrans<'a> {
+    fn new(stm: &'a ShardedSTM) -> Self {
+        ShardedWriteTrans { trans: stm.shards.iter().map(|s| s.begin_write()).collect() }
+    }
+
+    fn conflict(&self) -> bool {
+        self.trans.iter().any(|tr| tr.conflict)
+    }
+
+    pub fn store(&mut self, addr: usize, val: [u8; STRIPE_SIZE]) {
+        let (shard, local) = route(addr);
+        self.trans[shard].store(local, val);
+    }
+
+    pub fn load(&mut self, addr: usize) -> Option<[u8; STRIPE_SIZE]> {
+        if self.conflict() {
+            return None;
+        }
+        let (shard, local) = route(addr);
+        self.trans[shard].load(local)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+
+    use super::*;
+    use crate::{load, store};
+
+    const ACCOUNTS: usize = 16;     // shard ごとの口座の数
+    const INITIAL: u64 = 1000;
+
+    // 口座 i のアドレス (shard 0 と shard 1 に交互に配置する)
+    fn account(i: usize) -> usize {
+        (i % 2) * MEM_SIZE + (i / 2) * STRIPE_SIZE
+    }
+
+    #[test]
+    fn routes_addresses_to_shards() {
+        let stm = ShardedSTM::new(2);
+        assert_eq!(stm.capacity(), 2 * MEM_SIZE);
+        stm.write_transaction(|tr| {
+            store!(tr, MEM_SIZE + 8, 7u64.to_le_bytes());
+            STMResult::Ok(())
+        });
+        assert_eq!(u64::from_le_bytes(stm.shard(1).read_raw(8)), 7);
+        assert_eq!(stm.shard(0).read_raw(8), [0; STRIPE_SIZE]);
+        // 1 つの shard で完結するトランザクションは、他の shard の clock を進めない
+        assert_eq!((stm.shard(0).global_version(), stm.shard(1).global_version()), (0, 1));
+        let read = stm.read_transaction(|tr| STMResult::Ok(u64::from_le_bytes(load!(tr, MEM_SIZE + 8))));
+        assert_eq!(read, Some(7));
+    }
+
+    #[test]
+    fn shards_use_the_builder_config() {
+        let stm = ShardedSTM::from_builder(2, || StmBuilder::default().with_stats());
+        stm.write_transaction(|tr| {
+            store!(tr, 0, 1u64.to_le_bytes());
+            store!(tr, MEM_SIZE, 1u64.to_le_bytes());
+            STMResult::Ok(())
+        });
+        for i in 0..2 {
+            assert!(stm.shard(i).stats().is_some(), "shard {} was built without the builder's config", i);
+        }
+        assert_eq!(stm.shard(0).version_vector()[0], 1);
+        assert_eq!(stm.shard(1).version_vector()[0], 1);
+    }
+
+    // shard をまたぐ送金を並行に繰り返しても合計は変わらず、reader は常に合計が一致するスナップショットを読む
+    #[test]
+    fn cross_shard_transfers_conserve_total() {
+        let stm = ShardedSTM::new(2);
+        stm.write_transaction(|tr| {
+            for i in 0..2 * ACCOUNTS {
+                store!(tr, account(i), INITIAL.to_le_bytes());
+            }
+            STMResult::Ok(())
+        });
+        let expected = INITIAL * 2 * ACCOUNTS as u64;
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            let writers: Vec<_> = (0..4).map(|t| {
+                let stm = &stm;
+                s.spawn(move || {
+                    for n in 0..500 {
+                        let from = account((n * 7 + t) % (2 * ACCOUNTS));
+                        let to = account((n * 7 + t + 1) % (2 * ACCOUNTS));     // 常に別の shard
+                        stm.write_transaction(|tr| {
+                            let a = u64::from_le_bytes(load!(tr, from));
+                            let b = u64::from_le_bytes(load!(tr, to));
+                            let amount = a.min(3);
+                            store!(tr, from, (a - amount).to_le_bytes());
+                            store!(tr, to, (b + amount).to_le_bytes());
+                            STMResult::Ok(())
+                        }).unwrap();
+                    }
+                })
+            }).collect();
+            s.spawn(|| {
+                while !done.load(Relaxed) {
+                    let total = stm.read_transaction(|tr| {
+                        let mut total = 0;
+                        for i in 0..2 * ACCOUNTS {
+                            total += u64::from_le_bytes(load!(tr, account(i)));
+                        }
+                        STMResult::Ok(total)
+                    }).unwrap();
+                    assert_eq!(total, expected, "observed a partially committed cross-shard transfer");
+                }
+            });
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            done.store(true, Relaxed);
+        });
+        let total: u64 = (0..2 * ACCOUNTS).map(|i| {
+            let (shard, local) = route(account(i));
+            u64::from_le_bytes(stm.shard(shard).read_raw(local))
+        }).sum();
+        assert_eq!(total, expected);
+    }
+}
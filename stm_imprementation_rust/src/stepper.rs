@@ -0,0 +1,213 @@
+// 1 回の書き込みトランザクションを TL2 の手順ごとに 1 ステップずつ実行する (教材・可視化用)
+// closure の実行中は一時停止できないため、最初のステップで closure を 1 回実行して load / store を記録し、
+// その記録を 1 つずつ返した後に、lock の獲得 (ストライプごと)、version の割り当てと read_set の検証、commit を 1 ステップずつ行う。
+// lock は実際に獲得されるため、ステップの間に他のスレッドから STM::locked_stripes などで観測できる。
+// 通常の commit の最適化 (1 ストライプの場合の lock と検証の同時実行、read_version + 1 == version の場合の検証の省略) は行わない。
+// 競合しても retry しない (Conflict を返して終わる; 途中で drop した場合も獲得済みの lock は解放される)
+// 例: STM::new() の上で 0 を読んで 8 に書き込むトランザクションは
+//     Read(0, _), Write(8, _), LockAcquire(8), Validate(Ok(())), Commit(1) の順にステップを返す
+
+use std::collections::VecDeque;
+
+use crate::tl2::{STMResult, WriteTrans, STM, STRIPE_SIZE};
+
+// 1 ステップで行った操作
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Phase<const S: usize = STRIPE_SIZE> {
+    Read(usize, [u8; S]),       // closure の load (自身の書き込みを読んだ場合を含む)
+    Write(usize, [u8; S]),      // closure の store (write_set への stage)
+    LockAcquire(usize),         // 書き込み先のストライプの lock を獲得した
+    Validate(Result<(), usize>),    // version を割り当てた後の read_set の検証 (失敗した場合は更新されていたアドレス)
+    Commit(u64),                // 書き込みを公開した (割り当てた version; 書き込みがなければ read_version)
+    Conflict(Option<usize>),    // 読み込みまたは lock の獲得で競合した (トランザクションは終了する)
+}
+
+enum State {
+    Start,
+    Lock(Vec<usize>),   // 残りの lock するアドレス (末尾から lock する)
+    Commit(u64),        // 検証に成功し、割り当てた version で公開する
+    Done,
+}
+
+pub struct SteppableTransaction<'a, F, R, const S: usize = STRIPE_SIZE> {
+    stm: &'a STM<S>,
+    trans: WriteTrans<'a, S>,
+    f: Option<F>,
+    result: Option<R>,
+    committed: bool,
+    pending: VecDeque<Phase<S>>,    // closure の実行中に記録した、まだ返していない操作
+    state: State,
+}
+
+impl<'a, F, R, const S: usize> SteppableTransaction<'a, F, R, S>
+where F: FnOnce(&mut WriteTrans<'_, S>) -> STMResult<R> {
+    pub fn new(stm: &'a STM<S>, f: F) -> Self {
+        SteppableTransaction {
+            stm,
+            trans: stm.begin_write(),
+            f: Some(f),
+            result: None,
+            committed: false,
+            pending: VecDeque::new(),
+            state: State::Start,
+        }
+    }
+
+    // Commit を返した後であれば closure の結果を返す (それ以外の場合は None)
+    pub fn into_result(self) -> Option<R> {
+        if self.committed { self.result } else { None }
+    }
+
+    // closure を 1 回実行し、記録した操作を pending に積む
+    fn execute(&mut self) {
+        let f = self.f.take().unwrap();
+        self.trans.trace = Some(Vec::new());
+        let outcome = f(&mut self.trans);
+        self.pending.extend(self.trans.trace.take().unwrap());
+        match outcome {
+            STMResult::Ok(val) if !self.trans.conflict && !self.trans.abort_requested => {
+                self.result = Some(val);
+                let mut addrs: Vec<usize> = self.trans.write_set.keys().copied().collect();
+                addrs.sort_unstable_by(|a, b| b.cmp(a));    // アドレスの昇順に lock する
+                self.state = State::Lock(addrs);
+            }
+            _ => {
+                if self.trans.conflict {
+                    self.pending.push_back(Phase::Conflict(self.trans.conflict_addr));
+                }
+                self.state = State::Done;   // abort・条件待ちの場合も commit せずに終える
+            }
+        }
+    }
+
+    fn advance(&mut self) -> Option<Phase<S>> {
+        match std::mem::replace(&mut self.state, State::Done) {
+            State::Start | State::Done => None,
+            State::Lock(mut addrs) => match addrs.pop() {
+                Some(addr) => {
+                    if !self.trans.lock_stripe(addr) {
+                        return Some(Phase::Conflict(Some(addr)));   // 獲得済みの lock は drop 時に解放される
+                    }
+                    self.state = State::Lock(addrs);
+                    Some(Phase::LockAcquire(addr))
+                }
+                None if self.trans.write_set.is_empty() => {
+                    // 書き込みがなければ read_version の時点で commit したものとする (STM::try_commit と同様)
+                    self.committed = true;
+                    self.trans.run_callbacks();
+                    Some(Phase::Commit(self.trans.read_version()))
+                }
+                None => {
+                    let version = self.stm.stamp_commit(&self.trans);
+                    let result = self.trans.validate_read_set();
+                    if result.is_ok() {
+                        self.state = State::Commit(version);
+                    }
+                    Some(Phase::Validate(result))
+                }
+            },
+            State::Commit(version) => {
+                self.stm.apply_commit(&mut self.trans, version)?;     // poison された場合は Commit を返さずに終える
+                self.committed = true;
+                self.trans.run_callbacks();
+                Some(Phase::Commit(version))
+            }
+        }
+    }
+}
+
+impl<F, R, const S: usize> Iterator for SteppableTransaction<'_, F, R, S>
+where F: FnOnce(&mut WriteTrans<'_, S>) -> STMResult<R> {
+    type Item = Phase<S>;
+
+    fn next(&mut self) -> Option<Phase<S>> {
+        if let State::Start = self.state {
+            self.execute();
+        }
+        match self.pending.pop_front() {
+            Some(phase) => Some(phase),
+            None => self.advance(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::store;
+
+    // on_commit で登録した処理は Commit のステップで実行し、競合して終わった場合は実行しない
+    #[test]
+    fn runs_callbacks_on_commit_step() {
+        let stm = STM::new();
+        let ran = Rc::new(Cell::new(0));
+        let register = |tr: &mut WriteTrans<'_>| {
+            store!(tr, 0, 1u64.to_le_bytes());
+            let ran = ran.clone();
+            tr.on_commit(move || ran.set(ran.get() + 1));
+            STMResult::Ok(())
+        };
+
+        for phase in stm.steppable(register) {
+            let expected = if let Phase::Commit(_) = phase { 1 } else { 0 };
+            assert_eq!(ran.get(), expected, "callback must run exactly at the commit step");
+        }
+        assert_eq!(ran.get(), 1);
+
+        // lock の獲得で競合する場合は実行しない
+        let mut tx = stm.steppable(register);
+        assert!(matches!(tx.next(), Some(Phase::Write(0, _))));
+        let mut blocker = stm.begin_write();
+        blocker.store(0, [0; 8]);
+        assert!(blocker.lock_write_set());
+        assert_eq!(tx.by_ref().last(), Some(Phase::Conflict(Some(0))));
+        drop(blocker);
+        assert_eq!(ran.get(), 1);
+
+        // 書き込みのないトランザクションも、Commit のステップで実行する
+        let ran_empty = Rc::new(Cell::new(false));
+        let flag = ran_empty.clone();
+        let tx = stm.steppable(move |tr| {
+            let flag = flag.clone();
+            tr.on_commit(move || flag.set(true));
+            STMResult::Ok(())
+        });
+        assert_eq!(tx.last(), Some(Phase::Commit(1)));
+        assert!(ran_empty.get());
+    }
+
+    // steppable で lock を獲得した段階のストライプは is_locked で lock されて見え、commit / drop の後は見えない
+    #[test]
+    fn is_locked_reflects_commit_locks() {
+        let stm = STM::new();
+        assert!(!stm.is_locked(8));
+
+        let mut tx = stm.steppable(|tr| {
+            store!(tr, 8, 1u64.to_le_bytes());
+            STMResult::Ok(())
+        });
+        assert!(matches!(tx.next(), Some(Phase::Write(8, _))));
+        assert!(!stm.is_locked(8), "staging a write must not take the lock");
+        assert_eq!(tx.next(), Some(Phase::LockAcquire(8)));
+        assert!(stm.is_locked(8));
+        assert!(!stm.is_locked(0), "other stripes stay unlocked");
+        for _ in tx.by_ref() {}
+        assert_eq!(tx.into_result(), Some(()));
+        assert!(!stm.is_locked(8));
+
+        // commit せずに drop した場合も lock は解放される
+        let mut tx = stm.steppable(|tr| {
+            store!(tr, 16, 2u64.to_le_bytes());
+            STMResult::Ok(())
+        });
+        tx.next();
+        assert_eq!(tx.next(), Some(Phase::LockAcquire(16)));
+        assert!(stm.is_locked(16));
+        drop(tx);
+        assert!(!stm.is_locked(16));
+        assert_eq!(stm.read_raw(16), [0; 8]);
+    }
+}
@@ -1,64 +1,751 @@
-use std::cell::UnsafeCell;
-use std::collections::{HashMap, HashSet};
-use std::sync::atomic::{fence, AtomicU64};
+use std::any::Any;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::cell::RefCell;
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::mem;
+use std::ops::{Deref, DerefMut, Range};
+use std::hash::{BuildHasher, Hasher};
+use std::panic::{self, AssertUnwindSafe};
+use std::{hint, thread};
+use std::thread::{Scope, ScopedJoinHandle, Thread, ThreadId};
+use std::time::{Duration, Instant};
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{fence, AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::atomic::Ordering::{Relaxed, Release, Acquire, AcqRel, SeqCst};
 
+use crate::group_commit::GroupCommit;
+use crate::stepper::{Phase, SteppableTransaction};
+use crate::retry::{Immediate, RetryPolicy};
+
 // software transactional memory の TL2 実装
 // todo: global_version_clock のオーバーフロー対策
+//       version は 63 bit (最上位 bit は lock 用) であり、毎秒 10^9 回 commit しても一巡に約 292 年かかるため、現在は対策していない。
+//       対策する場合は、実行中のトランザクションがない (quiescent な) 区間で全ストライプの version を書き換える必要があり、
+//       実行中のトランザクションの追跡 (epoch など) が先に必要となる。その区間の中で、version の書き換えの前に
+//       登録された callback (STM::on_rollover; version を key とする cache の破棄など) を呼び出す
 // todo: オブジェクト単位での管理 => Garbage Collection
 // todo: ライブロック回避のためのアクセス数制限 (Semaphore など)
+// Memory::set_fine_grained (範囲ごとにストライプを 1 byte にする) は実装しない:
+//   write_set と、ストライプごとの記録 (初期化済み・history・last_writer・差分検証の bit・audit) は全て S byte 単位であり、
+//   store_bytes も S byte ごとに stage する。1 つの Memory の中に大きさの異なるストライプを混在させるには、これらを全て
+//   可変長のストライプ単位に改める必要がある。隣接するバイトの false conflict を避けたいデータは、S = 1 の STM (STM<1>) に置けば
+//   同じ効果が得られる (benches/fine_grained.rs で S = 8 と S = 1 の競合の回数を比較する)
+//
+// ストライプ単位の read/write lock は採用しない:
+// reader を lock に参加させると読み込みのたびに共有される reader 数への書き込みが発生し、
+// reader 同士でもキャッシュラインを奪い合うことになる。現在の楽観的な読み込みでは reader は共有状態に一切書き込まない。
+// また writer が lock を保持するのは commit 中の write_set のコピーの間だけであり、reader が retry するのもこの区間に限られる。
+// benches/reader_retries.rs (全ストライプを書き換える commit を繰り返す writer と、4 ストライプを読む reader) では、
+// reader の 1 回の読み込みあたりの closure の実行回数は writer がいても 1.002 程度であり、retry による損失はほぼない
+// (writer がいる場合の throughput の低下は、書き換えられたキャッシュラインの読み直しによるもので、read/write lock では避けられない)
+//
+// todo: 優先度の継承 (priority inheritance)
+//       トランザクションの優先度 (wound-wait など) と、lock の解放を待機する contention manager が前提となる。
+//       現在は優先度がなく、lock 中のストライプに出会ったトランザクションは待機せず retry するため優先度の逆転は起こらない。
+//       導入する場合は lock_ver に holder の (継承された) 優先度を記録する必要がある。
+
+// ストライプの大きさは Memory, STM, ReadTrans, WriteTrans などの const generic parameter S で指定する (2^n でなければならない)
+// 省略した場合は STRIPE_SIZE。異なる S の STM を 1 つのプログラムで併用できる
+// S = 1 (バイト単位のストライプ) も可: shift_size = 0 となり、アライメントの検査 (addr & 0 == 0) は常に通る
+// ただし txcounter / txqueue は u64 を 1 ストライプに格納するため 8 以上が必要 (これらと txmap, sharded, deterministic は STRIPE_SIZE のみ)
+pub const STRIPE_SIZE: usize = 8;   //   8 byte
+pub const MEM_SIZE: usize = 512;    // 512 byte (2^n でなければならない)
+// MEM_SIZE / S 個のストライプを使用可能
+// 定数を誤って変更した場合に shift_size の計算が黙って壊れないよう、コンパイル時に検査する
+const _: () = assert!(STRIPE_SIZE.is_power_of_two(), "STRIPE_SIZE must be a power of two");
+const _: () = assert!(MEM_SIZE.is_power_of_two(), "MEM_SIZE must be a power of two");
+const _: () = assert!(MEM_SIZE.is_multiple_of(STRIPE_SIZE), "MEM_SIZE must be a multiple of STRIPE_SIZE");
+
+pub const CACHE_LINE: usize = 64;   // Memory が確保するデータ本体のアライメント (byte)
+const SPIN_LIMIT: usize = 16;       // 連続してこの回数以上 retry する場合は spin をやめて他スレッドに実行を譲る
+const RECENT_COMMITS: usize = 64;   // 直近の commit の書き込み先を記録する数 (差分検証に用いる)
+const BUSY: u64 = u64::MAX;         // RecentCommit を更新中であることを表す version
+const SPLIT_ADVICE_TOP: usize = 4;  // SplitAdvice で報告するアドレスの数
+
+// ストライプの (version, 値) の記録 (古い順)
+type History<const S: usize> = VecDeque<(u64, [u8; S])>;
+
+// 直近の commit の記録 (Memory::record_commit を参照)
+struct RecentCommit {
+    version: AtomicU64,
+    written: AtomicU64,
+}
+
+impl RecentCommit {
+    fn new() -> Self {
+        RecentCommit { version: AtomicU64::new(0), written: AtomicU64::new(0) }
+    }
+}
+
+// 現在のスレッドを識別する番号 (Memory::last_writer が返す値; 1 から順にスレッドごとに割り当てる)
+pub fn writer_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static WRITER_ID: u64 = NEXT_ID.fetch_add(1, Relaxed);
+    }
+    WRITER_ID.with(|id| *id)
+}
+
+// Memory::with_layout に与える、同時にアクセスされるアドレスのグループ
+// 各グループのストライプは物理的に隣接して (グループの順、グループ内ではアドレスの並び順に) 配置され、
+// 同じ cache line に載りやすくなる。複数のグループに現れたアドレスは最初のグループに配置する
+#[derive(Debug, Clone, Default)]
+pub struct LayoutHint {
+    groups: Vec<Vec<usize>>,
+}
+
+impl LayoutHint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn group(mut self, addrs: &[usize]) -> Self {
+        self.groups.push(addrs.to_vec());
+        self
+    }
+}
+
+// Memory::with_adaptive_striping の、ストライプから lock_ver への対応
+// 物理ストライプを len 個ずつの region に分け、競合しない (cold な) region は 1 つの lock_ver を共有する
+// map[region] = (region の先頭の lock_ver の index << 1) | cold
+struct LockRegions {
+    shift: u32,                 // len = 2^shift
+    map: Vec<usize>,
+    contention: Vec<AtomicU64>, // region ごとの競合の回数 (前回の merge_cold_regions 以降)
+}
+
+impl LockRegions {
+    fn word(&self, stripe: usize) -> usize {
+        let entry = self.map[stripe >> self.shift];
+        if entry & 1 != 0 {
+            entry >> 1
+        } else {
+            (entry >> 1) + (stripe & ((1 << self.shift) - 1))
+        }
+    }
+}
+
+// cache line 境界に揃えた、N 個の T からなるブロック (大きさとアライメントは CACHE_LINE)
+#[repr(C, align(64))]
+struct CacheLine<T, const N: usize>([T; N]);
+
+const _: () = assert!(mem::align_of::<CacheLine<AtomicU8, CACHE_LINE>>() == CACHE_LINE);
+
+// CacheLine を並べた、先頭が cache line 境界に揃ったバッファ ([T] として扱う)
+// len 以降 (最後の CacheLine の余り) は使わない
+struct Aligned<T, const N: usize> {
+    lines: Vec<CacheLine<T, N>>,
+    len: usize,
+}
+
+impl<T, const N: usize> Aligned<T, N> {
+    const FITS: () = assert!(mem::size_of::<[T; N]>() == CACHE_LINE, "a CacheLine must be exactly one cache line");
+
+    // i 番目の要素を f(i) で初期化する
+    fn new(len: usize, mut f: impl FnMut(usize) -> T) -> Self {
+        let () = Self::FITS;
+        let lines = (0..len.div_ceil(N))
+            .map(|l| CacheLine(std::array::from_fn(|i| f(l * N + i))))
+            .collect();
+        Aligned { lines, len }
+    }
+}
+
+impl<T, const N: usize> Deref for Aligned<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // SAFETY: CacheLine は repr(C) で [T; N] だけを持ち、大きさがアライメントと等しい (FITS) ため、
+        // lines は隙間なく並んだ lines.len() * N 個の T であり、len はそれ以下
+        unsafe { std::slice::from_raw_parts(self.lines.as_ptr() as *const T, self.len) }
+    }
+}
+
+// データ本体の格納先
+// ストライプが 8 byte の倍数であれば、Memory が確保するバッファは u64 単位 (Words) で持つ。
+// ストライプは常に word 単位で読み書きされるため、同じ位置に大きさの異なる atomic なアクセスが混在することはない
+// (ReadTrans::load_single_word は 1 回の load でストライプを読める)
+// Memory が確保するバッファの先頭は CACHE_LINE 境界に揃い、S が CACHE_LINE の約数であればストライプは cache line をまたがない
+enum Storage {
+    Bytes(Aligned<AtomicU8, CACHE_LINE>),       // Memory が確保したバッファ (ストライプが 8 byte 未満)
+    Words(Aligned<AtomicU64, { CACHE_LINE / 8 }>),  // Memory が確保したバッファ (word i にバイト 8i..8i+8 を little endian で格納する)
+    Borrowed(&'static [AtomicU8]),  // 呼び出し側が用意したバッファ (Memory::from_mut_slice を参照)
+}
+
+impl Storage {
+    fn new<const S: usize>(bytes: impl ExactSizeIterator<Item = u8>) -> Self {
+        let bytes: Vec<u8> = bytes.collect();
+        let storage = if !S.is_multiple_of(8) {
+            Storage::Bytes(Aligned::new(bytes.len(), |i| AtomicU8::new(bytes.get(i).copied().unwrap_or(0))))
+        } else {
+            Storage::Words(Aligned::new(bytes.len() / 8, |i| {
+                let word = bytes.get(8 * i..8 * i + 8).map_or([0; 8], |w| w.try_into().unwrap());
+                AtomicU64::new(u64::from_le_bytes(word))
+            }))
+        };
+        debug_assert!((storage.as_ptr() as usize).is_multiple_of(CACHE_LINE));
+        storage
+    }
+
+    // バッファの先頭
+    fn as_ptr(&self) -> *const u8 {
+        match self {
+            Storage::Words(mem) => mem.as_ptr() as *const u8,
+            _ => self.bytes().as_ptr() as *const u8,
+        }
+    }
+
+    // バイト単位のバッファ (Words 以外)
+    fn bytes(&self) -> &[AtomicU8] {
+        match self {
+            Storage::Bytes(mem) => mem,
+            Storage::Borrowed(mem) => mem,
+            Storage::Words(_) => &[],
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Storage::Words(mem) => mem.len() * 8,
+            _ => self.bytes().len(),
+        }
+    }
+
+    // addr から out.len() byte を読む (Words では addr と out.len() は 8 の倍数)
+    fn read(&self, addr: usize, out: &mut [u8]) {
+        match self {
+            Storage::Words(mem) => {
+                for (chunk, m) in out.chunks_exact_mut(8).zip(&mem[addr / 8..]) {
+                    chunk.copy_from_slice(&m.load(Relaxed).to_le_bytes());
+                }
+            }
+            _ => {
+                let len = out.len();
+                for (v, m) in out.iter_mut().zip(&self.bytes()[addr..addr + len]) {
+                    *v = m.load(Relaxed);
+                }
+            }
+        }
+    }
+
+    fn write(&self, addr: usize, val: &[u8]) {
+        match self {
+            Storage::Words(mem) => {
+                for (chunk, m) in val.chunks_exact(8).zip(&mem[addr / 8..]) {
+                    m.store(u64::from_le_bytes(chunk.try_into().unwrap()), Relaxed);
+                }
+            }
+            _ => {
+                for (m, v) in self.bytes()[addr..addr + val.len()].iter().zip(val) {
+                    m.store(*v, Relaxed);
+                }
+            }
+        }
+    }
+
+    // addr (8 の倍数) からの 8 byte を u64 (little endian) として読む
+    // Words では 1 回の atomic な load で読むため、読んだ値が途中で書き換わることはない
+    fn read_word(&self, addr: usize) -> u64 {
+        match self {
+            Storage::Words(mem) => mem[addr / 8].load(Relaxed),
+            _ => {
+                let mut word = [0; 8];
+                self.read(addr, &mut word);
+                u64::from_le_bytes(word)
+            }
+        }
+    }
 
-const STRIPE_SIZE: usize = 8;   //   8 byte (2^n でなければならない)
-const MEM_SIZE: usize = 512;    // 512 byte (2^n でなければならない)
-// MEM_SIZE / STRIPE_SIZE 個のストライプを使用可能
+    // 値を変えずに page_size ごとに書き込む (Memory::prefault を参照)
+    fn touch(&self, page_size: usize) {
+        match self {
+            Storage::Words(mem) => mem.iter().step_by(page_size / 8).for_each(|m| { m.fetch_or(0, Relaxed); }),
+            _ => self.bytes().iter().step_by(page_size).for_each(|m| { m.fetch_or(0, Relaxed); }),
+        }
+    }
+}
 
-pub struct Memory {
-    mem: Vec<u8>,
+pub struct Memory<const S: usize = STRIPE_SIZE> {
+    mem: Storage,               // データ本体 (並行に読み書きされるため atomic なバイト列として持つ)
     lock_ver: Vec<AtomicU64>,   // ストライプのロックとバージョン
+    initialized: Vec<AtomicBool>,   // ストライプに一度でも値が commit されたかどうか (strict_init の検査に用いる)
+    last_writer: Option<Vec<AtomicU64>>,    // ストライプに最後に commit したスレッドの writer_id (デバッグ用; 有効な場合のみ確保)
+    read_heat: Option<Vec<AtomicU64>>,      // ストライプをメモリから読み込んだ回数 (with_read_heat で有効にした場合のみ確保)
+    history: Option<Vec<Mutex<History<S>>>>,   // ストライプごとの直近の commit の値 (with_history で有効にした場合のみ確保)
+    history_len: usize,
+    recent: Vec<RecentCommit>,  // version % RECENT_COMMITS 番目に、その version の commit の書き込み先を記録する
     global_clock: AtomicU64,    
+    poisoned: AtomicBool,       // 不変条件の違反を検出した (STM::is_poisoned を参照)
+    flags: Vec<AtomicBool>,     // ストライプごとの通知用の flag (データ本体とは別の領域; STM::publish_flag を参照)
+    lock_conflicts: Vec<AtomicU64>, // ストライプごとの commit 時の lock の獲得に失敗した回数 (LockOrder::ContentionDescending に用いる)
+    layout: Option<Vec<usize>>, // 論理ストライプ番号 -> 物理ストライプ番号 (with_layout で指定した場合のみ)
+    regions: Option<LockRegions>,   // 物理ストライプ番号 -> lock_ver の index (with_adaptive_striping で指定した場合のみ)
+    single_threaded: bool,      // load の copy 後の検査を省略する (STM::with_single_threaded を参照)
+    owner: OnceLock<ThreadId>,  // single_threaded で最初に load したスレッド (デバッグビルドでのみ記録する)
     shift_size: u32,            // メモリアドレスからストライプ番号への変換に用いる
 }
 
-impl Memory {
+impl<const S: usize> Memory<S> {
+    // S は 2^n かつ MEM_SIZE 以下でなければならない (コンパイル時に検査する)
+    const VALID_STRIPE: () = assert!(S.is_power_of_two() && S <= MEM_SIZE);
+
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
-        let mem = [0].repeat(MEM_SIZE);     // 全体のメモリを確保
-        let shift = STRIPE_SIZE.trailing_zeros();   // (2^n).trailing_zeros() = n
+        Self::allocate(MEM_SIZE)
+    }
+
+    // データ本体の大きさを実行時に指定してメモリを確保する (内容はすべて未初期化の 0)
+    // size は 2^n かつ S 以上でなければならない (S も 2^n であるため、S の倍数になる)
+    pub fn with_capacity(size: usize) -> Result<Self, MemoryError> {
+        if !size.is_power_of_two() || size < S {
+            return Err(MemoryError::InvalidCapacity { size, stripe_size: S });
+        }
+        Ok(Self::allocate(size))
+    }
+
+    #[allow(clippy::redundant_field_names)]
+    fn allocate(size: usize) -> Self {
+        let () = Self::VALID_STRIPE;
+        let mem = Storage::new::<S>((0..size).map(|_| 0));     // 全体のメモリを確保
+        let shift = S.trailing_zeros();   // (2^n).trailing_zeros() = n
         let mut lock_ver = Vec::new();
-        for _ in 0..(MEM_SIZE >> shift) {       // 使用可能なストライプの個数
+        for _ in 0..(size >> shift) {       // 使用可能なストライプの個数
             lock_ver.push(AtomicU64::new(0));
         }
 
+        let initialized = (0..lock_ver.len()).map(|_| AtomicBool::new(false)).collect();
         Memory { 
             mem: mem, 
             lock_ver: lock_ver, 
+            initialized,
+            last_writer: None,
+            read_heat: None,
+            history: None,
+            history_len: 0,
+            recent: (0..RECENT_COMMITS).map(|_| RecentCommit::new()).collect(),
             global_clock: AtomicU64::new(0), 
+            poisoned: AtomicBool::new(false),
+            flags: (0..(size >> shift)).map(|_| AtomicBool::new(false)).collect(),
+            lock_conflicts: (0..(size >> shift)).map(|_| AtomicU64::new(0)).collect(),
+            layout: None,
+            regions: None,
+            single_threaded: false,
+            owner: OnceLock::new(),
+            shift_size: shift,
+        }
+    }
+
+    // 初期値を与えてメモリを確保する
+    // 初期値は commit 済みの値として扱うため、全ストライプの version と global_clock を 1 にし、初期化済みとする
+    pub fn from_bytes(initial: Vec<u8>) -> Result<Self, MemoryError> {
+        if initial.len() != MEM_SIZE {
+            return Err(MemoryError::InvalidLength { expected: MEM_SIZE, actual: initial.len() });
+        }
+
+        let () = Self::VALID_STRIPE;
+        let shift = S.trailing_zeros();
+        let mut lock_ver = Vec::new();
+        for _ in 0..(MEM_SIZE >> shift) {
+            lock_ver.push(AtomicU64::new(1));
+        }
+
+        let initialized = (0..lock_ver.len()).map(|_| AtomicBool::new(true)).collect();
+        Ok(Memory {
+            mem: Storage::new::<S>(initial.into_iter()),
+            lock_ver,
+            initialized,
+            last_writer: None,
+            read_heat: None,
+            history: None,
+            history_len: 0,
+            recent: (0..RECENT_COMMITS).map(|_| RecentCommit::new()).collect(),
+            global_clock: AtomicU64::new(1),
+            poisoned: AtomicBool::new(false),
+            flags: (0..(MEM_SIZE >> shift)).map(|_| AtomicBool::new(false)).collect(),
+            lock_conflicts: (0..(MEM_SIZE >> shift)).map(|_| AtomicU64::new(0)).collect(),
+            layout: None,
+            regions: None,
+            single_threaded: false,
+            owner: OnceLock::new(),
             shift_size: shift,
+        })
+    }
+
+    // 呼び出し側のバッファ (static な配列など) をデータ本体として用いる (データ本体のヒープ確保を行わない)
+    // ストライプの個数は buf.len() / S となり、buf.len() は S の倍数でなければならない
+    // buf の内容は from_bytes と同様に commit 済みの初期値として扱う
+    // (lock_ver などのストライプごとの管理領域は、ストライプの個数に応じて確保する)
+    pub fn from_mut_slice(buf: &'static mut [u8]) -> Result<Self, MemoryError> {
+        if buf.is_empty() || buf.len() & (S - 1) != 0 {
+            return Err(MemoryError::InvalidBufferLength { len: buf.len(), stripe_size: S });
+        }
+
+        let () = Self::VALID_STRIPE;
+        let shift = S.trailing_zeros();
+        let stripes = buf.len() >> shift;
+        // SAFETY: AtomicU8 は u8 と同じ大きさ・アライメント・ビット表現を持つ。
+        // buf は排他的に借用されている ('static) ため、以降は AtomicU8 を通してのみアクセスされる
+        let mem = unsafe { &*(buf as *mut [u8] as *const [AtomicU8]) };
+        Ok(Memory {
+            mem: Storage::Borrowed(mem),
+            lock_ver: (0..stripes).map(|_| AtomicU64::new(1)).collect(),
+            initialized: (0..stripes).map(|_| AtomicBool::new(true)).collect(),
+            last_writer: None,
+            read_heat: None,
+            history: None,
+            history_len: 0,
+            recent: (0..RECENT_COMMITS).map(|_| RecentCommit::new()).collect(),
+            global_clock: AtomicU64::new(1),
+            poisoned: AtomicBool::new(false),
+            flags: (0..stripes).map(|_| AtomicBool::new(false)).collect(),
+            lock_conflicts: (0..stripes).map(|_| AtomicU64::new(0)).collect(),
+            layout: None,
+            regions: None,
+            single_threaded: false,
+            owner: OnceLock::new(),
+            shift_size: shift,
+        })
+    }
+
+    // アドレスの指すストライプの、データ本体における先頭の位置 (with_layout で配置を変えた場合は変更後の位置)
+    // from_mut_slice 以外ではデータ本体の先頭は CACHE_LINE 境界に揃うため、S が CACHE_LINE の約数であれば
+    // ストライプは cache line をまたがない (from_mut_slice ではバッファのアライメントは呼び出し側に依存する)
+    pub fn stripe_ptr(&self, addr: usize) -> *const u8 {
+        let offset = self.stripe_index(addr) << self.shift_size;
+        if CACHE_LINE.is_multiple_of(S) && !matches!(self.mem, Storage::Borrowed(_)) {
+            debug_assert!((self.mem.as_ptr() as usize + offset).is_multiple_of(S));
+        }
+        self.mem.as_ptr().wrapping_add(offset)
+    }
+
+    // データ本体の大きさ (バイト; from_mut_slice ではバッファの長さ、with_capacity では指定した大きさ、それ以外は MEM_SIZE)
+    pub fn size(&self) -> usize {
+        self.mem.len()
+    }
+
+    // データ本体とストライプの version の全てのページに書き込んで、最初のトランザクションでのページフォルトを避ける
+    // (Vec の確保時に 0 で初期化されるため通常は既に触れているが、OS がゼロページを遅延して割り当てる場合に備える)
+    // todo: mlock によるページの固定 (libc への依存が必要)
+    pub fn prefault(&self) {
+        const PAGE_SIZE: usize = 4096;
+        self.mem.touch(PAGE_SIZE);
+        for v in self.lock_ver.iter().step_by(PAGE_SIZE / std::mem::size_of::<AtomicU64>()) {
+            v.fetch_or(0, Relaxed);
+        }
+    }
+
+    // 各ストライプに最後に commit したスレッドを記録するようにする (commit ごとに書き込むストライプ数だけ store が増える)
+    pub fn with_last_writer(mut self) -> Self {
+        self.last_writer = Some((0..self.stripes()).map(|_| AtomicU64::new(0)).collect());
+        self
+    }
+
+    // 各ストライプをメモリから読み込んだ回数を数えるようにする (読み込みごとに共有されるカウンタへの fetch_add が増える)
+    // 数えるのは ReadTrans / WriteTrans の load と read_raw によるメモリからの copy で、
+    // トランザクション内の cache や write_set から返した読み込みは数えない
+    pub fn with_read_heat(mut self) -> Self {
+        self.read_heat = Some((0..self.stripes()).map(|_| AtomicU64::new(0)).collect());
+        self
+    }
+
+    // 読み込まれたストライプの (アドレス, 読み込んだ回数) を、回数の多い順 (同じ回数ではアドレスの昇順) に返す
+    // 書き込みの競合 (lock_conflicts) とは独立: 競合せずに頻繁に読まれるストライプは、version を key とする cache の候補となる
+    // with_read_heat で有効にしていない場合は空
+    pub fn read_heat(&self) -> Vec<(usize, u64)> {
+        let Some(read_heat) = &self.read_heat else {
+            return Vec::new();
+        };
+        let mut heat: Vec<(usize, u64)> = read_heat.iter().enumerate()
+            .map(|(stripe, n)| (stripe << self.shift_size, n.load(Relaxed)))
+            .filter(|(_, n)| *n > 0)
+            .collect();
+        heat.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        heat
+    }
+
+    fn note_read(&self, addr: usize) {
+        if let Some(read_heat) = &self.read_heat {
+            read_heat[addr >> self.shift_size].fetch_add(1, Relaxed);
+        }
+    }
+
+    // 対象アドレスのストライプに最後に commit したスレッドの writer_id
+    // 一度も commit されていない場合、または with_last_writer で記録を有効にしていない場合は 0
+    // 並行に commit されている間は、返した時点で既に別のスレッドが書き込んでいるかもしれない
+    pub fn last_writer(&self, addr: usize) -> u64 {
+        match &self.last_writer {
+            Some(last_writer) => last_writer[addr >> self.shift_size].load(Relaxed),
+            None => 0,
+        }
+    }
+
+    // hint のグループごとに、そのアドレスのストライプを物理的に隣接するストライプへ配置する (LayoutHint を参照)
+    // 公開されるアドレスは論理アドレスのままで、data 本体と lock_ver の参照時に物理ストライプへ変換する
+    // 既存の値と version は配置に合わせて移動するため、論理アドレスから見た内容は変わらない
+    // with_adaptive_striping より前に指定しなければならない
+    pub fn with_layout(mut self, hint: LayoutHint) -> Self {
+        assert!(self.regions.is_none(), "with_layout must be applied before with_adaptive_striping");
+        let stripes = self.stripes();
+        let mut layout = vec![usize::MAX; stripes];
+        let mut next = 0;
+        let grouped = hint.groups.iter().flatten().map(|addr| {
+            assert_eq!(addr & (S - 1), 0);
+            addr >> self.shift_size
+        });
+        for stripe in grouped.chain(0..stripes) {   // グループに含まれないストライプは元の順に後ろへ詰める
+            if layout[stripe] == usize::MAX {
+                layout[stripe] = next;
+                next += 1;
+            }
+        }
+
+        // 現在の (論理ストライプの) 内容を読み出してから配置を切り替え、物理ストライプへ書き戻す
+        let contents: Vec<_> = (0..stripes)
+            .map(|stripe| {
+                let addr = stripe << self.shift_size;
+                let index = self.stripe_index(addr);
+                (self.copy_stripe(addr), self.lock_ver[index].load(Relaxed), self.initialized[index].load(Relaxed))
+            })
+            .collect();
+        self.layout = Some(layout);
+        for (stripe, (val, lock_ver, initialized)) in contents.into_iter().enumerate() {
+            let addr = stripe << self.shift_size;
+            let index = self.stripe_index(addr);
+            self.write_stripe(addr, &val);
+            self.lock_ver[index].store(lock_ver, Relaxed);
+            self.initialized[index].store(initialized, Relaxed);
+        }
+        self
+    }
+
+    // 物理ストライプを region_len 個ずつの region に分けて region ごとの競合を数え、
+    // merge_cold_regions で競合のなかった region の lock_ver を 1 つにまとめられるようにする (大きなバッファでの lock_ver の削減)
+    // まとめた region では 1 つのストライプへの書き込みが region 全体の version を進めるため、
+    // 同じ region の別のストライプを読んだトランザクションは (実際には競合していなくても) retry することがある
+    // region_len は 2^n で、ストライプの個数を割り切らなければならない
+    pub fn with_adaptive_striping(mut self, region_len: usize) -> Self {
+        assert!(region_len.is_power_of_two() && self.stripes().is_multiple_of(region_len));
+        let regions = self.stripes() / region_len;
+        self.regions = Some(LockRegions {
+            shift: region_len.trailing_zeros(),
+            map: (0..regions).map(|region| (region * region_len) << 1).collect(),
+            contention: (0..regions).map(|_| AtomicU64::new(0)).collect(),
+        });
+        self
+    }
+
+    // 前回の呼び出し以降に競合しなかった region の lock_ver を 1 つにまとめ、競合した region は再びストライプごとに分ける
+    // まとめた lock_ver の version は region の各ストライプの version の最大値とする (version は後戻りしない)。
+    // 実行中のトランザクションが古い対応で lock_ver を参照しないよう、&mut self (= 他のトランザクションがない時点) でのみ行う
+    // まとめた region の個数を返す (with_adaptive_striping を指定していない場合は 0)
+    pub fn merge_cold_regions(&mut self) -> usize {
+        let Some(regions) = &self.regions else {
+            return 0;
+        };
+        let region_len = 1 << regions.shift;
+        let mut lock_ver = Vec::new();
+        let mut map = Vec::with_capacity(regions.map.len());
+        let mut cold_regions = 0;
+        for (region, contention) in regions.contention.iter().enumerate() {
+            let stripes = region * region_len..(region + 1) * region_len;
+            let versions: Vec<u64> = stripes.map(|stripe| self.lock_ver[regions.word(stripe)].load(Relaxed)).collect();
+            assert!(versions.iter().all(|v| v & (1 << 63) == 0), "merge_cold_regions while a stripe is locked");
+            let cold = contention.swap(0, Relaxed) == 0;
+            map.push((lock_ver.len() << 1) | cold as usize);
+            if cold {
+                lock_ver.push(AtomicU64::new(*versions.iter().max().unwrap()));
+                cold_regions += 1;
+            } else {
+                lock_ver.extend(versions.into_iter().map(AtomicU64::new));
+            }
         }
+        self.lock_ver = lock_ver;
+        self.regions.as_mut().unwrap().map = map;
+        cold_regions
+    }
+
+    // 各ストライプについて直近 len 回の commit の (version, 値) を保持し、過去の version の読み込み (STM::read_at_version) に用いる
+    // 有効にした時点の値を最初の記録とする。commit ごとに書き込むストライプ数だけ Mutex の獲得と copy が増える
+    pub fn with_history(mut self, len: usize) -> Self {
+        assert!(len >= 1, "history length must be at least 1");
+        self.history = Some((0..self.stripes())
+            .map(|stripe| {
+                let addr = stripe << self.shift_size;
+                Mutex::new(VecDeque::from([(self.get_version(addr), self.copy_stripe(addr))]))
+            })
+            .collect());
+        self.history_len = len;
+        self
+    }
+
+    // commit 時 (lock 中) に書き込んだ値を記録する
+    fn record_history(&self, addr: usize, version: u64, val: &[u8; S]) {
+        if let Some(history) = &self.history {
+            let mut entries = history[addr >> self.shift_size].lock().unwrap();
+            if entries.len() == self.history_len {
+                entries.pop_front();
+            }
+            entries.push_back((version, *val));
+        }
+    }
+
+    // version 以下で最新の commit の値 (記録が残っていない、または with_history で有効にしていない場合は None)
+    // lock 中のストライプについては、commit 中の値が記録される前でありうるため、呼び出し側で lock を検査すること
+    fn history_load(&self, addr: usize, version: u64) -> Option<[u8; S]> {
+        let entries = self.history.as_ref()?[addr >> self.shift_size].lock().unwrap();
+        entries.iter().rev().find(|(v, _)| *v <= version).map(|(_, val)| *val)
     }
 
     // subroutines
     // global_clock を +1 してその値を返す
-    fn inc_global_clock(&mut self) -> u64 {
+    pub(crate) fn inc_global_clock(&self) -> u64 {
         self.global_clock.fetch_add(1, AcqRel) + 1
     }
 
+    // global_clock を version まで進める (STM::apply_with_version を参照)
+    // global_clock が既に version 以上であれば進めずに Err(現在の値) を返す
+    #[cfg(feature = "replay")]
+    pub(crate) fn advance_global_clock_to(&self, version: u64) -> Result<(), u64> {
+        self.global_clock.fetch_update(AcqRel, Acquire, |current| (current < version).then_some(version)).map(|_| ())
+    }
+
+    // 対象アドレスのストライプを表す bit (ストライプ数が 64 を超える場合は複数のストライプが同じ bit を共有する)
+    fn stripe_bit(&self, addr: usize) -> u64 {
+        1 << ((addr >> self.shift_size) & 63)
+    }
+
+    // version の commit が書き込むストライプ (stripe_bit の和) を記録する
+    // lock を獲得し version を割り当てた直後に記録するため、記録された commit が検証に失敗して書き込まない場合もある (その場合も安全側に働く)
+    pub(crate) fn record_commit(&self, version: u64, written: u64) {
+        let recent = &self.recent[version as usize % RECENT_COMMITS];
+        // seqlock と同様: 更新中は BUSY とし、読み込み側は前後で同じ version を観測した場合のみ written を採用する
+        recent.version.store(BUSY, Relaxed);
+        fence(Release);
+        recent.written.store(written, Relaxed);
+        recent.version.store(version, Release);
+    }
+
+    // version が from より大きく to より小さい commit が書き込んだストライプ (stripe_bit の和) を返す
+    // いずれかの commit の記録が見つからない (まだ記録されていない、または上書きされた) 場合は None
+    fn written_between(&self, from: u64, to: u64) -> Option<u64> {
+        if to.saturating_sub(from) > RECENT_COMMITS as u64 {
+            return None;
+        }
+        let mut written = 0;
+        for version in (from + 1)..to {
+            let recent = &self.recent[version as usize % RECENT_COMMITS];
+            if recent.version.load(Acquire) != version {
+                return None;
+            }
+            let w = recent.written.load(Relaxed);
+            fence(Acquire);
+            if recent.version.load(Relaxed) != version {
+                return None;
+            }
+            written |= w;
+        }
+        Some(written)
+    }
+
+    // アドレスの指すストライプの、data 本体・lock_ver・initialized における index (物理ストライプ番号)
+    // last_writer / history / commit の記録 (stripe_bit) は論理ストライプ番号のまま用いる
+    fn stripe_index(&self, addr: usize) -> usize {
+        let stripe = addr >> self.shift_size;
+        match &self.layout {
+            Some(layout) => layout[stripe],
+            None => stripe,
+        }
+    }
+
+    // アドレスの指すストライプの lock と version を保持する lock_ver の index
+    // (with_adaptive_striping で cold な region をまとめた場合、複数のストライプが同じ index になる)
+    pub(crate) fn lock_word(&self, addr: usize) -> usize {
+        let stripe = self.stripe_index(addr);
+        match &self.regions {
+            Some(regions) => regions.word(stripe),
+            None => stripe,
+        }
+    }
+
+    // 複数のストライプが lock_ver を共有しうるかどうか
+    pub(crate) fn shares_lock_words(&self) -> bool {
+        self.regions.is_some()
+    }
+
+    // ストライプの個数
+    fn stripes(&self) -> usize {
+        self.initialized.len()
+    }
+
+    // lock_ver の個数 (merge_cold_regions で cold な region をまとめると減る)
+    pub fn lock_words(&self) -> usize {
+        self.lock_ver.len()
+    }
+
+    // STM のトランザクションが addr で競合したことを記録する (with_adaptive_striping の場合のみ)
+    pub(crate) fn record_contention(&self, addr: usize) {
+        if let Some(regions) = &self.regions {
+            regions.contention[self.stripe_index(addr) >> regions.shift].fetch_add(1, Relaxed);
+        }
+    }
+
+    // commit 時に addr の lock を獲得できなかった回数
+    pub fn lock_conflicts(&self, addr: usize) -> u64 {
+        self.lock_conflicts[addr >> self.shift_size].load(Relaxed)
+    }
+
     // 対象のアドレスの version を取得
+    #[allow(clippy::needless_return)]
     fn get_version(&self, addr: usize) -> u64 {
-        let stripe = addr >> self.shift_size;               // ストライプの index
+        let stripe = self.lock_word(addr);               // ストライプの index
         let n = self.lock_ver[stripe].load(Relaxed);    // version 値
         return n & !(1 << 63);      // 最上位 bit を落とす (最上位 bit は lock 用 bit として用いる)
     }
 
+    // 現在 lock されているストライプのアドレス (先頭のアドレス) の一覧
+    // 各ストライプを順に読むだけなので、返した時点で既に解放・獲得されているかもしれない (停止の原因を調べるための目安)
+    pub fn locked_stripes(&self) -> Vec<usize> {
+        (0..self.stripes())
+            .map(|stripe| stripe << self.shift_size)
+            .filter(|addr| self.is_locked(*addr))
+            .collect()
+    }
+
+    // 全ストライプの version (index i はアドレス i * S のストライプ)
+    fn version_vector(&self) -> Vec<u64> {
+        (0..self.stripes()).map(|stripe| self.get_version(stripe << self.shift_size)).collect()
+    }
+
     // ロックされておらず、かつ addr の指す stripe の version: n が version 以下である (modify されていない) かどうか
+    #[allow(clippy::needless_return)]
     fn test_not_modify(&self, addr: usize, version: u64) -> bool {
-        let stripe = addr >> self.shift_size;               // ストライプの index
+        let stripe = self.lock_word(addr);               // ストライプの index
         let n = self.lock_ver[stripe].load(Relaxed);    // version 値
         return n <= version;        // lock されていれば最上位 bit が on になるため、このように簡単に判別できる
     }
 
+    // 対象アドレスのストライプに値が commit されたことがあるかどうか
+    // 初期化を記録した commit の version を検証済みの読み込みの後に呼べば、その commit の記録は必ず観測できる
+    fn is_initialized(&self, addr: usize) -> bool {
+        let stripe = self.stripe_index(addr);       // initialized はストライプごと
+        self.initialized[stripe].load(Relaxed)
+    }
+
+    // 対象アドレスのストライプが lock されているかどうか
+    fn is_locked(&self, addr: usize) -> bool {
+        let stripe = self.lock_word(addr);
+        self.lock_ver[stripe].load(Relaxed) & (1 << 63) != 0
+    }
+
     // 対象アドレスのロックの獲得を試みる
-    fn lock_addr(&mut self, addr: usize) -> bool {
-        let stripe = addr >> self.shift_size;       // ストライプの index
+    #[allow(clippy::redundant_pattern_matching)]
+    fn lock_addr(&self, addr: usize) -> bool {
+        let stripe = self.lock_word(addr);       // ストライプの index
         let lock_bit_setter = |val: u64| {
             let lock_bit = val & (1 << 63);
             if lock_bit == 0 {      // lock bit が設定されていない -> 設定
@@ -74,257 +761,4525 @@ impl Memory {
         }
     }
 
-    fn unlock_addr(&mut self, addr: usize) {
-        let stripe = addr >> self.shift_size;       // ストライプの index
-        self.lock_ver[stripe].fetch_and(!(1 << 63), Relaxed);   // lock bit 消去
+    // lock されておらず、かつ version が max_version 以下 (None なら任意) の場合に限り lock を獲得する
+    // lock の獲得と version の検証を 1 回の CAS で行う
+    fn lock_addr_if_not_modify(&self, addr: usize, max_version: Option<u64>) -> bool {
+        let stripe = self.lock_word(addr);
+        let setter = |val: u64| {
+            let modified = match max_version {
+                Some(version) => val > version,     // lock 中ならば最上位 bit により必ず version を超える
+                None => val & (1 << 63) != 0,
+            };
+            if modified { None } else { Some(val | (1 << 63)) }
+        };
+        self.lock_ver[stripe].fetch_update(Relaxed, Relaxed, setter).is_ok()
+    }
+
+    fn unlock_addr(&self, addr: usize) {
+        let stripe = self.lock_word(addr);       // ストライプの index
+        let prev = self.lock_ver[stripe].fetch_and(!(1 << 63), Relaxed);   // lock bit 消去
+        if prev & (1 << 63) == 0 {      // 保持しているはずの lock が外れていた
+            self.poison();
+        }
+    }
+
+    pub(crate) fn poison(&self) {
+        self.poisoned.store(true, Relaxed);
+    }
+
+    pub(crate) fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Relaxed)
+    }
+
+    // load の copy 後の検査を省略してよいかどうか
+    // デバッグビルドでは、最初に load したスレッド以外からの load を panic させる
+    fn skips_recheck(&self) -> bool {
+        if !self.single_threaded {
+            return false;
+        }
+        if cfg!(debug_assertions) {
+            let current = thread::current().id();
+            assert_eq!(*self.owner.get_or_init(|| current), current, "a single-threaded STM was used from more than one thread");
+        }
+        true
+    }
+
+    // ストライプのデータ本体へのアクセス
+    // 不変条件: write_stripe は対象ストライプの lock を保持している間にのみ呼ばれる (書き込みは高々 1 スレッド)
+    // read_stripe は lock なしで書き込みと並行に呼ばれうるため、読み込んだ値は途中の (torn な) 値であるかもしれない。
+    // 呼び出し側はコピーの前後で version を検証し、その間に lock も更新もされていない場合のみ値を採用する (seqlock と同様)。
+    // 各バイトは atomic に読み書きするため、言語レベルでの data race は起こらない。
+    fn read_stripe(&self, addr: usize) -> [u8; S] {
+        self.note_read(addr);
+        self.copy_stripe(addr)
+    }
+
+    // read_stripe と同様だが、read_heat に数えない (Memory 自身の再配置・初期化用)
+    fn copy_stripe(&self, addr: usize) -> [u8; S] {
+        let addr = self.stripe_index(addr) << self.shift_size;     // 物理アドレス
+        let mut val = [0; S];
+        self.mem.read(addr, &mut val);
+        val
+    }
+
+    // 8 byte のストライプを u64 として読む (ReadTrans::load_single_word を参照)
+    fn read_stripe_word(&self, addr: usize) -> u64 {
+        self.note_read(addr);
+        let addr = self.stripe_index(addr) << self.shift_size;
+        self.mem.read_word(addr)
+    }
+
+    fn write_stripe(&self, addr: usize, val: &[u8; S]) {
+        let addr = self.stripe_index(addr) << self.shift_size;
+        self.mem.write(addr, val);
     }
 }
 
-pub struct ReadTrans<'a> {      // 読み込みトランザクション (= クリティカルセクションの読み込み) 時に作成  
-    read_version: u64,
-    conflict: bool,             // 競合発生中かどうか
-    mem: &'a Memory,
+// read_set / write_set (ReadTrans では cache) に用いるハッシュ関数
+// Sip: 標準の SipHash (既定)
+// Fast: アドレス (小さな整数) に特化した乗算 1 回のハッシュ。HashDoS 耐性はないが、アドレスは利用者のコードが決めるため問題にならない
+#[derive(Clone)]
+pub enum SetHasher {
+    Sip(RandomState),
+    Fast,
 }
 
-impl<'a> ReadTrans<'a> {
-    fn new(mem: &'a Memory) -> Self {
-        ReadTrans { 
-            read_version: mem.global_clock.load(Acquire),   // global_clock を copy
-            conflict: false, 
-            mem: mem, 
-        }
+impl Default for SetHasher {
+    fn default() -> Self {
+        SetHasher::Sip(RandomState::new())
     }
+}
 
-    // memory copy の前後で consistency check を行い、適合した場合のみ読み込み成功
-    pub fn load(&mut self, addr: usize) -> Option<[u8; STRIPE_SIZE]> {
-        assert_eq!(addr & (STRIPE_SIZE - 1), 0);    // address がストライプのアライメントに適合しない場合はエラー
+impl BuildHasher for SetHasher {
+    type Hasher = SetHasherState;
 
-        // consistency check
-        if self.conflict {
-            return None;
-        } 
-        if !self.mem.test_not_modify(addr, self.read_version) {
-            self.conflict = true;
-            return None;
+    fn build_hasher(&self) -> SetHasherState {
+        match self {
+            SetHasher::Sip(state) => SetHasherState::Sip(state.build_hasher()),
+            SetHasher::Fast => SetHasherState::Fast(0),
         }
+    }
+}
 
-        // メモリコピー
-        fence(Acquire);
-        let mut mem = [0; STRIPE_SIZE];
-        for i in 0..STRIPE_SIZE {
-            mem[i] = self.mem.mem[addr + i];
+pub enum SetHasherState {
+    Sip(DefaultHasher),
+    Fast(u64),
+}
+
+impl Hasher for SetHasherState {
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            SetHasherState::Sip(h) => h.write(bytes),
+            SetHasherState::Fast(h) => {
+                for b in bytes {
+                    *h = (h.rotate_left(8) ^ *b as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+                }
+            }
         }
+    }
 
-        fence(SeqCst);
-        // consistency check: 読み込みメモリがロックされておらず、かつ read_version 以下であるかどうか
-        if !self.mem.test_not_modify(addr, self.read_version) {
-            self.conflict = true;
-            return None;
+    fn write_usize(&mut self, n: usize) {
+        match self {
+            SetHasherState::Sip(h) => h.write_usize(n),
+            SetHasherState::Fast(h) => *h = (h.rotate_left(8) ^ n as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15),
         }
+    }
 
-        Some(mem)
+    fn finish(&self) -> u64 {
+        match self {
+            SetHasherState::Sip(h) => h.finish(),
+            SetHasherState::Fast(h) => h ^ (h >> 29),   // 乗算の結果は上位 bit ほど良く混ざるため、下位 bit (bucket の index) に折り返す
+        }
     }
 }
 
-pub struct WriteTrans<'a> {
+pub(crate) type ReadSet = HashSet<usize, SetHasher>;
+pub(crate) type WriteSet<const S: usize> = HashMap<usize, [u8; S], SetHasher>;
+
+// 読み込みトランザクションの一貫性のレベル
+// Linearizable: memory copy の前後で検査する (既定)。読み込んだ値は全て read_version 時点の同一のスナップショットに属する
+// Snapshot: memory copy の前の検査のみ行う。copy 中に commit が重なると、lock 前に検査を通過した古い値と新しい値が混ざった
+//           (ストライプ内で破れた) 値を読み得る。lock 中や read_version より新しいストライプは検出できる
+// Eventual: 一切検査しない。commit 途中の値や、異なる時点の値の組み合わせを読み得る。conflict も発生しないため retry しない
+//           正しさを必要としない監視表示などに限って用いる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadConsistency {
+    #[default]
+    Linearizable,
+    Snapshot,
+    Eventual,
+}
+
+// commit 時に write_set の各ストライプのデータを書き込み、version を公開する順序 (STM::with_commit_order を参照)
+// 書き込み中のストライプは lock されているため、トランザクションの読み込みの結果には影響しない。
+// 影響するのは、トランザクションを用いない読み込み (read_raw) や ReadConsistency::Eventual の読み込みが commit 途中に観測しうる組み合わせだけ
+// Unspecified: write_set (HashMap) の順序 (既定; 並べ替えない)
+// AddressAscending: アドレスの昇順 (低いアドレスのストライプほど先に書き込まれ、先に新しい version が公開される)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CommitOrder {
+    #[default]
+    Unspecified,
+    AddressAscending,
+}
+
+// commit 時に write_set のストライプの lock を獲得する順序 (STM::with_lock_order を参照)
+// どの順序でも lock の獲得に失敗したトランザクションは待機せずに lock を解放して retry するため、deadlock は起こらない
+// (lock の解放を待機する contention manager を導入する場合は、AddressAscending で全トランザクションの順序を揃える必要がある)
+// HashOrder: write_set (HashMap) の順序 (既定; 並べ替えない)
+// AddressAscending: アドレスの昇順
+// ContentionDescending: これまでに lock の獲得に失敗した回数 (Memory::lock_conflicts) の多い順
+//                       競合しやすいストライプを先に lock し、失敗する場合は他の lock を獲得する前に失敗させる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockOrder {
+    #[default]
+    HashOrder,
+    AddressAscending,
+    ContentionDescending,
+}
+
+pub struct ReadTrans<'a, const S: usize = STRIPE_SIZE> {      // 読み込みトランザクション (= クリティカルセクションの読み込み) 時に作成  
     read_version: u64,
-    read_set: HashSet<usize>,
-    write_set: HashMap<usize, [u8; STRIPE_SIZE]>,
-    locked: Vec<usize>,     // lock したアドレス (Drop するときのため覚えておく)
-    conflict: bool,
-    mem: &'a mut Memory,
+    pub(crate) conflict: bool,             // 競合発生中かどうか
+    consistency: ReadConsistency,
+    strict_init: bool,      // 未初期化のストライプの読み込みを失敗させるかどうか (STM::with_strict_init を参照)
+    cache: WriteSet<S>,        // 読み込み済みの値 (同じアドレスの 2 回目以降の load に用いる)
+    history: bool,          // read_version 時点の値を Memory の history から読む (STM::read_at_version を参照)
+    scratch: ScratchBuf,
+    mem: &'a Memory<S>,
 }
 
-impl<'a> WriteTrans<'a> {
-    fn new(mem: &'a mut Memory) -> Self {
-        WriteTrans { 
-            read_version: mem.global_clock.load(Acquire),       // global_clock を copy
-            read_set: HashSet::new(), 
-            write_set: HashMap::new(), 
-            locked: Vec::new(), 
+impl<'a, const S: usize> ReadTrans<'a, S> {
+    #[allow(clippy::redundant_field_names)]
+    pub(crate) fn new(mem: &'a Memory<S>, read_capacity: usize, hasher: SetHasher) -> Self {
+        ReadTrans { 
+            read_version: mem.global_clock.load(Acquire),   // global_clock を copy
             conflict: false, 
+            consistency: ReadConsistency::Linearizable,
+            strict_init: false,
+            cache: HashMap::with_capacity_and_hasher(read_capacity, hasher),
+            history: false,
+            scratch: ScratchBuf::default(),
             mem: mem, 
         }
     }
 
-    // メモリの変更内容 (val) を write_set に (一時) 保存
-    pub fn store(&mut self, addr: usize, val: [u8; STRIPE_SIZE]) {
-        assert_eq!(addr & (STRIPE_SIZE - 1), 0);
-        self.write_set.insert(addr, val);
+    fn with_consistency(mut self, consistency: ReadConsistency) -> Self {
+        self.consistency = consistency;
+        self
     }
 
-    pub fn load(&mut self, addr: usize) -> Option<[u8; STRIPE_SIZE]> {
-        assert_eq!(addr & (STRIPE_SIZE - 1), 0);    // address がストライプのアライメントに適合しない場合はエラー
+    fn with_strict_init(mut self, strict_init: bool) -> Self {
+        self.strict_init = strict_init;
+        self
+    }
 
-        if self.conflict {
-            return None;
-        }
+    fn with_scratch(mut self, scratch: ScratchBuf) -> Self {
+        self.scratch = scratch.cleared();
+        self
+    }
 
-        self.read_set.insert(addr);     // 読み込みアドレス保存
+    // 現在の global_clock の代わりに、過去の version の時点の値を読む
+    fn at_version(mut self, version: u64) -> Self {
+        self.read_version = version;
+        self.history = true;
+        self
+    }
 
-        if let Some(m) = self.write_set.get(&addr) {    // データが write_set にあればそれを読み込み
-            return Some(*m);
-        }   // ない場合はメモリコピーを行う (ReadTrans の場合と同様)
+    // closure の作業用バッファ (ScratchBuf を参照)
+    pub fn scratch(&mut self) -> &mut ScratchBuf {
+        &mut self.scratch
+    }
 
-        if !self.mem.test_not_modify(addr, self.read_version) {     // consistency check
-            self.conflict = true;
-            return None;
-        }
+    // strict_init が有効な場合、未初期化のストライプの読み込みは None を返す (conflict ではないため retry されない)
+    pub fn load(&mut self, addr: usize) -> Option<[u8; S]> {
+        self.try_load(addr).ok()
+    }
+
+    // load と同様だが、読み込んだ値を呼び出し側のバッファに書き込む (検査は load と同じ)
+    pub fn load_into(&mut self, addr: usize, buf: &mut [u8; S]) -> Option<()> {
+        *buf = self.load(addr)?;
+        Some(())
+    }
+
+    // range の中のアドレスを step ごとに load し、値を f で畳み込む (全ての値は同じスナップショットのもの)
+    // いずれかの load が失敗した場合は残りを読まずに None を返す (競合ならトランザクションは retry される)
+    // range.start はストライプのアライメントに適合し、step は S の倍数でなければならない
+    pub fn fold_range<A>(&mut self, range: Range<usize>, step: usize, init: A, mut f: impl FnMut(A, [u8; S]) -> A) -> Option<A> {
+        assert!(step > 0 && step & (S - 1) == 0);
+        let mut acc = init;
+        for addr in range.step_by(step) {
+            acc = f(acc, self.load(addr)?);
+        }
+        Some(acc)
+    }
+
+    // 8 byte のストライプを u64 (little endian) として読む (S が 8 でなければコンパイルエラー)
+    // Linearizable で未読のストライプに限り、データの copy を 1 回の atomic な load (Storage::Words) で行い、
+    // 後半の fence(SeqCst) と cache への記録を省略する。それ以外は load と同じ
+    // (from_mut_slice のバッファはバイトごとに読むが、以下と同じ理由で一貫した値になる)
+    //
+    // 値が一貫していること (tear-free):
+    // commit は lock bit の設定 -> fence(Release) -> データの書き込み -> version の公開の順に行う。
+    // 読んだ word が commit 中の書き込みの値であれば、その fence(Release) と読み込み後の fence(Acquire) が同期し、
+    // 後の lock_ver の load は lock bit (または新しい version) を必ず観測するため、前後の値が一致せず失敗する。
+    // 前後の値が一致すれば、最初の load (Acquire) で観測した version の commit の書き込みより後の値のみ読める。
+    // cache に記録しなくても、同じストライプを再び読むと、その間に commit されていれば version が read_version を超えるため失敗する
+    pub fn load_single_word(&mut self, addr: usize) -> Option<u64> {
+        let () = Self::SINGLE_WORD;
+        if self.history || self.consistency != ReadConsistency::Linearizable || self.cache.contains_key(&addr) {
+            return self.load(addr).map(|val| u64::from_le_bytes(val[..8].try_into().unwrap()));
+        }
+        assert_eq!(addr & (S - 1), 0);
+        if self.conflict {
+            return None;
+        }
+
+        let lock_ver = &self.mem.lock_ver[self.mem.lock_word(addr)];
+        let before = lock_ver.load(Acquire);
+        if before > self.read_version {     // lock 中ならば最上位 bit により必ず超える
+            self.conflict = true;
+            return None;
+        }
+        let val = self.mem.read_stripe_word(addr);
+        fence(Acquire);
+        if lock_ver.load(Relaxed) != before {
+            self.conflict = true;
+            return None;
+        }
+        if self.strict_init && !self.mem.is_initialized(addr) {
+            return None;
+        }
+        Some(val)
+    }
+
+    const SINGLE_WORD: () = assert!(S == 8, "load_single_word requires 8-byte stripes");
+
+    // load と同様だが、失敗理由を返す
+    pub fn try_load(&mut self, addr: usize) -> Result<[u8; S], LoadError> {
+        if self.history {
+            return self.load_history(addr);
+        }
+        let val = self.load_checked(addr).ok_or(LoadError::Conflict)?;
+        if self.strict_init && !self.mem.is_initialized(addr) {
+            return Err(LoadError::Uninitialized);
+        }
+        Ok(val)
+    }
+
+    // 読み込みトランザクションを、同じスナップショットから続ける書き込みトランザクション (TxScope) に切り替える
+    // read_version と、ここまでに読んだアドレスとその値を引き継ぐ: promote 後の load は引き継いだ値を読み直さずに返し、
+    // 引き継いだアドレスは commit 時に read_set として検証される。TxScope::finish で commit する
+    // 競合中の場合、または読んだストライプが read_version より後に更新されている (lock 中を含む) 場合は None を返す
+    // (read_transaction から Retry を返すなどして最初からやり直す)。load_single_word で読んだ値は引き継がない
+    // stm はこの ReadTrans を実行している STM でなければならない
+    pub fn promote<'s>(&self, stm: &'s STM<S>) -> Option<TxScope<'s, S>> {
+        assert!(std::ptr::eq(self.mem, &stm.mem), "promote must be given the STM running this transaction");
+        // Snapshot / Eventual で読んだ値も、ここで更新されていないことを確かめれば read_version 時点の値である
+        if self.conflict || self.cache.keys().any(|addr| !self.mem.test_not_modify(*addr, self.read_version)) {
+            return None;
+        }
+        let mut trans = stm.begin_write();
+        trans.read_version = self.read_version;
+        trans.read_set.extend(self.cache.keys());
+        trans.snapshot.extend(self.cache.iter().map(|(addr, val)| (*addr, *val)));
+        Some(TxScope { stm, trans })
+    }
+
+    // memory copy の前後で consistency check を行い、適合した場合のみ読み込み成功
+    fn load_checked(&mut self, addr: usize) -> Option<[u8; S]> {
+        assert_eq!(addr & (S - 1), 0);    // address がストライプのアライメントに適合しない場合はエラー
+
+        // consistency check
+        if self.conflict {
+            return None;
+        } 
+        if let Some(m) = self.cache.get(&addr) {    // 読み込み済みならその値を返す (同じスナップショットの値なので一貫している)
+            return Some(*m);
+        }
+        if self.consistency == ReadConsistency::Eventual {  // 検査なし
+            let mem = self.mem.read_stripe(addr);
+            self.cache.insert(addr, mem);
+            return Some(mem);
+        }
+        if !self.mem.test_not_modify(addr, self.read_version) {
+            self.conflict = true;
+            return None;
+        }
+
+        // メモリコピー
+        fence(Acquire);
+        let mem = self.mem.read_stripe(addr);
+
+        if self.consistency == ReadConsistency::Snapshot || self.mem.skips_recheck() {  // copy 後の検査を省略
+            self.cache.insert(addr, mem);
+            return Some(mem);
+        }
+
+        fence(SeqCst);
+        // consistency check: 読み込みメモリがロックされておらず、かつ read_version 以下であるかどうか
+        if !self.mem.test_not_modify(addr, self.read_version) {
+            self.conflict = true;
+            return None;
+        }
+
+        self.cache.insert(addr, mem);
+        Some(mem)
+    }
+
+    // read_version 以下で最新の commit の値を読む
+    // read_version 以下の version を割り当てられた commit は、lock を解除する前に値を記録するため、
+    // lock されていなければその値は記録済み (lock 中であれば競合として retry する)
+    fn load_history(&mut self, addr: usize) -> Result<[u8; S], LoadError> {
+        assert_eq!(addr & (S - 1), 0);
+
+        if self.conflict {
+            return Err(LoadError::Conflict);
+        }
+        if let Some(m) = self.cache.get(&addr) {
+            return Ok(*m);
+        }
+        if self.mem.is_locked(addr) {
+            self.conflict = true;
+            return Err(LoadError::Conflict);
+        }
+        fence(Acquire);
+        let val = self.mem.history_load(addr, self.read_version).ok_or(LoadError::Evicted)?;
+        self.cache.insert(addr, val);
+        Ok(val)
+    }
+}
+
+// ReadTrans と WriteTrans のどちらでも読み込めるようにするための trait (txmap などで用いる)
+// 読み込みだけを行う処理は &mut impl Loadable を受け取れば、どちらのトランザクションからも呼び出せる
+// (load! は Loadable を実装した型に対しても使える)
+pub trait Loadable<const S: usize = STRIPE_SIZE> {
+    fn load(&mut self, addr: usize) -> Option<[u8; S]>;
+}
+
+impl<'a, const S: usize> Loadable<S> for ReadTrans<'a, S> {
+    fn load(&mut self, addr: usize) -> Option<[u8; S]> {
+        ReadTrans::load(self, addr)
+    }
+}
+
+impl<'a, const S: usize> Loadable<S> for WriteTrans<'a, S> {
+    fn load(&mut self, addr: usize) -> Option<[u8; S]> {
+        WriteTrans::load(self, addr)
+    }
+}
+
+// 書き込みもできるトランザクションの trait (WriteTrans だけが実装する)
+// ReadTrans は実装しないため、&mut impl Storable を要求する処理に ReadTrans を渡すとコンパイルエラーになる
+pub trait Storable<const S: usize = STRIPE_SIZE>: Loadable<S> {
+    fn store(&mut self, addr: usize, val: [u8; S]);
+}
+
+impl<'a, const S: usize> Storable<S> for WriteTrans<'a, S> {
+    fn store(&mut self, addr: usize, val: [u8; S]) {
+        WriteTrans::store(self, addr, val)
+    }
+}
+
+// トランザクションの closure が各実行で使い回せる作業用のバイト列 (ReadTrans::scratch, WriteTrans::scratch)
+// 実行ごとに空になるが、確保した容量は同じトランザクションの次の実行 (retry) に引き継がれるため、
+// 条件待ちや競合で再実行を繰り返しても、2 回目以降の実行では (容量を超えない限り) heap の確保が起きない
+// 借用したまま load はできないため、load と交互に使う場合は mem::take(tr.scratch()) で取り出し、使い終わったら
+// *tr.scratch() = buf で戻す (戻さなかった場合は、次の実行で確保し直すだけ)
+#[derive(Debug, Default)]
+pub struct ScratchBuf {
+    bytes: Vec<u8>,
+}
+
+impl ScratchBuf {
+    fn cleared(mut self) -> Self {
+        self.bytes.clear();
+        self
+    }
+}
+
+impl Deref for ScratchBuf {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.bytes
+    }
+}
+
+impl DerefMut for ScratchBuf {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.bytes
+    }
+}
+
+// dry run で記録される WriteTrans の操作 (STM::write_transaction_dry_run を参照)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation<const S: usize = STRIPE_SIZE> {
+    Load { addr: usize },
+    Store { addr: usize, bytes: [u8; S] },   // bytes は stage された値
+}
+
+pub struct WriteTrans<'a, const S: usize = STRIPE_SIZE> {
+    read_version: u64,
+    pub(crate) read_set: ReadSet,
+    pub(crate) write_set: WriteSet<S>,
+    locked: Vec<usize>,     // lock したアドレス (Drop するときのため覚えておく)
+    pub(crate) conflict: bool,
+    pub(crate) conflict_addr: Option<usize>,   // 最後に競合したアドレス (読み込み・lock・検証のいずれかで失敗したアドレス)
+    pub(crate) validation_failed: bool,     // commit 時の read_set の検証で失敗した (conflict_addr はその検証で更新されていたアドレス)
+    pub(crate) abort_requested: bool,   // request_abort を参照
+    span_check: bool,           // 複数ストライプにまたがる書き込みの部分的な上書きを検出するかどうか
+    spans: Vec<(usize, usize)>, // 複数ストライプにまたがる書き込みの範囲 [start, end) (span_check が有効な場合のみ記録)
+    commit_ordering: Ordering,  // commit 時の version の store に用いる ordering
+    commit_order: CommitOrder,  // commit 時にストライプを書き込む順序
+    lock_order: LockOrder,      // commit 時にストライプを lock する順序
+    ops: Option<Vec<Operation<S>>>,    // dry run の場合のみ、load / store を記録する
+    pub(crate) trace: Option<Vec<Phase<S>>>,   // SteppableTransaction の場合のみ、load の値と store を記録する
+    audit: Option<Vec<AuditEntry>>,  // write_transaction_audit の場合のみ、commit した (addr, 以前の version, 新しい version) を記録する
+    strict_init: bool,          // ReadTrans::strict_init と同様
+    resets: Vec<usize>,         // reset_stripe で未初期化に戻すアドレス (その後に store したアドレスは含まない)
+    callbacks: Vec<Box<dyn FnOnce()>>,  // commit した場合に実行する処理 (on_commit を参照)
+    snapshot: WriteSet<S>,      // ReadTrans::promote で引き継いだ、read_version 時点の値 (メモリから読み直さない)
+    scratch: ScratchBuf,
+    pub(crate) mem: &'a Memory<S>,
+}
+
+impl<'a, const S: usize> WriteTrans<'a, S> {
+    pub(crate) fn new(mem: &'a Memory<S>, read_capacity: usize, write_capacity: usize, hasher: SetHasher) -> Self {
+        Self::new_at(mem, read_capacity, write_capacity, hasher, mem.global_clock.load(Acquire))    // global_clock を copy
+    }
+
+    // read_version を与えて作成する (global_clock を読まない; read_version は現在の global_clock 以下でなければならない)
+    #[allow(clippy::redundant_field_names)]
+    fn new_at(mem: &'a Memory<S>, read_capacity: usize, write_capacity: usize, hasher: SetHasher, read_version: u64) -> Self {
+        WriteTrans { 
+            read_version,
+            read_set: HashSet::with_capacity_and_hasher(read_capacity, hasher.clone()), 
+            write_set: HashMap::with_capacity_and_hasher(write_capacity, hasher.clone()), 
+            locked: Vec::with_capacity(write_capacity), 
+            conflict: false, 
+            conflict_addr: None,
+            validation_failed: false,
+            abort_requested: false,
+            span_check: false,
+            spans: Vec::new(),
+            commit_ordering: Relaxed,
+            commit_order: CommitOrder::default(),
+            lock_order: LockOrder::default(),
+            ops: None,
+            trace: None,
+            audit: None,
+            strict_init: false,
+            resets: Vec::new(),
+            callbacks: Vec::new(),
+            snapshot: HashMap::with_hasher(hasher),
+            scratch: ScratchBuf::default(),
+            mem: mem, 
+        }
+    }
+
+    fn with_span_check(mut self, span_check: bool) -> Self {
+        self.span_check = span_check;
+        self
+    }
+
+    fn with_commit_ordering(mut self, ordering: Ordering) -> Self {
+        self.commit_ordering = ordering;
+        self
+    }
+
+    fn with_commit_order(mut self, order: CommitOrder) -> Self {
+        self.commit_order = order;
+        self
+    }
+
+    fn with_lock_order(mut self, order: LockOrder) -> Self {
+        self.lock_order = order;
+        self
+    }
+
+    fn with_strict_init(mut self, strict_init: bool) -> Self {
+        self.strict_init = strict_init;
+        self
+    }
+
+    // 現在の global_clock の代わりに version を read_version とする (STM::write_transaction_at を参照)
+    fn at_version(mut self, version: u64) -> Self {
+        self.read_version = version;
+        self
+    }
+
+    // トランザクションを abort する (closure が何を返しても commit も retry もせず、write_transaction は None を返す)
+    // 呼び出し後は STMResult::Abort を返して closure を終えればよい。stage 済みの書き込みは破棄される
+    // closure の実行中は lock を獲得していない (lock は closure の終了後に獲得する) ため、
+    // abort したトランザクションは lock の獲得も global_clock の更新も行わず、メモリは変更されない
+    pub fn request_abort(&mut self) {
+        self.abort_requested = true;
+        self.write_set.clear();
+    }
+
+    pub fn abort_requested(&self) -> bool {
+        self.abort_requested
+    }
+
+    // メモリの変更内容 (val) を write_set に (一時) 保存
+    pub fn store(&mut self, addr: usize, val: [u8; S]) {
+        assert_eq!(addr & (S - 1), 0);
+        self.check_span(addr, S);
+        self.stage(addr, val);
+    }
+
+    // 複数ストライプにまたがる値 (bytes) を write_set に (一時) 保存
+    // addr はストライプのアライメントに適合し、bytes.len() は S の倍数でなければならない
+    // 同じトランザクション内の書き込みはストライプごとに後勝ち: store / store_bytes / copy_within のいずれも、
+    // 覆う各ストライプの stage 済みの値を丸ごと置き換える (覆わないストライプの値はそのまま残る)
+    // 例: store(0, a); store_bytes(0, b ++ c) => 0: b, 8: c
+    //     store_bytes(0, a ++ b); store(8, c) => 0: a, 8: c (span_check が有効な場合は部分的な上書きとして panic)
+    pub fn store_bytes(&mut self, addr: usize, bytes: &[u8]) {
+        assert_eq!(addr & (S - 1), 0);
+        assert_eq!(bytes.len() & (S - 1), 0);
+        self.check_span(addr, bytes.len());
+        for (i, chunk) in bytes.chunks_exact(S).enumerate() {
+            self.stage(addr + i * S, chunk.try_into().unwrap());
+        }
+    }
+
+    // このトランザクションが commit した後に f を実行する (登録順; lock の解放後に、commit したスレッドで実行する)
+    // retry した場合や abort した場合は実行せずに破棄する (retry 後の実行で改めて登録される)
+    // write_transaction 系のメソッド (async 版を含む)、TxScope::finish、steppable、DeterministicSTM::write_transaction で commit した場合に実行する
+    pub fn on_commit(&mut self, f: impl FnOnce() + 'static) {
+        self.callbacks.push(Box::new(f));
+    }
+
+    // on_commit で登録され、まだ実行されていない処理の個数
+    pub fn pending_callbacks(&self) -> usize {
+        self.callbacks.len()
+    }
+
+    pub(crate) fn run_callbacks(&mut self) {
+        for f in mem::take(&mut self.callbacks) {
+            f();
+        }
+    }
+
+    // 現在の書き込み (stage 済みの値と reset_stripe, on_commit の登録) を記録する (rollback を参照)
+    pub(crate) fn savepoint(&self) -> Savepoint<S> {
+        Savepoint {
+            write_set: self.write_set.clone(),
+            resets: self.resets.clone(),
+            spans: self.spans.clone(),
+            callbacks: self.callbacks.len(),
+        }
+    }
+
+    // savepoint の時点より後の書き込みと on_commit の登録を破棄する
+    // read_set はそのまま残す (読んだストライプは commit 時に検証される)
+    pub(crate) fn rollback(&mut self, savepoint: Savepoint<S>) {
+        self.write_set = savepoint.write_set;
+        self.resets = savepoint.resets;
+        self.spans = savepoint.spans;
+        self.callbacks.truncate(savepoint.callbacks);
+    }
+
+    // f を入れ子のトランザクション (closed nesting) として実行する
+    // f が Ok を返した場合、その書き込みはこのトランザクションの一部となり Ok(Some(r)) を返す。
+    // f が Abort を返した場合は、f が stage した書き込み・reset_stripe・on_commit の登録だけを破棄して Ok(None) を返し、
+    // このトランザクションはそのまま続けられる (f の前の書き込みは残る)。
+    // Retry / RetryOk はこのトランザクション全体の結果として返す (f の中の競合は全体の retry となる)。
+    // f の中で request_abort した場合は、このトランザクション全体を abort する (Abort を返す)
+    // f が読んだストライプは破棄せず read_set に残す: abort するかどうかの判断が読んだ値に依存しうるため、commit 時に検証する
+    pub fn nested<R>(&mut self, f: impl FnOnce(&mut Self) -> STMResult<R>) -> STMResult<Option<R>> {
+        let savepoint = self.savepoint();
+        let outcome = f(self);
+        if self.abort_requested {
+            return STMResult::Abort;
+        }
+        match outcome {
+            STMResult::Ok(r) => STMResult::Ok(Some(r)),
+            STMResult::Abort => {
+                self.rollback(savepoint);
+                STMResult::Ok(None)
+            }
+            STMResult::Retry => STMResult::Retry,
+            STMResult::RetryOk => STMResult::RetryOk,
+        }
+    }
+
+    // ストライプを初期状態に戻す: 0 で埋めた値を stage し、commit 時に未初期化 (strict_init での読み込みは Uninitialized) とする
+    // 0 の store とは異なり「解放して消去する」操作を表す (allocator の free list などと組み合わせる)。
+    // 値と初期化の状態は同じ commit で公開される。以降に同じストライプへ store した場合、reset は取り消される
+    pub fn reset_stripe(&mut self, addr: usize) {
+        assert_eq!(addr & (S - 1), 0);
+        self.check_span(addr, S);
+        self.stage(addr, [0; S]);
+        self.resets.push(addr);
+    }
+
+    fn stage(&mut self, addr: usize, val: [u8; S]) {
+        if !self.resets.is_empty() {
+            self.resets.retain(|a| *a != addr);
+        }
+        if let Some(ops) = self.ops.as_mut() {
+            ops.push(Operation::Store { addr, bytes: val });
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            trace.push(Phase::Write(addr, val));
+        }
+        self.write_set.insert(addr, val);
+    }
+
+    // span_check が有効な場合、[addr, addr + len) への書き込みが、同じトランザクション内の
+    // 複数ストライプにまたがる書き込みの一部だけを上書きしていないかを検査する (一部だけの上書きは panic)
+    // 範囲全体を覆う書き込みは許され、以前の範囲はその書き込みに置き換わる
+    fn check_span(&mut self, addr: usize, len: usize) {
+        if !self.span_check {
+            return;
+        }
+        let end = addr + len;
+        self.spans.retain(|&(start, span_end)| {
+            if span_end <= addr || end <= start {
+                return true;    // 重ならない
+            }
+            assert!(addr <= start && span_end <= end,
+                "store to [{:#x}, {:#x}) partially overwrites a multi-stripe store to [{:#x}, {:#x})", addr, end, start, span_end);
+            false
+        });
+        if len > S {
+            self.spans.push((addr, end));
+        }
+    }
+
+    // strict_init が有効な場合、未初期化のストライプの読み込みは None を返す (ReadTrans::load と同様)
+    pub fn load(&mut self, addr: usize) -> Option<[u8; S]> {
+        self.try_load(addr).ok()
+    }
+
+    // load と同様だが、読み込んだ値を呼び出し側のバッファに書き込む (ReadTrans::load_into と同様)
+    pub fn load_into(&mut self, addr: usize, buf: &mut [u8; S]) -> Option<()> {
+        *buf = self.load(addr)?;
+        Some(())
+    }
+
+    // load と同様だが、失敗理由を返す
+    pub fn try_load(&mut self, addr: usize) -> Result<[u8; S], LoadError> {
+        let result = self.try_load_untraced(addr);
+        if let (Some(trace), Ok(val)) = (self.trace.as_mut(), &result) {
+            trace.push(Phase::Read(addr, *val));
+        }
+        result
+    }
+
+    // addr のストライプに最後に commit された version を値として読む (楽観的な同時実行制御のトークンなどに用いる)
+    // load と同様に read_set に加えるため、commit までに version が変われば競合として retry する
+    // (「クライアントのトークンが現在の version と一致する場合のみ更新する」をデータに version を格納せずに書ける)
+    // lock 中、または read_version より新しい場合は競合 (None)。自身の store は version を変えない (commit 時に新しい version となる)
+    pub fn load_version(&mut self, addr: usize) -> Option<u64> {
+        assert_eq!(addr & (S - 1), 0);
+        if self.conflict {
+            return None;
+        }
+        self.read_set.insert(addr);
+        if !self.mem.test_not_modify(addr, self.read_version) {
+            self.conflict = true;
+            self.conflict_addr = Some(addr);
+            return None;
+        }
+        Some(self.mem.get_version(addr))
+    }
+
+    fn try_load_untraced(&mut self, addr: usize) -> Result<[u8; S], LoadError> {
+        assert_eq!(addr & (S - 1), 0);    // address がストライプのアライメントに適合しない場合はエラー
+
+        if self.conflict {
+            return Err(LoadError::Conflict);
+        }
+        if let Some(ops) = self.ops.as_mut() {
+            ops.push(Operation::Load { addr });
+        }
+
+        if let Some(m) = self.write_set.get(&addr) {    // データが write_set にあればそれを読み込み
+            if self.strict_init && self.resets.contains(&addr) {
+                return Err(LoadError::Uninitialized);   // 自身が reset_stripe したストライプ
+            }
+            return Ok(*m);                              // 自身の書き込みが優先されるため、read_set には加えない
+        }   // ない場合はメモリコピーを行う (ReadTrans の場合と同様)
+
+        let val = self.load_from_memory(addr).ok_or(LoadError::Conflict)?;
+        if self.strict_init && !self.mem.is_initialized(addr) {
+            return Err(LoadError::Uninitialized);
+        }
+        Ok(val)
+    }
+
+    fn load_from_memory(&mut self, addr: usize) -> Option<[u8; S]> {
+        self.read_set.insert(addr);     // メモリから読み込むアドレスを保存 (write 前に読んでいればそのまま残る)
+        if let Some(val) = self.snapshot.get(&addr) {   // promote 前に読んだ値 (read_set として検証される)
+            return Some(*val);
+        }
+
+        if !self.mem.test_not_modify(addr, self.read_version) {     // consistency check
+            self.conflict = true;
+            self.conflict_addr = Some(addr);
+            return None;
+        }
 
         // メモリコピー
         fence(Acquire);
-        let mut mem = [0; STRIPE_SIZE];
-        for i in 0..STRIPE_SIZE {
-            mem[i] = self.mem.mem[addr + i];
+        let mem = self.mem.read_stripe(addr);
+        if self.mem.skips_recheck() {   // copy 中に書き込むスレッドは存在しない
+            return Some(mem);
+        }
+
+        fence(SeqCst);
+        // consistency check: 読み込みメモリがロックされておらず、かつ read_version 以下であるかどうか
+        if !self.mem.test_not_modify(addr, self.read_version) {
+            self.conflict = true;
+            self.conflict_addr = Some(addr);
+            return None;
+        }
+
+        Some(mem)
+    }
+
+    // 最後に競合したアドレス (競合していなければ None)
+    pub fn conflict_addr(&self) -> Option<usize> {
+        self.conflict_addr
+    }
+
+    // ここまでにメモリから読み込んだストライプのアドレス (順不同; write_set から読んだアドレスは含まない)
+    pub fn read_addresses(&self) -> impl Iterator<Item = usize> + '_ {
+        self.read_set.iter().copied()
+    }
+
+    // ここまでに書き込んだストライプのアドレス (順不同)
+    pub fn write_addresses(&self) -> impl Iterator<Item = usize> + '_ {
+        self.write_set.keys().copied()
+    }
+
+    fn with_scratch(mut self, scratch: ScratchBuf) -> Self {
+        self.scratch = scratch.cleared();
+        self
+    }
+
+    // ctx の確保済みの領域を空にして用いる (一度も用いていない ctx の場合は new で確保したものをそのまま使う)
+    fn with_context(mut self, ctx: TxContext<S>) -> Self {
+        if ctx.read_set.capacity() > 0 {
+            self.read_set = ctx.read_set;
+            self.read_set.clear();
+        }
+        if ctx.write_set.capacity() > 0 {
+            self.write_set = ctx.write_set;
+            self.write_set.clear();
+        }
+        if ctx.locked.capacity() > 0 {
+            self.locked = ctx.locked;
+            self.locked.clear();
+        }
+        self.with_scratch(ctx.scratch)
+    }
+
+    // 獲得中の lock を解放し、確保した領域を TxContext として取り出す (次の実行に容量を引き継ぐ)
+    fn take_context(&mut self) -> TxContext<S> {
+        for addr in self.locked.drain(..) {
+            self.mem.unlock_addr(addr);
+        }
+        TxContext {
+            read_set: mem::take(&mut self.read_set),
+            write_set: mem::take(&mut self.write_set),
+            locked: mem::take(&mut self.locked),
+            scratch: mem::take(&mut self.scratch),
+            read_hint: None,
+        }
+    }
+
+    // closure の作業用バッファ (ScratchBuf を参照)
+    pub fn scratch(&mut self) -> &mut ScratchBuf {
+        &mut self.scratch
+    }
+
+    // src から len バイトを dst に (トランザクションの一部として) コピーする
+    // 全ての読み込みを書き込みの stage より先に行うため、src と dst の範囲が重なっていてもよい
+    // src, dst はストライプのアライメントに適合し、len は S の倍数でなければならない
+    pub fn copy_within(&mut self, src: usize, dst: usize, len: usize) -> Option<()> {
+        assert_eq!(src & (S - 1), 0);
+        assert_eq!(dst & (S - 1), 0);
+        assert_eq!(len & (S - 1), 0);
+
+        let mut values = Vec::with_capacity(len / S);
+        for offset in (0..len).step_by(S) {
+            values.push(self.load(src + offset)?);
+        }
+        self.check_span(dst, len);      // コピー先は 1 つの (複数ストライプにまたがる) 書き込みとして扱う
+        for (i, val) in values.into_iter().enumerate() {
+            self.stage(dst + i * S, val);
+        }
+        Some(())
+    }
+
+    // a と b のストライプの値を (トランザクションの一部として) 入れ替える
+    // 両方を読んでから stage するため、両方が read_set と write_set に入り、commit 時に検証される (a == b なら値は変わらない)
+    // 競合した場合は何も stage せずに None を返す
+    pub fn exchange(&mut self, a: usize, b: usize) -> Option<()> {
+        let va = self.load(a)?;
+        let vb = self.load(b)?;
+        self.store(a, vb);
+        self.store(b, va);
+        Some(())
+    }
+
+    // from の値が cond を満たす場合に限り、その値を to に移して from を 0 にする (移した場合は Some(true))
+    // 満たさない場合はどちらも変更しない。from と to は両方とも読み込むため、いずれも commit 時に検証される
+    // from == to の場合は何も stage せず、値が cond を満たすかどうかだけを返す (値は移動先にあるとみなす)
+    pub fn move_if(&mut self, from: usize, to: usize, cond: impl Fn([u8; S]) -> bool) -> Option<bool> {
+        let val = self.load(from)?;
+        if from == to {
+            return Some(cond(val));
+        }
+        self.load(to)?;
+        if !cond(val) {
+            return Some(false);
+        }
+        self.store(to, val);
+        self.store(from, [0; S]);
+        Some(true)
+    }
+
+    // 各 (addr, expected, new) について addr の値が expected と一致するかを調べ、全て一致した場合のみ new を stage する
+    // 1 つでも一致しなければ何も stage せずに Some(false) を返す (競合した場合は None)
+    // 比較はトランザクションの読み込みとして行われるため、commit 時に他の書き込みがあれば検証で retry になる
+    pub fn multi_cas(&mut self, ops: &[(usize, [u8; S], [u8; S])]) -> Option<bool> {
+        for (addr, expected, _) in ops.iter() {
+            if self.load(*addr)? != *expected {
+                return Some(false);
+            }
+        }
+        for (addr, _, new) in ops.iter() {
+            self.store(*addr, *new);
+        }
+        Some(true)
+    }
+
+    // メモリを bit 列として扱い、bit_index 番目の bit を 1 にする。以前の値を返す (読み込みが競合した場合は None)
+    // bit_index 番目の bit はアドレス bit_index / 8 のバイトの (bit_index % 8) 番目の bit (各ストライプを little endian の整数とみなした場合の bit 順)
+    // 同じストライプの bit は 1 つのストライプとして読み書きされるため、異なる bit を操作するトランザクション同士も競合する
+    // 値が変わらない場合は書き込まない (読み込みのみとなる)
+    pub fn test_and_set_bit(&mut self, bit_index: usize) -> Option<bool> {
+        self.update_bit(bit_index, true)
+    }
+
+    // bit_index 番目の bit を 0 にする。以前の値を返す (test_and_set_bit を参照)
+    pub fn clear_bit(&mut self, bit_index: usize) -> Option<bool> {
+        self.update_bit(bit_index, false)
+    }
+
+    // bit_index 番目の bit を読む (test_and_set_bit を参照)
+    pub fn test_bit(&mut self, bit_index: usize) -> Option<bool> {
+        let (addr, byte, mask) = bit_location::<S>(bit_index, self.mem.size());
+        Some(self.load(addr)?[byte] & mask != 0)
+    }
+
+    fn update_bit(&mut self, bit_index: usize, set: bool) -> Option<bool> {
+        let (addr, byte, mask) = bit_location::<S>(bit_index, self.mem.size());
+        let mut val = self.load(addr)?;
+        let old = val[byte] & mask != 0;
+        if old != set {
+            val[byte] ^= mask;
+            self.store(addr, val);
+        }
+        Some(old)
+    }
+
+    // 現時点で commit できる状態かどうかを調べる (lock の獲得も commit も行わず、共有状態を変更しない)
+    // 調べた直後に他のトランザクションが lock / commit すれば結果は変わりうるため、あくまで目安として用いる
+    pub fn would_commit(&self) -> bool {
+        if self.conflict {
+            return false;
+        }
+        // 読み込んだアドレスが lock も更新もされていない
+        if !self.read_set.iter().all(|addr| self.mem.test_not_modify(*addr, self.read_version)) {
+            return false;
+        }
+        // 書き込み先のアドレスが他のトランザクションに lock されていない
+        self.write_set.keys().all(|addr| !self.mem.is_locked(*addr))
+    }
+
+    // write_set に対応するメモリを lock_order の順にロックしようと試みる
+    pub(crate) fn lock_write_set(&mut self) -> bool {
+        let failed = if self.lock_order == LockOrder::HashOrder {
+            self.write_set.keys().copied().find(|addr| !Self::lock_next(self.mem, &mut self.locked, *addr))
+        } else {
+            let mut addrs: Vec<usize> = self.write_set.keys().copied().collect();
+            match self.lock_order {
+                LockOrder::AddressAscending => addrs.sort_unstable(),
+                _ => addrs.sort_by_cached_key(|addr| (std::cmp::Reverse(self.mem.lock_conflicts(*addr)), *addr)),
+            }
+            addrs.into_iter().find(|addr| !Self::lock_next(self.mem, &mut self.locked, *addr))
+        };
+        if let Some(addr) = failed {
+            self.mem.lock_conflicts[addr >> self.mem.shift_size].fetch_add(1, Relaxed);
+            self.conflict_addr = Some(addr);
+            return false;
+        }
+        true
+    }
+
+    // lock_write_set の 1 ストライプ分 (失敗した場合は false)
+    fn lock_next(mem: &Memory<S>, locked: &mut Vec<usize>, addr: usize) -> bool {
+        if mem.shares_lock_words() && locked.iter().any(|l| mem.lock_word(*l) == mem.lock_word(addr)) {
+            return true;        // 同じ lock_ver を共有するストライプを既に lock している
+        }
+        if mem.lock_addr(addr) {        // lock 獲得に成功
+            locked.push(addr);          // drop 時のために覚えておく
+            true
+        } else {
+            false
+        }
+    }
+
+    // 1 つのストライプの lock の獲得を試みる (SteppableTransaction 用; lock_write_set と同様に locked に記録する)
+    pub(crate) fn lock_stripe(&mut self, addr: usize) -> bool {
+        if self.mem.shares_lock_words() && self.holds_lock_word(addr) {
+            return true;
+        }
+        if self.mem.lock_addr(addr) {
+            self.locked.push(addr);
+            true
+        } else {
+            self.conflict_addr = Some(addr);
+            false
+        }
+    }
+
+    pub(crate) fn read_version(&self) -> u64 {
+        self.read_version
+    }
+
+    // addr の lock_ver を、このトランザクションが lock しているかどうか
+    fn holds_lock_word(&self, addr: usize) -> bool {
+        let word = self.mem.lock_word(addr);
+        self.locked.iter().any(|locked| self.mem.lock_word(*locked) == word)
+    }
+
+    // 書き込み先が 1 ストライプのみで、それ以外のアドレスを (メモリから) 読んでいない場合、そのアドレスを返す
+    fn single_stripe(&self) -> Option<usize> {
+        if self.write_set.len() != 1 || self.read_set.len() > 1 {
+            return None;
+        }
+        let addr = *self.write_set.keys().next().unwrap();
+        match self.read_set.iter().next() {
+            Some(read) if *read != addr => None,
+            _ => Some(addr),
+        }
+    }
+
+    // single_stripe の場合の lock 獲得: lock と read_set の検証を 1 回の CAS で行う
+    // (読んだアドレスは書き込み先のストライプのみなので、その version が read_version 以下であれば read_set 全体が検証済み)
+    // global_clock の更新は省略できない: ストライプの version は global_clock 以下でなければならず
+    // (そうでないと reader が永久に conflict する)、他のトランザクションの read_version + 1 == new_version による検証の省略も
+    // 全ての commit が global_clock を進めることを前提としているため
+    fn lock_single_stripe(&mut self, addr: usize) -> bool {
+        let max_version = if self.read_set.is_empty() { None } else { Some(self.read_version) };
+        if self.mem.lock_addr_if_not_modify(addr, max_version) {
+            self.locked.push(addr);
+            true
+        } else {
+            self.conflict_addr = Some(addr);
+            false
+        }
+    }
+
+    // 差分検証: read_version から new_version までの間に commit されたストライプと read_set が重ならなければ検証を省略する
+    // new_version より後の commit は、このトランザクションより後に直列化されるため考慮しなくてよい
+    // (read_version + 1 == new_version の場合に検証を省略するのと同じ理由)
+    // 間の commit の記録が揃っていない場合は、全ての read_set を検証する
+    fn validate_read_set_since(&self, new_version: u64) -> Result<(), usize> {
+        if let Some(written) = self.mem.written_between(self.read_version, new_version) {
+            return match self.read_set.iter().find(|addr| written & self.mem.stripe_bit(**addr) != 0) {
+                Some(addr) => Err(*addr),
+                None => Ok(()),
+            };
+        }
+        self.validate_read_set()
+    }
+
+    // write_set の各ストライプを表す bit の和
+    pub(crate) fn written_bits(&self) -> u64 {
+        self.write_set.keys().fold(0, |bits, addr| bits | self.mem.stripe_bit(*addr))
+    }
+
+    // 検証に失敗した場合は、更新されていたアドレスを返す
+    pub(crate) fn validate_read_set(&self) -> Result<(), usize> {                  // read_set 検証
+        for addr in self.read_set.iter() {                          // メモリから読み込んだすべてのアドレスに対し
+            // 読んだ後に書き込んだアドレスは自身が lock しているため、lock bit を除いた version で検証する
+            // (読み込みから lock までの間に他のトランザクションが commit していれば version > read_version となる)
+            // lock_ver を共有する他のストライプに書き込む場合も、その lock_ver は自身が lock している
+            if self.write_set.contains_key(addr) || (self.mem.shares_lock_words() && self.holds_lock_word(*addr)) {
+                let version = self.mem.get_version(*addr);             // 処理中に version が更新されていないか調べる
+                if version > self.read_version {
+                    return Err(*addr);
+                }
+            } else {                                                        // write していないならば
+                if !self.mem.test_not_modify(*addr, self.read_version) {    // 処理中に version が更新されていないか調べる
+                    return Err(*addr);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 1 つのストライプのデータと、それに付随する記録 (初期化済み・最後の writer・history) を書き込む (commit を参照)
+    fn write_back(&self, addr: usize, val: &[u8; S], version: u64) {
+        self.mem.write_stripe(addr, val);
+        let initialized = &self.mem.initialized[self.mem.stripe_index(addr)];
+        let init = !self.resets.contains(&addr);    // reset_stripe したストライプは未初期化に戻す
+        if initialized.load(Relaxed) != init {  // 状態が変わらなければ書き込まない (cache line を汚さない)
+            initialized.store(init, Relaxed);
+        }
+        if let Some(last_writer) = &self.mem.last_writer {
+            last_writer[addr >> self.mem.shift_size].store(writer_id(), Relaxed);
+        }
+        self.mem.record_history(addr, version, val);   // version の公開 (lock の解除) より前に記録する
+    }
+
+    // 書き込んだかどうかを返す
+    pub(crate) fn commit(&mut self, version: u64) -> bool {
+        // 書き込み先の lock を全て保持していなければ、他のトランザクションと同時に書き込みうる
+        // その場合は何も書き込まずに poison し、false を返す (獲得済みの lock は drop で解放される)
+        if self.write_set.keys().any(|addr| !self.mem.is_locked(*addr)) {
+            self.mem.poison();
+            return false;
+        }
+
+        // lock bit の設定をデータの書き込みより先に公開する
+        // (書き込み途中のデータを読んだ reader は、コピー後の検証で必ず lock bit を観測する)
+        fence(Release);
+
+        // メモリに書き込み (copy)
+        match self.commit_order {
+            CommitOrder::Unspecified => {
+                for (addr, val) in self.write_set.iter() {
+                    self.write_back(*addr, val, version);
+                }
+            }
+            CommitOrder::AddressAscending => {
+                let mut addrs: Vec<usize> = self.write_set.keys().copied().collect();
+                addrs.sort_unstable();
+                for addr in addrs {
+                    self.write_back(addr, &self.write_set[&addr], version);
+                }
+                self.locked.sort_unstable();    // version もアドレスの昇順に公開する
+            }
+        }
+        fence(Release);
+
+        if let Some(audit) = self.audit.as_mut() {
+            let start = audit.len();
+            for (addr, _) in self.write_set.iter() {
+                audit.push((*addr, self.mem.get_version(*addr), version));     // lock 中なので、以前の version は確定している
+            }
+            if self.commit_order == CommitOrder::AddressAscending {
+                audit[start..].sort_unstable_by_key(|(addr, _, _)| *addr);   // 書き込んだ順序で記録する
+            }
+        }
+        // lock した lock_ver ごとに 1 回だけ version を書き込む
+        // (lock_ver を共有するストライプごとに書き込むと、最初の書き込みで lock が外れた後に他のトランザクションの lock を上書きしうる)
+        for addr in self.locked.iter() {
+            let word = self.mem.lock_word(*addr);
+            self.mem.lock_ver[word].store(version, self.commit_ordering);  // version 更新
+        }
+        self.locked.clear();    // lock flag 解除
+        true
+    }
+}
+
+impl<'a, const S: usize> Drop for WriteTrans<'a, S> {
+    // locked に記録されたメモリのロックを解除
+    // lock を獲得するのは closure の実行後 (lock_write_set) なので、closure が panic した場合 locked は空である。
+    // lock_write_set の途中で失敗 (または panic) した場合も、unwind 時にこの drop が走り獲得済みの lock は必ず解放される。
+    fn drop(&mut self) {
+        for addr in self.locked.iter() {
+            self.mem.unlock_addr(*addr);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemoryError {
+    InvalidLength { expected: usize, actual: usize },     // 初期値の長さが MEM_SIZE と一致しない
+    InvalidBufferLength { len: usize, stripe_size: usize },     // バッファの長さがストライプの大きさの (正の) 倍数でない
+    InvalidCapacity { size: usize, stripe_size: usize },        // with_capacity の大きさが 2^n でないか、ストライプより小さい
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryError::InvalidLength { expected, actual } => {
+                write!(f, "initial memory length must be {} bytes, got {}", expected, actual)
+            }
+            MemoryError::InvalidBufferLength { len, stripe_size } => {
+                write!(f, "buffer length must be a positive multiple of {} bytes, got {}", stripe_size, len)
+            }
+            MemoryError::InvalidCapacity { size, stripe_size } => {
+                write!(f, "capacity must be a power of two of at least {} bytes, got {}", stripe_size, size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MemoryError {}
+
+// try_load の失敗理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    Conflict,       // 競合が発生した (トランザクションは retry される)
+    Uninitialized,  // strict_init が有効で、一度も値が commit されていないストライプを読み込んだ
+    Evicted,        // STM::read_at_version で、指定した version の値の記録が残っていない
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Conflict => write!(f, "transaction conflicted"),
+            LoadError::Uninitialized => write!(f, "load of a stripe that has never been stored"),
+            LoadError::Evicted => write!(f, "value at the requested version is no longer in the history"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+// 条件待ち (RetryOk) による再実行の前の待ち方
+// Spin: 常に spin する
+// SpinThenYield: spins 回までは spin し、それ以降は他スレッドに実行を譲る (既定は spins = 16)
+// Block: closure が読み込んだストライプのいずれかに commit されるまでスレッドを park する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitPolicy {
+    Spin,
+    SpinThenYield { spins: usize },
+    Block,
+}
+
+impl Default for WaitPolicy {
+    fn default() -> Self {
+        WaitPolicy::SpinThenYield { spins: SPIN_LIMIT }
+    }
+}
+
+// bit_index 番目の bit の (ストライプのアドレス, ストライプ内のバイト位置, マスク)
+// size はデータ本体の大きさ (バイト; with_capacity などで実行時に決まるため Memory::size() を渡す)
+fn bit_location<const S: usize>(bit_index: usize, size: usize) -> (usize, usize, u8) {
+    assert!(bit_index < size * 8, "bit index out of range");
+    let byte_addr = bit_index / 8;
+    (byte_addr & !(S - 1), byte_addr & (S - 1), 1 << (bit_index % 8))
+}
+
+// retry 前の待機を管理する
+// 待機は次の実行の直前 (= 前回の WriteTrans が drop されて lock が解放された後) に行う
+pub(crate) struct Backoff<'a> {
+    policy: &'a dyn RetryPolicy,
+    wait_policy: WaitPolicy,
+    conflicts: usize,           // 競合による retry の回数
+    waits: usize,               // 条件待ち (RetryOk) による再実行の回数
+    pending: Option<Pending>,
+}
+
+enum Pending {
+    Conflict,
+    Condition,
+}
+
+impl<'a> Backoff<'a> {
+    pub(crate) fn new(policy: &'a dyn RetryPolicy) -> Self {
+        Backoff { policy, wait_policy: WaitPolicy::default(), conflicts: 0, waits: 0, pending: None }
+    }
+
+    fn with_wait_policy(mut self, wait_policy: WaitPolicy) -> Self {
+        self.wait_policy = wait_policy;
+        self
+    }
+
+    pub(crate) fn conflict(&mut self) {
+        self.conflicts += 1;
+        self.pending = Some(Pending::Conflict);
+    }
+
+    pub(crate) fn condition(&mut self) {
+        self.waits += 1;
+        self.pending = Some(Pending::Condition);
+    }
+
+    // 前回の実行結果に応じて待機する; retry policy が諦めた場合は false
+    pub(crate) fn wait(&mut self) -> bool {
+        match self.pending.take() {
+            None => true,       // 初回の実行は待機しない
+            Some(Pending::Conflict) => match self.policy.delay(self.conflicts) {
+                Some(d) => {
+                    pause(self.conflicts, d);
+                    true
+                }
+                None => false,
+            },
+            Some(Pending::Condition) => {
+                match self.wait_policy {
+                    WaitPolicy::Spin => hint::spin_loop(),
+                    WaitPolicy::SpinThenYield { spins } if self.waits < spins => hint::spin_loop(),
+                    WaitPolicy::SpinThenYield { .. } => thread::yield_now(),
+                    WaitPolicy::Block => {}     // closure の実行直後に park 済み (STM::block_on)
+                }
+                true
+            }
+        }
+    }
+}
+
+// 待ち時間が 0 の場合は、最初の数回は spin し、それ以降は他スレッドに実行を譲る
+fn pause(n: usize, d: Duration) {
+    if !d.is_zero() {
+        thread::sleep(d);
+    } else if n < SPIN_LIMIT {
+        hint::spin_loop();
+    } else {
+        thread::yield_now();
+    }
+}
+
+// 1 つの書き込みトランザクションの、実行をまたいで引き継ぐ状態 (STM::attempt を参照)
+struct Attempts {
+    audit: bool,                    // write_transaction_audit の場合のみ、commit した書き込みを記録する
+    pinned: Option<u64>,            // write_transaction_at で固定した read_version
+    last_conflict: Option<usize>,
+    escalations: u64,
+    offenders: HashMap<usize, usize>,   // split_advisor 用: 検証に失敗したアドレスと回数
+    advised: bool,
+}
+
+impl Attempts {
+    fn new(audit: bool, pinned: Option<u64>) -> Self {
+        Attempts { audit, pinned, last_conflict: None, escalations: 0, offenders: HashMap::new(), advised: false }
+    }
+}
+
+// 書き込みトランザクションの 1 回の実行の結果
+enum Attempt<R> {
+    Committed(R, TxTiming, Vec<AuditEntry>),
+    Conflict,                       // 競合した: retry policy に従って再実行する
+    Condition { read_version: u64 },    // RetryOk: 呼び出し側が ctx の read_set のいずれかへの read_version 以降の commit を待って再実行する
+    GiveUp,                         // 再実行せずに None を返す (abort・poison・shutdown・固定した version の超過)
+}
+
+pub enum STMResult<T> {
+    Ok(T),
+    Retry,
+    RetryOk,    // 競合はないが条件が満たされていない: トランザクション全体を再実行する
+    Abort,      // retry せずに破棄する (lock の獲得も global_clock の更新も行わない; WriteTrans::request_abort を参照)
+}
+
+// commit したトランザクションの論理時刻
+// begin: 最後の (commit した) 実行の開始時に copy した global_clock の値 (read_version)
+// commit: commit 時に割り当てられた version。書き込みのないトランザクションは begin の時点で commit したものとし、commit == begin
+// commit - begin が大きいほど、実行中に他のトランザクションが多く commit している
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxTiming {
+    pub begin: u64,
+    pub commit: u64,
+    pub last_conflict: Option<usize>,   // 最後に競合で失敗した実行の競合したアドレス (競合しなかった場合は None)
+    pub escalations: u64,               // commit に進んだ実行の回数 (TxStats を参照)
+}
+
+// write_transaction_audit が返す、書き込んだストライプの (addr, 以前の version, 新しい version)
+pub type AuditEntry = (usize, u64, u64);
+
+// STM::apply の結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApplyOutcome {
+    Committed(u64),                     // 割り当てられた version
+    Conflict { addr: Option<usize> },   // 競合したアドレス (分かる場合)
+    Poisoned,                           // STM が poison されているため何も書き込まなかった
+    Aborted,                            // request_abort されていたため何も書き込まなかった (TxScope::finish のみ)
+    StaleVersion { current: u64 },      // 指定した version が global_clock (current) 以下のため何も書き込まなかった (apply_with_version のみ)
+}
+
+// STM が poison されているため、トランザクションを実行しなかったことを表す (STM::is_poisoned を参照)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Poisoned;
+
+impl fmt::Display for Poisoned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "STM is poisoned by a detected invariant violation")
+    }
+}
+
+impl std::error::Error for Poisoned {}
+
+// STM::shutdown の後に、条件が満たされるのを待つ (RetryOk を返した) トランザクションが待機をやめたことを表す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShuttingDown;
+
+impl fmt::Display for ShuttingDown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "STM is shutting down")
+    }
+}
+
+impl std::error::Error for ShuttingDown {}
+
+// STM::atomically で合成される、独立に定義されたトランザクションの操作
+pub type TxOp<const S: usize = STRIPE_SIZE> = Box<dyn Fn(&mut WriteTrans<'_, S>) -> STMResult<()>>;
+
+// 書き込みトランザクションの read_set / write_set / locked / scratch の確保先 (STM::write_transaction_in を参照)
+// トランザクションの間で内容は引き継がず (実行の開始時に空にする)、確保した容量だけを使い回す
+// 同じスレッドで大量のトランザクションを実行する場合に、トランザクションごとの heap の確保をなくす
+#[derive(Default)]
+pub struct TxContext<const S: usize = STRIPE_SIZE> {
+    read_set: ReadSet,
+    write_set: WriteSet<S>,
+    locked: Vec<usize>,
+    scratch: ScratchBuf,
+    read_hint: Option<u64>,     // 最初の実行の read_version (STM::write_transaction_hinted を参照; 実行の開始時に取り出す)
+}
+
+impl<const S: usize> TxContext<S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// WriteTrans::savepoint で記録した書き込みの状態
+pub(crate) struct Savepoint<const S: usize> {
+    write_set: WriteSet<S>,
+    resets: Vec<usize>,
+    spans: Vec<(usize, usize)>,
+    callbacks: usize,
+}
+
+// 独立に定義されたトランザクションの操作 (member) のまとまり。全ての member を 1 つの WriteTrans で順に実行する
+// atomically と異なり、いずれかの member が Ok 以外を返した場合は、それまでの member が stage した書き込みと
+// on_commit で登録した処理を全て破棄してから、その結果をまとまり全体の結果とする。
+// そのため、run_in の結果を呼び出し側で処理する (Retry の場合に別の操作を試す等) 場合も、失敗したまとまりの書き込みは残らない
+#[derive(Default)]
+pub struct TxGroup<const S: usize = STRIPE_SIZE> {
+    members: Vec<TxOp<S>>,
+}
+
+impl<const S: usize> TxGroup<S> {
+    pub fn new() -> Self {
+        TxGroup { members: Vec::new() }
+    }
+
+    pub fn member(mut self, op: impl Fn(&mut WriteTrans<'_, S>) -> STMResult<()> + 'static) -> Self {
+        self.members.push(Box::new(op));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    // 全ての member を tr で順に実行する (失敗した場合は tr を実行前の状態に戻す)
+    pub fn run_in(&self, tr: &mut WriteTrans<'_, S>) -> STMResult<()> {
+        let savepoint = tr.savepoint();
+        for member in self.members.iter() {
+            match member(tr) {
+                STMResult::Ok(()) if !tr.abort_requested => {}
+                STMResult::Ok(()) => return STMResult::Abort,   // request_abort: 書き込みは request_abort で破棄済み
+                outcome => {
+                    tr.rollback(savepoint);
+                    return outcome;
+                }
+            }
+        }
+        STMResult::Ok(())
+    }
+}
+
+// トランザクションの closure が panic したことを表す (STM::write_transaction_catch を参照)
+pub struct TxPanic {
+    payload: Box<dyn Any + Send>,
+}
+
+impl TxPanic {
+    // panic のメッセージ (panic! に文字列が渡された場合)
+    pub fn message(&self) -> Option<&str> {
+        if let Some(s) = self.payload.downcast_ref::<&str>() {
+            Some(s)
+        } else {
+            self.payload.downcast_ref::<String>().map(|s| s.as_str())
+        }
+    }
+
+    pub fn into_payload(self) -> Box<dyn Any + Send> {
+        self.payload
+    }
+}
+
+impl fmt::Debug for TxPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TxPanic").field("message", &self.message()).finish()
+    }
+}
+
+// 大きなトランザクションの分割の目安 (STM::split_advisor を参照)
+// offenders: commit 時の read_set の検証に失敗したアドレスと、その回数 (多い順; 上位のみ)
+// これらのストライプを読む部分を別のトランザクションに分けると、残りの部分の retry を減らせる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitAdvice {
+    pub write_set_len: usize,   // 最後に検証に失敗した実行の write_set の大きさ
+    pub aborts: usize,          // 検証に失敗した回数 (retry をまたいだ合計)
+    pub offenders: Vec<(usize, usize)>,
+}
+
+struct SplitAdvisor {
+    min_write_set: usize,
+    min_aborts: usize,
+    sender: Sender<SplitAdvice>,
+}
+
+// commit されたストライプの変更通知 (STM::subscribe を参照)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent<const S: usize = STRIPE_SIZE> {
+    pub addr: usize,
+    pub version: u64,                   // commit で割り当てられた version
+    pub bytes: [u8; S],       // commit された値
+}
+
+struct Subscriber<const S: usize> {
+    id: u64,                            // Subscription::id と同じ
+    addrs: HashSet<usize>,
+    sender: Sender<ChangeEvent<S>>,
+}
+
+pub struct STM<const S: usize = STRIPE_SIZE> {
+    pub(crate) mem: Memory<S>,
+    retry_policy: Box<dyn RetryPolicy>,
+    read_capacity: usize,               // read_set (ReadTrans では読み込みの cache) の初期容量
+    write_capacity: usize,              // write_set の初期容量
+    span_check: bool,                   // WriteTrans::check_span を参照
+    commit_ordering: Ordering,          // commit 時の version の公開に用いる ordering
+    commit_order: CommitOrder,          // commit 時にストライプを書き込む順序
+    lock_order: LockOrder,              // commit 時にストライプを lock する順序
+    strict_init: bool,                  // 未初期化のストライプの読み込みを失敗させるかどうか
+    subscribers: Mutex<Vec<Subscriber<S>>>,
+    num_subscribers: AtomicUsize,       // 購読者がいない場合に commit 時の Mutex を避けるため
+    next_subscriber_id: AtomicU64,      // Subscriber::id の払い出し用
+    waiters: Mutex<Vec<Waiter>>,        // WaitPolicy::Block で park しているスレッドと、RetryFuture の waker
+    num_waiters: AtomicUsize,           // num_subscribers と同様
+    advisor: Mutex<Option<SplitAdvisor>>,   // split_advisor で登録した場合のみ
+    has_advisor: AtomicBool,            // advisor が登録されていない場合に retry ごとの集計を避けるため
+    shutting_down: AtomicBool,          // STM::shutdown を参照
+    next_waiter_id: AtomicU64,          // Waiter::id の払い出し用
+    stats: Option<StatsCounters>,       // with_stats で有効にした場合のみ集計する
+    group_commit: Option<GroupCommit>,  // with_group_commit で有効にした場合のみ
+    speculation_limit: Option<Duration>,    // with_speculation_limit を参照
+    pub(crate) hasher: SetHasher,       // トランザクションごとの read_set / write_set に用いる
+}
+
+// write_transaction の集計 (STM::stats を参照)
+// escalations: 投機的実行を終えて lock の獲得 (commit) に進んだ回数。commit に成功した実行も 1 回と数える (書き込みのない実行は数えない)
+//              1 回のトランザクションでこの回数が多いほど、commit の段階で競合を繰り返している (分割の候補)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TxStats {
+    pub commits: u64,           // commit したトランザクションの数
+    pub gave_up: u64,           // retry policy が諦めたトランザクションの数
+    pub escalations: u64,       // 全トランザクションの escalation の合計
+    pub max_escalations: u64,   // 1 回のトランザクションの escalation の最大値
+    pub conflict_aborts: u64,   // 競合により retry した実行の数
+    pub slow_aborts: u64,       // 投機的実行が speculation limit を超えたため retry した実行の数
+    pub read_hints: u64,        // ReadHint を read_version として、global_clock の読み込みを省略した実行の数
+    pub stale_hints: u64,       // そのうち競合により retry した実行の数 (ReadHint が古かった)
+}
+
+impl TxStats {
+    pub fn aborts(&self, reason: AbortReason) -> u64 {
+        match reason {
+            AbortReason::Conflict => self.conflict_aborts,
+            AbortReason::Slow => self.slow_aborts,
+        }
+    }
+}
+
+// STM::write_transaction_hinted に渡す、最初の実行の read_version (STM::read_hint で取得する)
+// global_clock は単調増加するため、取得した時点の version は以降も常に現在の global_clock 以下であり、
+// global_clock を読み直して検査しなくても read_version として用いてよい。古くなっていた場合は、
+// その後に commit されたストライプの読み込みが競合となり、次の実行は通常どおり global_clock を読む
+#[derive(Clone, Copy)]
+pub struct ReadHint<'a, const S: usize = STRIPE_SIZE> {
+    stm: &'a STM<S>,    // 他の STM の version を read_version として用いないため
+    version: u64,
+}
+
+impl<'a, const S: usize> ReadHint<'a, S> {
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+// write_transaction の実行を commit せずに retry した理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbortReason {
+    Conflict,   // 読み込み・lock の獲得・検証のいずれかで競合した
+    Slow,       // closure の実行が STM::with_speculation_limit の時間を超えた
+}
+
+struct StatsCounters {
+    commits: AtomicU64,
+    gave_up: AtomicU64,
+    escalations: AtomicU64,
+    max_escalations: AtomicU64,
+    conflict_aborts: AtomicU64,
+    slow_aborts: AtomicU64,
+    read_hints: AtomicU64,
+    stale_hints: AtomicU64,
+}
+
+// WaitPolicy::Block で park しているスレッド (または Pending を返した RetryFuture) と、
+// その再実行のきっかけとなるアドレス (直前の実行の read_set)
+struct Waiter {
+    id: u64,
+    addrs: Vec<usize>,
+    wake: WakeTarget,
+}
+
+enum WakeTarget {
+    Thread(Thread),     // unpark する
+    Task(Waker),        // wake して executor に再度 poll させる
+}
+
+// ストライプの大きさが既定 (STRIPE_SIZE) の STM の作成
+// (式の中の STM::new() で S を推論させずに済むよう、既定の大きさの場合のみ new / from_bytes / builder を提供する)
+impl STM {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self::new_sized()
+    }
+
+    // 1 つのスレッドからのみ用いる STM を作成する (with_single_threaded を参照)
+    pub fn single_threaded() -> Self {
+        Self::new().with_single_threaded()
+    }
+
+    // 初期値を与えて STM を作成 (Memory::from_bytes を参照)
+    pub fn from_bytes(initial: Vec<u8>) -> Result<Self, MemoryError> {
+        Self::from_bytes_sized(initial)
+    }
+
+    // 設定をまとめて与えて STM を作成する (StmBuilder を参照; 他の大きさの場合は StmBuilder::<S>::default())
+    pub fn builder() -> StmBuilder {
+        StmBuilder::default()
+    }
+}
+
+impl<const S: usize> STM<S> {
+    // ストライプの大きさを指定して作成する (STM::<16>::new_sized() など)
+    pub fn new_sized() -> Self {
+        Self::from_memory(Memory::new())
+    }
+
+    pub fn from_bytes_sized(initial: Vec<u8>) -> Result<Self, MemoryError> {
+        Ok(Self::from_memory(Memory::from_bytes(initial)?))
+    }
+
+    // 作成済みの Memory (Memory::from_mut_slice など) の上に STM を作成する
+    pub fn from_memory(mem: Memory<S>) -> Self {
+        STM {
+            mem,
+            retry_policy: Box::new(Immediate),
+            read_capacity: 0,
+            write_capacity: 0,
+            span_check: false,
+            commit_ordering: Relaxed,
+            commit_order: CommitOrder::default(),
+            lock_order: LockOrder::default(),
+            strict_init: false,
+            subscribers: Mutex::new(Vec::new()),
+            num_subscribers: AtomicUsize::new(0),
+            next_subscriber_id: AtomicU64::new(0),
+            waiters: Mutex::new(Vec::new()),
+            num_waiters: AtomicUsize::new(0),
+            advisor: Mutex::new(None),
+            has_advisor: AtomicBool::new(false),
+            shutting_down: AtomicBool::new(false),
+            next_waiter_id: AtomicU64::new(0),
+            stats: None,
+            group_commit: None,
+            speculation_limit: None,
+            hasher: SetHasher::default(),
+        }
+    }
+
+    // 競合による retry の待機方針を設定する (retry::RetryPolicy を参照)
+    pub fn with_retry_policy<P: RetryPolicy + 'static>(mut self, policy: P) -> Self {
+        self.retry_policy = Box::new(policy);
+        self
+    }
+
+    // 作成時にメモリの全ページに触れておく (Memory::prefault を参照)
+    pub fn with_prefault(self, prefault: bool) -> Self {
+        if prefault {
+            self.mem.prefault();
+        }
+        self
+    }
+
+    // 各ストライプに最後に commit したスレッドを記録する (Memory::with_last_writer を参照)
+    pub fn with_last_writer(mut self) -> Self {
+        self.mem = self.mem.with_last_writer();
+        self
+    }
+
+    // 各ストライプを読み込んだ回数を数える (Memory::with_read_heat を参照)
+    pub fn with_read_heat(mut self) -> Self {
+        self.mem = self.mem.with_read_heat();
+        self
+    }
+
+    // 読み込まれたストライプと回数 (Memory::read_heat を参照)
+    pub fn read_heat(&self) -> Vec<(usize, u64)> {
+        self.mem.read_heat()
+    }
+
+    // 各ストライプの直近 len 回の commit の値を保持する (Memory::with_history, read_at_version を参照)
+    pub fn with_history(mut self, len: usize) -> Self {
+        self.mem = self.mem.with_history(len);
+        self
+    }
+
+    // 同時にアクセスされるアドレスを物理的に隣接させる (Memory::with_layout を参照)
+    pub fn with_layout(mut self, hint: LayoutHint) -> Self {
+        self.mem = self.mem.with_layout(hint);
+        self
+    }
+
+    // 1 つのスレッドからのみ用いる (決定的な再実行やテストなど) ことを前提に、ReadTrans / WriteTrans の load で
+    // copy 後の検査 (fence(SeqCst) と lock_ver の再読み込み) を省略する。copy の前の検査は残るため、
+    // 同じスレッドで入れ子に commit したトランザクションとの競合は従来どおり検出する。
+    // 複数のスレッドから並行に用いると不整合な値を読みうる (デバッグビルドでは、最初に load したスレッド以外からの load は panic する)
+    pub fn with_single_threaded(mut self) -> Self {
+        self.mem.single_threaded = true;
+        self
+    }
+
+    // region ごとの競合を監視し、merge_cold_regions で競合しない region の lock_ver をまとめる (Memory::with_adaptive_striping を参照)
+    pub fn with_adaptive_striping(mut self, region_len: usize) -> Self {
+        self.mem = self.mem.with_adaptive_striping(region_len);
+        self
+    }
+
+    // Memory::merge_cold_regions を参照 (&mut self のため、実行中のトランザクションはない)
+    pub fn merge_cold_regions(&mut self) -> usize {
+        self.mem.merge_cold_regions()
+    }
+
+    // 対象アドレスのストライプに最後に commit したスレッドの writer_id (Memory::last_writer を参照)
+    pub fn last_writer(&self, addr: usize) -> u64 {
+        self.mem.last_writer(addr)
+    }
+
+    // write_transaction の集計を有効にする (トランザクションの終了ごとに共有カウンタへの書き込みが入る)
+    pub fn with_stats(mut self) -> Self {
+        self.stats = Some(StatsCounters {
+            commits: AtomicU64::new(0),
+            gave_up: AtomicU64::new(0),
+            escalations: AtomicU64::new(0),
+            max_escalations: AtomicU64::new(0),
+            conflict_aborts: AtomicU64::new(0),
+            slow_aborts: AtomicU64::new(0),
+            read_hints: AtomicU64::new(0),
+            stale_hints: AtomicU64::new(0),
+        });
+        self
+    }
+
+    // with_stats で有効にした場合、これまでの集計を返す (各値は個別に読むため、並行に更新されている間は互いに一貫しない)
+    pub fn stats(&self) -> Option<TxStats> {
+        self.stats.as_ref().map(|stats| TxStats {
+            commits: stats.commits.load(Relaxed),
+            gave_up: stats.gave_up.load(Relaxed),
+            escalations: stats.escalations.load(Relaxed),
+            max_escalations: stats.max_escalations.load(Relaxed),
+            conflict_aborts: stats.conflict_aborts.load(Relaxed),
+            slow_aborts: stats.slow_aborts.load(Relaxed),
+            read_hints: stats.read_hints.load(Relaxed),
+            stale_hints: stats.stale_hints.load(Relaxed),
+        })
+    }
+
+    // 競合による retry を集計し、競合したアドレスを Memory::with_adaptive_striping の監視に記録する
+    fn record_conflict(&self, addr: Option<usize>) {
+        self.record_abort(AbortReason::Conflict);
+        if let Some(addr) = addr {
+            self.mem.record_contention(addr);
+        }
+    }
+
+    fn record_abort(&self, reason: AbortReason) {
+        if let Some(stats) = &self.stats {
+            match reason {
+                AbortReason::Conflict => stats.conflict_aborts.fetch_add(1, Relaxed),
+                AbortReason::Slow => stats.slow_aborts.fetch_add(1, Relaxed),
+            };
+        }
+    }
+
+    fn record_hint(&self, stale: bool) {
+        if let Some(stats) = &self.stats {
+            let counter = if stale { &stats.stale_hints } else { &stats.read_hints };
+            counter.fetch_add(1, Relaxed);
+        }
+    }
+
+    fn record_stats(&self, escalations: u64, committed: bool) {
+        if let Some(stats) = &self.stats {
+            if committed {
+                stats.commits.fetch_add(1, Relaxed);
+            } else {
+                stats.gave_up.fetch_add(1, Relaxed);
+            }
+            stats.escalations.fetch_add(escalations, Relaxed);
+            stats.max_escalations.fetch_max(escalations, Relaxed);
+        }
+    }
+
+    // トランザクションを用いずにストライプの現在のバイト列を copy する (監視表示など、おおよその値で十分な場合用)
+    // version の検査も retry も行わないため linearizable ではない: commit 途中の (ストライプ内で新旧の混ざった) 値を返しうる
+    // 各バイトは atomic に読むため、並行に書き込まれていても未定義動作にはならない
+    pub fn read_raw(&self, addr: usize) -> [u8; S] {
+        assert_eq!(addr & (S - 1), 0);
+        let val = self.mem.read_stripe(addr);
+        fence(Acquire);
+        val
+    }
+
+    // addr のストライプの flag を立てる (トランザクションを用いない通知; ドアベル)
+    // flag はストライプのデータ本体とは別の atomic で、トランザクションの読み書きとは干渉しない。
+    // Release で書き込むため、poll_flag で立ったことを観測したスレッドからは、
+    // publish_flag までにこのスレッドが commit したトランザクションの書き込みが全て見える
+    pub fn publish_flag(&self, addr: usize) {
+        assert_eq!(addr & (S - 1), 0);
+        self.mem.flags[addr >> self.mem.shift_size].store(true, Release);
+    }
+
+    // addr のストライプの flag が立っているかどうか (Acquire で読む; 待機はしない)
+    pub fn poll_flag(&self, addr: usize) -> bool {
+        assert_eq!(addr & (S - 1), 0);
+        self.mem.flags[addr >> self.mem.shift_size].load(Acquire)
+    }
+
+    // addr のストライプの flag を下ろす (立っていたかどうかを返す)
+    pub fn clear_flag(&self, addr: usize) -> bool {
+        assert_eq!(addr & (S - 1), 0);
+        self.mem.flags[addr >> self.mem.shift_size].swap(false, AcqRel)
+    }
+
+    // addr のストライプを現在いずれかの writer が lock しているかどうか (locked_stripes の 1 アドレス版; 軽量な監視用)
+    // lock_ver を 1 回 Acquire で読むだけなので、返した時点で既に解放・獲得されているかもしれない (目安としてのみ用いる)
+    // with_adaptive_striping で lock_ver を共有している場合は、同じ lock_ver の他のストライプの lock でも true になる
+    pub fn is_locked(&self, addr: usize) -> bool {
+        assert_eq!(addr & (S - 1), 0);
+        self.mem.lock_ver[self.mem.lock_word(addr)].load(Acquire) & (1 << 63) != 0
+    }
+
+    // 現在 lock されているストライプのアドレス (Memory::locked_stripes を参照)
+    pub fn locked_stripes(&self) -> Vec<usize> {
+        self.mem.locked_stripes()
+    }
+
+    // 内部の不変条件の違反 (保持しているはずの lock が外れていた等) を検出したかどうか
+    // poison された STM では、以降のトランザクションは何もせずに None を返す (try_read_transaction / try_write_transaction は Err(Poisoned))
+    // (Mutex の poison と同様に、壊れているかもしれない状態を黙って使い続けないための仕組み)
+    pub fn is_poisoned(&self) -> bool {
+        self.mem.is_poisoned()
+    }
+
+    // STM を poison する (呼び出し側が自身の不変条件の違反を検出した場合など)
+    pub fn poison(&self) {
+        self.mem.poison();
+    }
+
+    // 状態を検査・修復した後に、再びトランザクションを実行できるようにする
+    pub fn clear_poison(&self) {
+        self.mem.poisoned.store(false, Relaxed);
+    }
+
+    // 終了処理: 以降、closure が RetryOk を返したトランザクションは待機せずに None を返し
+    // (try_retry_until は Err(ShuttingDown))、park している (WaitPolicy::Block / RetryFuture の) 待機を全て起こす。
+    // 通常の commit や競合による retry は影響を受けないため、終了前の最後の書き込みはそのまま行える
+    pub fn shutdown(&self) {
+        self.shutting_down.store(true, SeqCst);
+        // flag を立ててから waiters を読むため、ここで起こされない待機は登録後に flag を観測して park しない (block_on を参照)
+        let waiters = self.waiters.lock().unwrap();
+        for w in waiters.iter() {
+            match &w.wake {
+                WakeTarget::Thread(thread) => thread.unpark(),
+                WakeTarget::Task(waker) => waker.wake_by_ref(),
+            }
+        }
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(SeqCst)
+    }
+
+    // 現在の global_clock の値 (最後に割り当てられた version)
+    pub fn global_version(&self) -> u64 {
+        self.mem.global_clock.load(Acquire)
+    }
+
+    // 全ストライプの現在の version (lock bit を除く) を返す
+    // 各ストライプを順に読むだけなので、並行に commit されている間は一貫した断面にならない。
+    // 他のトランザクションが実行されていない (静止している) ときに呼ぶこと。
+    pub fn version_vector(&self) -> Vec<u64> {
+        self.mem.version_vector()
+    }
+
+    // データ本体の全ストライプのバイト列の hash (FNV-1a; 実行・プラットフォームによらず同じ値になる)
+    // 最終状態が同じになるはずの 2 つの実行を比較する用途。1 つの読み込みトランザクションで読むため、
+    // 並行に commit されていても一貫したスナップショットの値となる (strict_init で未初期化のストライプは 0 として扱う)
+    // version は含まない (同じ値でも commit の回数によって version は異なる; version_vector を参照)
+    pub fn heap_checksum(&self) -> Option<u64> {
+        self.read_transaction(|tr| {
+            let mut hash = 0xcbf2_9ce4_8422_2325u64;
+            for addr in (0..self.mem.size()).step_by(S) {
+                let val = match tr.try_load(addr) {
+                    Ok(val) => val,
+                    Err(LoadError::Uninitialized) => [0; S],
+                    Err(_) => return STMResult::Retry,
+                };
+                for b in val {
+                    hash = (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3);
+                }
+            }
+            STMResult::Ok(hash)
+        })
+    }
+
+    // lock_ver の個数 (Memory::merge_cold_regions を参照)
+    pub fn lock_words(&self) -> usize {
+        self.mem.lock_words()
+    }
+
+    // トランザクションごとに作成する read_set / write_set のハッシュ関数を設定する (SetHasher を参照)
+    pub fn with_hasher(mut self, hasher: SetHasher) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    // トランザクションごとに作成する read_set / write_set の初期容量を設定する
+    // 1 回のトランザクションで触れるアドレス数が予測できる場合、closure の実行中の再確保を避けられる
+    pub fn with_set_capacity(mut self, read_capacity: usize, write_capacity: usize) -> Self {
+        self.read_capacity = read_capacity;
+        self.write_capacity = write_capacity;
+        self
+    }
+
+    // write transaction の closure の実行 (WriteTrans の作成から commit の試行まで) に limit より長くかかった場合、
+    // commit を試みずに競合と同様に retry する (retry policy に従って待機し、集計では AbortReason::Slow と数える)
+    // 実行が長いほど read_set が他の commit に無効化されやすいため、重すぎる closure を見つけるために用いる
+    // closure が常に limit より遅い場合は commit されない (retry policy が諦めるまで retry し続ける)
+    pub fn with_speculation_limit(mut self, limit: Duration) -> Self {
+        self.speculation_limit = Some(limit);
+        self
+    }
+
+    // commit の準備ができた write transaction を window の間まとめ、global_clock の更新を 1 回で済ませる (group_commit を参照)
+    // 書き込みの競合が激しい場合に global_clock への書き込みを減らす代わりに、各 commit が最大で window だけ遅れる
+    // 複数のシャードにまたがる ShardedSTM の commit と、DeterministicSTM::with_split_commit の commit には適用されない
+    pub fn with_group_commit(mut self, window: Duration) -> Self {
+        self.group_commit = Some(GroupCommit::new(window));
+        self
+    }
+
+    // 同じトランザクション内で、複数ストライプにまたがる書き込み (store_bytes, copy_within) の一部だけを
+    // 後から上書きした場合に panic させる (デバッグ用; 書き込みのたびに範囲の検査が入る)
+    pub fn with_span_check(mut self, span_check: bool) -> Self {
+        self.span_check = span_check;
+        self
+    }
+
+    // 一度も値が commit されていないストライプの load を失敗させる (デバッグ用; 既定では 0 が読み込まれる)
+    // load は None を、try_load は Err(LoadError::Uninitialized) を返す。from_bytes で作成した場合は全ストライプが初期化済み
+    pub fn with_strict_init(mut self, strict_init: bool) -> Self {
+        self.strict_init = strict_init;
+        self
+    }
+
+    // commit 時に version を公開する store の ordering を設定する (既定は Relaxed + 直前の fence(Release))
+    // fence(Release) はそのまま残るため、Release / SeqCst は正しさに必要ない。ordering に起因するバグの調査用の設定
+    // SeqCst は x86 では store ごとに xchg (full barrier) となり、書き込むストライプ数に比例して commit が遅くなる
+    pub fn with_commit_ordering(mut self, ordering: Ordering) -> Self {
+        assert!(matches!(ordering, Relaxed | Release | SeqCst), "commit ordering must be Relaxed, Release or SeqCst");
+        self.commit_ordering = ordering;
+        self
+    }
+
+    // commit 時に各ストライプのデータを書き込み、version を公開する順序を設定する (CommitOrder を参照)
+    pub fn with_commit_order(mut self, order: CommitOrder) -> Self {
+        self.commit_order = order;
+        self
+    }
+
+    // commit 時に write_set のストライプを lock する順序を設定する (LockOrder を参照)
+    pub fn with_lock_order(mut self, order: LockOrder) -> Self {
+        self.lock_order = order;
+        self
+    }
+
+    pub fn lock_order(&self) -> LockOrder {
+        self.lock_order
+    }
+
+    // commit 時に addr の lock を獲得できなかった回数 (LockOrder::ContentionDescending の順序に用いる)
+    pub fn lock_conflicts(&self, addr: usize) -> u64 {
+        self.mem.lock_conflicts(addr)
+    }
+
+    // addrs のいずれかのストライプに commit されるたびに ChangeEvent を受け取る
+    // subscribe から戻った後に開始した commit が通知の対象となる。Subscription を drop すると購読は解除され、
+    // 以降の commit は (監視していたストライプに書き込んでも) 通知を試みない
+    pub fn subscribe(&self, addrs: &[usize]) -> Subscription<'_, S> {
+        for addr in addrs {
+            assert_eq!(addr & (S - 1), 0);
+        }
+        let (sender, receiver) = channel();
+        let id = self.next_subscriber_id.fetch_add(1, Relaxed);
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.push(Subscriber { id, addrs: addrs.iter().copied().collect(), sender });
+        self.num_subscribers.store(subscribers.len(), Release);
+        Subscription { stm: self, id, receiver }
+    }
+
+    // 登録されている購読の数
+    pub fn subscriptions(&self) -> usize {
+        self.num_subscribers.load(Acquire)
+    }
+
+    fn unsubscribe(&self, id: u64) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|sub| sub.id != id);
+        self.num_subscribers.store(subscribers.len(), Release);
+    }
+
+    // 書き込むストライプが min_write_set 個以上のトランザクションが、commit 時の read_set の検証に min_aborts 回失敗した時点で
+    // (retry を含む 1 回の write_transaction につき 1 度だけ)、検証に失敗したアドレスの集計 (SplitAdvice) を受け取る
+    // 集計は同じ write_transaction の retry をまたいで行う。動作は変えない診断用の機能で、トランザクションを分割する目安とする
+    // 登録できる advisor は 1 つで、再び呼ぶと以前の Receiver は受け取らなくなる。Receiver を drop すると登録は解除される
+    pub fn split_advisor(&self, min_write_set: usize, min_aborts: usize) -> Receiver<SplitAdvice> {
+        assert!(min_aborts > 0);
+        let (sender, receiver) = channel();
+        *self.advisor.lock().unwrap() = Some(SplitAdvisor { min_write_set, min_aborts, sender });
+        self.has_advisor.store(true, Release);
+        receiver
+    }
+
+    // offenders (アドレスごとの検証の失敗回数) が advisor の条件を満たしていれば SplitAdvice を送る (送った場合は true)
+    fn advise_split(&self, write_set_len: usize, offenders: &HashMap<usize, usize>) -> bool {
+        let mut advisor = self.advisor.lock().unwrap();
+        let Some(config) = advisor.as_ref() else {
+            return false;
+        };
+        let aborts: usize = offenders.values().sum();
+        if write_set_len < config.min_write_set || aborts < config.min_aborts {
+            return false;
+        }
+        let mut ranked: Vec<(usize, usize)> = offenders.iter().map(|(addr, n)| (*addr, *n)).collect();
+        ranked.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked.truncate(SPLIT_ADVICE_TOP);
+        if config.sender.send(SplitAdvice { write_set_len, aborts, offenders: ranked }).is_err() {
+            *advisor = None;    // Receiver が drop されている -> 登録解除
+            self.has_advisor.store(false, Release);
+        }
+        true
+    }
+
+    // commit した write_set を購読者に通知する
+    // commit が version を公開 (= lock を解放) した後に呼ぶこと
+    pub(crate) fn notify(&self, write_set: &WriteSet<S>, version: u64) {
+        if self.num_subscribers.load(Acquire) == 0 {
+            return;
+        }
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|sub| {
+            for (addr, bytes) in write_set.iter() {
+                if sub.addrs.contains(addr) {
+                    let event = ChangeEvent { addr: *addr, version, bytes: *bytes };
+                    if sub.sender.send(event).is_err() {
+                        return false;       // Receiver が (Subscription の drop より前に) 破棄されている -> 購読解除
+                    }
+                }
+            }
+            true
+        });
+        self.num_subscribers.store(subscribers.len(), Release);
+    }
+
+    // write_trans の read_set のいずれかのストライプに commit されるまで park する (WaitPolicy::Block)
+    // 登録後に version を再検査するため、登録前に commit されていれば park しない。
+    // commit 側は version の公開後に num_waiters を読むため (いずれも SeqCst fence の後)、どちらかが必ず相手を観測する
+    fn block_on(&self, read_set: &ReadSet, read_version: u64) {
+        let addrs: Vec<usize> = read_set.iter().copied().collect();
+        if addrs.is_empty() {
+            thread::yield_now();    // 待機するきっかけがない
+            return;
+        }
+
+        let id = self.next_waiter_id.fetch_add(1, Relaxed);
+        // shutdown は flag を立ててから waiters を読むため、登録した後に flag を確かめれば起こされないまま park することはない
+        if self.register_waiter(id, &addrs, WakeTarget::Thread(thread::current()), read_version) && !self.is_shutting_down() {
+            thread::park();     // spurious wakeup の場合も再実行して条件を確かめるだけなので問題ない
+        }
+        self.unregister_waiter(id);
+    }
+
+    // id の待機を (既に登録されていれば置き換えて) 登録し、addrs がいずれも read_version 以降に commit されていないかを返す
+    // true であれば、以降の commit で wake されるまで待機してよい
+    fn register_waiter(&self, id: u64, addrs: &[usize], wake: WakeTarget, read_version: u64) -> bool {
+        {
+            let mut waiters = self.waiters.lock().unwrap();
+            waiters.retain(|w| w.id != id);
+            waiters.push(Waiter { id, addrs: addrs.to_vec(), wake });
+            self.num_waiters.store(waiters.len(), SeqCst);
+        }
+        fence(SeqCst);
+        addrs.iter().all(|addr| self.mem.test_not_modify(*addr, read_version))
+    }
+
+    fn unregister_waiter(&self, id: u64) {
+        let mut waiters = self.waiters.lock().unwrap();
+        waiters.retain(|w| w.id != id);
+        self.num_waiters.store(waiters.len(), SeqCst);
+    }
+
+    // commit したストライプを待っているスレッドを起こす (version の公開後に呼ぶ)
+    pub(crate) fn wake_waiters(&self, write_set: &WriteSet<S>) {
+        fence(SeqCst);
+        if self.num_waiters.load(Relaxed) == 0 {
+            return;
+        }
+        let waiters = self.waiters.lock().unwrap();
+        for w in waiters.iter() {
+            if w.addrs.iter().any(|addr| write_set.contains_key(addr)) {
+                match &w.wake {
+                    WakeTarget::Thread(thread) => thread.unpark(),
+                    WakeTarget::Task(waker) => waker.wake_by_ref(),
+                }
+            }
+        }
+    }
+
+    // std::thread::scope の中で、この STM を Arc なしで共有するスレッドを起動する
+    // scope を抜ける時点で起動したスレッドはすべて join 済み
+    pub fn scope<'env, F, T>(&'env self, f: F) -> T
+    where F: for<'scope> FnOnce(&StmScope<'scope, 'env, S>) -> T {
+        thread::scope(|scope| f(&StmScope { stm: self, scope }))
+    }
+
+    pub fn read_transaction<F, R>(&self, f: F) -> Option<R> 
+    where F: Fn(&mut ReadTrans<'_, S>) -> STMResult<R> {
+        self.read_transaction_versioned(f).map(|(result, _)| result)
+    }
+
+    // read_transaction と同様だが、STM が poison されている (または実行中に poison された) 場合は Err(Poisoned) を返す
+    pub fn try_read_transaction<F, R>(&self, f: F) -> Result<Option<R>, Poisoned>
+    where F: Fn(&mut ReadTrans<'_, S>) -> STMResult<R> {
+        self.check_poison(self.read_transaction(f))
+    }
+
+    fn check_poison<R>(&self, result: Option<R>) -> Result<Option<R>, Poisoned> {
+        match result {
+            None if self.is_poisoned() => Err(Poisoned),
+            result => Ok(result),
+        }
+    }
+
+    // 一貫性のレベルを指定して読み込みトランザクションを実行する (各レベルで失われる保証は ReadConsistency を参照)
+    pub fn read_transaction_with<F, R>(&self, consistency: ReadConsistency, f: F) -> Option<R>
+    where F: Fn(&mut ReadTrans<'_, S>) -> STMResult<R> {
+        self.read_transaction_at(consistency, f).map(|(result, _)| result)
+    }
+
+    // read_transaction と同様だが、読み込んだスナップショットの version (read_version) も返す
+    fn read_transaction_versioned<F, R>(&self, f: F) -> Option<(R, u64)>
+    where F: Fn(&mut ReadTrans<'_, S>) -> STMResult<R> {
+        self.read_transaction_at(ReadConsistency::Linearizable, f)
+    }
+
+    // version の時点の値を読む読み込みトランザクション (with_history を参照)
+    // 各 load は、そのストライプに version 以下で最後に commit された値を返す。記録が残っていない場合の load は
+    // None (try_load は Err(LoadError::Evicted)) を返す。version が現在の global_version より大きい場合は None を返す
+    pub fn read_at_version<F, R>(&self, version: u64, f: F) -> Option<R>
+    where F: Fn(&mut ReadTrans<'_, S>) -> STMResult<R> {
+        if version > self.global_version() {
+            return None;    // 割り当て済みの version でなければ、これから commit される値が定まっていない
+        }
+        self.read_transaction_from(ReadConsistency::Linearizable, Some(version), f).map(|(result, _)| result)
+    }
+
+    fn read_transaction_at<F, R>(&self, consistency: ReadConsistency, f: F) -> Option<(R, u64)>
+    where F: Fn(&mut ReadTrans<'_, S>) -> STMResult<R> {
+        self.read_transaction_from(consistency, None, f)
+    }
+
+    // version が Some の場合は、その version の時点の値を history から読む
+    fn read_transaction_from<F, R>(&self, consistency: ReadConsistency, version: Option<u64>, f: F) -> Option<(R, u64)>
+    where F: Fn(&mut ReadTrans<'_, S>) -> STMResult<R> {
+        let mut backoff = Backoff::new(&*self.retry_policy);
+        let mut scratch = ScratchBuf::default();
+        loop {
+            if !backoff.wait() {    // 競合による retry の場合は待機する
+                return None;        // retry policy が諦めた
+            }
+            if self.is_poisoned() {
+                return None;
+            }
+            let mut read_trans = self.begin_read()
+                .with_consistency(consistency)
+                .with_scratch(mem::take(&mut scratch));
+            if let Some(version) = version {
+                read_trans = read_trans.at_version(version);
+            }
+
+            // 投機的実行
+            let outcome = f(&mut read_trans);
+            scratch = mem::take(&mut read_trans.scratch);   // 次の実行に容量を引き継ぐ
+            match outcome {
+                STMResult::Abort => return None,
+                STMResult::RetryOk => {
+                    backoff.condition();    // 条件が満たされるまで再実行
+                    continue;
+                }
+                STMResult::Retry => {
+                    if read_trans.conflict {
+                        backoff.conflict();
+                        continue;       // retry
+                    } else {
+                        return None;
+                    }
+                },
+                STMResult::Ok(val) => {
+                    if read_trans.conflict {
+                        backoff.conflict();
+                        continue;
+                    } else {
+                        return Some((val, read_trans.read_version));
+                    }
+                }
+            }
+        }
+    }
+
+    // addrs の各ストライプを 1 つの一貫したスナップショットとして読み込み、その借用を f に渡す
+    // スナップショットは呼び出し側が用意した buffer (スタック上の配列など) に copy され、全てのアドレスを読み終えた後に
+    // 再検証してから f を呼ぶため、f の実行中に他のトランザクションが commit してもスナップショットは変化せず、f は 1 回だけ呼ばれる
+    // buffer の長さは addrs の長さ以上でなければならない (データ本体の大きさは実行時に決まるため、内部に固定長の buffer は持たない)
+    pub fn with_read_snapshot<F, R>(&self, addrs: &[usize], buffer: &mut [[u8; S]], f: F) -> Option<R>
+    where F: FnOnce(&[[u8; S]]) -> R {
+        assert!(addrs.len() <= buffer.len(), "snapshot buffer is shorter than addrs");
+        assert!(addrs.iter().all(|addr| addr & (S - 1) == 0));
+
+        let snapshot = &mut buffer[..addrs.len()];
+        let mut backoff = Backoff::new(&*self.retry_policy);
+        'retry: loop {
+            if !backoff.wait() {
+                return None;
+            }
+            let read_version = self.mem.global_clock.load(Acquire);
+
+            // メモリコピー (copy の前に lock されておらず read_version 以下であることを検査)
+            for (addr, buf) in addrs.iter().zip(snapshot.iter_mut()) {
+                if !self.mem.test_not_modify(*addr, read_version) {
+                    backoff.conflict();
+                    continue 'retry;
+                }
+                fence(Acquire);
+                *buf = self.mem.read_stripe(*addr);
+            }
+
+            fence(SeqCst);
+            // copy の後にまとめて再検証
+            if !addrs.iter().all(|addr| self.mem.test_not_modify(*addr, read_version)) {
+                backoff.conflict();
+                continue;
+            }
+            return Some(f(snapshot));
+        }
+    }
+
+    // 観測する version が後戻りしない読み込み用のハンドルを作成
+    pub fn monotonic_reader(&self) -> MonotonicReader<'_, S> {
+        MonotonicReader { stm: self, last: AtomicU64::new(0) }
+    }
+
+    // closure を用いずに 1 回分の書き込みトランザクションを組み立てる (TxScope を参照)
+    pub fn scope_write(&self) -> TxScope<'_, S> {
+        TxScope { stm: self, trans: self.begin_write() }
+    }
+
+    // f を TL2 の手順ごとに 1 ステップずつ実行する (stepper::SteppableTransaction を参照)
+    pub fn steppable<F, R>(&self, f: F) -> SteppableTransaction<'_, F, R, S>
+    where F: FnOnce(&mut WriteTrans<'_, S>) -> STMResult<R> {
+        SteppableTransaction::new(self, f)
+    }
+
+    // STM の設定を反映した ReadTrans を作成する (一貫性のレベルは Linearizable)
+    pub(crate) fn begin_read(&self) -> ReadTrans<'_, S> {
+        ReadTrans::new(&self.mem, self.read_capacity, self.hasher.clone())
+            .with_strict_init(self.strict_init)
+    }
+
+    // STM の設定を反映した WriteTrans を作成する (retry のループの外で 1 回だけ実行する場合に用いる)
+    pub(crate) fn begin_write(&self) -> WriteTrans<'_, S> {
+        self.begin_write_at(self.mem.global_clock.load(Acquire))   // global_clock を copy
+    }
+
+    // read_version を与えて作成する (WriteTrans::new_at を参照)
+    fn begin_write_at(&self, read_version: u64) -> WriteTrans<'_, S> {
+        WriteTrans::new_at(&self.mem, self.read_capacity, self.write_capacity, self.hasher.clone(), read_version)
+            .with_span_check(self.span_check)
+            .with_commit_ordering(self.commit_ordering)
+            .with_commit_order(self.commit_order)
+            .with_lock_order(self.lock_order)
+            .with_strict_init(self.strict_init)
+    }
+
+    pub fn write_transaction<F, R>(&self, f: F) -> Option<R>
+    where F: Fn(&mut WriteTrans<'_, S>) -> STMResult<R> {
+        self.write_transaction_versioned(f).map(|(result, _)| result)
+    }
+
+    // write_transaction と同様だが、read_set / write_set などを ctx に確保し、終了後も ctx に残す (TxContext を参照)
+    // 同じ ctx を次の write_transaction_in に渡せば、それらの確保をトランザクションごとに行わずに済む
+    pub fn write_transaction_in<F, R>(&self, ctx: &mut TxContext<S>, f: F) -> Option<R>
+    where F: Fn(&mut WriteTrans<'_, S>) -> STMResult<R> {
+        self.write_transaction_waiting(f, WaitPolicy::default(), false, None, ctx).map(|(result, _, _)| result)
+    }
+
+    // addr の値に f を適用した結果を書き込み、書き込んだ値を返す (AtomicU64::fetch_update のトランザクション版)
+    // 競合した場合は retry するため f は複数回呼ばれうる (副作用を持たせてはならない)
+    // write_transaction と同様に、retry policy が諦めた場合や poison された場合は None
+    pub fn update(&self, addr: usize, f: impl Fn([u8; S]) -> [u8; S]) -> Option<[u8; S]> {
+        self.write_transaction(|tr| {
+            let Some(val) = tr.load(addr) else {
+                return STMResult::Retry;
+            };
+            let new = f(val);
+            tr.store(addr, new);
+            STMResult::Ok(new)
+        })
+    }
+
+    // 現在の global_version を ReadHint として取得する (write_transaction_hinted を参照)
+    pub fn read_hint(&self) -> ReadHint<'_, S> {
+        ReadHint { stm: self, version: self.global_version() }
+    }
+
+    // write_transaction と同様だが、最初の実行は global_clock を読む代わりに hint の version を read_version とする
+    // commit した場合は hint をその version に進める。同じスレッドで続けて実行するトランザクションの間で
+    // 他のスレッドが commit していなければ、global_clock の読み込み (共有される cache line へのアクセス) を省略できる
+    // hint が他の STM のものであれば用いない。hint が古い場合は、読み込みが競合した後に通常どおり retry する
+    pub fn write_transaction_hinted<F, R>(&self, hint: &mut ReadHint<'_, S>, f: F) -> Option<R>
+    where F: Fn(&mut WriteTrans<'_, S>) -> STMResult<R> {
+        let same = std::ptr::eq(hint.stm, self);
+        let mut ctx = TxContext { read_hint: same.then_some(hint.version), ..TxContext::default() };
+        let (result, timing, _) = self.write_transaction_waiting(f, WaitPolicy::default(), false, None, &mut ctx)?;
+        if same {
+            hint.version = hint.version.max(timing.commit);
+        }
+        Some(result)
+    }
+
+    // write_transaction と同様だが、STM が poison されている (または実行中に poison された) 場合は Err(Poisoned) を返す
+    pub fn try_write_transaction<F, R>(&self, f: F) -> Result<Option<R>, Poisoned>
+    where F: Fn(&mut WriteTrans<'_, S>) -> STMResult<R> {
+        self.check_poison(self.write_transaction(f))
+    }
+
+    // ops を順に実行した後に finalize を実行し、全体を 1 つのトランザクションとして commit する
+    // いずれかの op が Ok 以外を返した場合は残りを実行せず、その結果 (Retry / RetryOk / Abort) をトランザクション全体の結果とする
+    pub fn atomically<R>(&self, ops: Vec<TxOp<S>>, finalize: impl Fn(&mut WriteTrans<'_, S>) -> STMResult<R>) -> Option<R> {
+        self.write_transaction(|tr| {
+            for op in ops.iter() {
+                match op(tr) {
+                    STMResult::Ok(()) => {}
+                    STMResult::Retry => return STMResult::Retry,
+                    STMResult::RetryOk => return STMResult::RetryOk,
+                    STMResult::Abort => return STMResult::Abort,
+                }
+            }
+            finalize(tr)
+        })
+    }
+
+    // group の全ての member を 1 つのトランザクションとして実行し commit する (TxGroup を参照)
+    // いずれかの member が Retry を返した場合は、全ての member の書き込みと on_commit の登録を破棄してから全体を retry する
+    pub fn run_group(&self, group: &TxGroup<S>) -> Option<()> {
+        self.write_transaction(|tr| group.run_in(tr))
+    }
+
+    // 事前に作成した write_set と read_set を、closure を実行せずに commit する (lock / 検証 / commit のみ)
+    // expected_version は read_set の値を読んだ時点の version: read_set のいずれかのストライプが
+    // expected_version より後に更新されていれば Conflict となり、何も書き込まない (retry は呼び出し側が行う)
+    // expected_version は global_version() 以下でなければならない
+    pub fn apply(&self, write_set: HashMap<usize, [u8; S]>, read_set: HashSet<usize>, expected_version: u64) -> ApplyOutcome {
+        if self.is_poisoned() {
+            return ApplyOutcome::Poisoned;
+        }
+        let mut write_trans = self.prepared_trans(write_set, read_set, expected_version);
+        // try_commit は書き込みのない write_trans を検証せずに commit したものとして扱う (closure の実行中に各読み込みを
+        // 検証済みであるため) が、事前に作成した read_set は検証されていないので、ここで expected_version と比べる
+        if write_trans.write_set.is_empty() {
+            if let Err(addr) = write_trans.validate_read_set() {
+                return ApplyOutcome::Conflict { addr: Some(addr) };
+            }
+        }
+        match self.try_commit(&mut write_trans) {
+            Some(version) => ApplyOutcome::Committed(version),
+            None => ApplyOutcome::Conflict { addr: write_trans.conflict_addr },
+        }
+    }
+
+    // apply と同様だが、global_clock から version を割り当てる代わりに、指定した version として commit する
+    // (WAL などに記録した commit を、元の実行と同じ version で再現するため)。commit の後 global_clock は version となる
+    // version は global_clock より大きくなければならない (各ストライプの version は global_clock 以下なので、
+    // 書き込むストライプの version も単調に増加する)。そうでなければ StaleVersion となり、何も書き込まない
+    // 間の version は割り当てられないまま飛ばされる。group commit は用いない
+    #[cfg(feature = "replay")]
+    pub fn apply_with_version(&self, write_set: HashMap<usize, [u8; S]>, read_set: HashSet<usize>, expected_version: u64, version: u64) -> ApplyOutcome {
+        if self.is_poisoned() {
+            return ApplyOutcome::Poisoned;
+        }
+        let current = self.global_version();
+        if version <= current {
+            return ApplyOutcome::StaleVersion { current };
+        }
+        let mut write_trans = self.prepared_trans(write_set, read_set, expected_version);
+        self.commit_with_version(&mut write_trans, version)
+    }
+
+    // apply / apply_with_version で commit する WriteTrans を作成する
+    fn prepared_trans(&self, write_set: HashMap<usize, [u8; S]>, read_set: HashSet<usize>, expected_version: u64) -> WriteTrans<'_, S> {
+        assert!(expected_version <= self.global_version(), "expected_version is newer than the global clock");
+        assert!(write_set.keys().chain(read_set.iter()).all(|addr| addr & (S - 1) == 0));
+        let mut write_trans = self.begin_write();
+        write_trans.read_version = expected_version;
+        write_trans.read_set.extend(read_set);
+        write_trans.write_set.extend(write_set);
+        write_trans
+    }
+
+    // closure を 1 回だけ実行し、その load / store を記録して返す (メモリは変更しない)
+    // write_set は commit せずに破棄するため、closure が Ok を返せば最初の試行で commit できたものとして扱う
+    // 競合していた場合 (load が None を返した場合) も retry せず、その時点までの記録を返す
+    pub fn write_transaction_dry_run<F, R>(&self, f: F) -> (Option<R>, Vec<Operation<S>>)
+    where F: FnOnce(&mut WriteTrans<'_, S>) -> STMResult<R> {
+        let mut write_trans = self.begin_write();
+        write_trans.ops = Some(Vec::new());
+
+        let result = match f(&mut write_trans) {
+            STMResult::Ok(val) if !write_trans.abort_requested => Some(val),
+            _ => None,
+        };
+        (result, write_trans.ops.take().unwrap_or_default())
+    }
+
+    // write_transaction と同様だが、closure の panic を捕捉して Err(TxPanic) を返す
+    // panic した実行は abort として扱われ、何も commit されない (closure の実行中は lock を保持していない)
+    pub fn write_transaction_catch<F, R>(&self, f: F) -> Result<Option<R>, TxPanic>
+    where F: Fn(&mut WriteTrans<'_, S>) -> STMResult<R> {
+        let panicked = RefCell::new(None);
+        let result = self.write_transaction(|tr| {
+            // panic 後の tr は途中の状態になりうるが、abort して破棄するため観測されない
+            match panic::catch_unwind(AssertUnwindSafe(|| f(tr))) {
+                Ok(r) => r,
+                Err(payload) => {
+                    *panicked.borrow_mut() = Some(payload);
+                    STMResult::Abort
+                }
+            }
+        });
+        match panicked.into_inner() {
+            Some(payload) => Err(TxPanic { payload }),
+            None => Ok(result),
+        }
+    }
+
+    // write_transaction と同様だが、commit 時に割り当てられた version も返す
+    // version は commit ごとに単調増加するため、トランザクション間の論理タイムスタンプとして使える
+    // 書き込みのないトランザクションは新しい version を割り当てず、開始時の read_version を返す
+    pub fn write_transaction_versioned<F, R>(&self, f: F) -> Option<(R, u64)>
+    where F: Fn(&mut WriteTrans<'_, S>) -> STMResult<R> {
+        self.write_transaction_waiting(f, WaitPolicy::default(), false, None, &mut TxContext::default()).map(|(result, timing, _)| (result, timing.commit))
+    }
+
+    // write_transaction と同様だが、commit したトランザクションの開始・commit 時の version も返す (TxTiming を参照)
+    pub fn write_transaction_traced<F, R>(&self, f: F) -> Option<(R, TxTiming)>
+    where F: Fn(&mut WriteTrans<'_, S>) -> STMResult<R> {
+        self.write_transaction_waiting(f, WaitPolicy::default(), false, None, &mut TxContext::default()).map(|(result, timing, _)| (result, timing))
+    }
+
+    // write_transaction と同様だが、書き込んだ各ストライプの (addr, 以前の version, 新しい version) も返す
+    // 以前の version は commit 時に lock を獲得した状態で読むため、最後に commit した実行が上書きした version と一致する
+    // 記録の順序は commit でストライプを書き込んだ順序 (CommitOrder を参照)
+    pub fn write_transaction_audit<F, R>(&self, f: F) -> Option<(R, Vec<AuditEntry>)>
+    where F: Fn(&mut WriteTrans<'_, S>) -> STMResult<R> {
+        self.write_transaction_waiting(f, WaitPolicy::default(), true, None, &mut TxContext::default()).map(|(result, _, audit)| (result, audit))
+    }
+
+    // write_transaction と同様だが、各実行の read_version を現在の global_clock ではなく version に固定する
+    // retry しても同じ時点のスナップショットを読むため、読み込みの結果は実行ごとに変わらない (再現性の調査用)
+    // version より後に commit されたストライプの load は競合となり、version を固定したままでは retry しても成功しないため、
+    // その場合は None を返す (lock 中のストライプとの競合は通常どおり retry する)。version が現在の global_version より大きい場合も None
+    // 読み込みは現在の値に対して行う: 上書きされた過去の値を読むには with_history と read_at_version を用いる
+    pub fn write_transaction_at<F, R>(&self, version: u64, f: F) -> Option<R>
+    where F: Fn(&mut WriteTrans<'_, S>) -> STMResult<R> {
+        if version > self.global_version() {
+            return None;
+        }
+        self.write_transaction_waiting(f, WaitPolicy::default(), false, Some(version), &mut TxContext::default()).map(|(result, _, _)| result)
+    }
+
+    // write_transaction_at で固定した version の時点から addr (競合したストライプ) が更新済みかどうか
+    // 更新済みであれば、同じ version で retry しても再び競合する
+    fn past_pinned(&self, pinned: Option<u64>, addr: Option<usize>) -> bool {
+        match (pinned, addr) {
+            (Some(version), Some(addr)) => !self.mem.is_locked(addr) && self.mem.get_version(addr) > version,
+            _ => false,
+        }
+    }
+
+    // write_transaction と同様だが、closure が RetryOk (条件が満たされていない) を返した場合の待ち方を指定する
+    // 条件が満たされるまで自前で spin するループを書く代わりに用いる
+    pub fn retry_until<F, R>(&self, f: F, policy: WaitPolicy) -> Option<R>
+    where F: Fn(&mut WriteTrans<'_, S>) -> STMResult<R> {
+        self.write_transaction_waiting(f, policy, false, None, &mut TxContext::default()).map(|(result, _, _)| result)
+    }
+
+    // retry_until と同様だが、shutdown によって待機をやめた場合は Err(ShuttingDown) を返す
+    pub fn try_retry_until<F, R>(&self, f: F, policy: WaitPolicy) -> Result<Option<R>, ShuttingDown>
+    where F: Fn(&mut WriteTrans<'_, S>) -> STMResult<R> {
+        match self.retry_until(f, policy) {
+            None if self.is_shutting_down() => Err(ShuttingDown),
+            result => Ok(result),
+        }
+    }
+
+    // retry_until (WaitPolicy::Block) の async 版: スレッドを park する代わりに、RetryOk を返した実行の read_set に waker を登録して
+    // Poll::Pending を返し、それらのストライプへの commit で wake される。executor のスレッドを塞がない
+    // 競合の場合は retry policy が諦めるかだけを参照し (sleep はしない)、wake してから Pending を返して他のタスクに譲る
+    pub fn write_transaction_retry_async<F, R>(&self, f: F) -> RetryFuture<'_, F, S>
+    where F: Fn(&mut WriteTrans<'_, S>) -> STMResult<R> + Unpin {
+        RetryFuture {
+            stm: self,
+            f,
+            id: self.next_waiter_id.fetch_add(1, Relaxed),
+            registered: false,
+            conflicts: 0,
+            attempts: Attempts::new(false, None),
+            ctx: TxContext::default(),
+        }
+    }
+
+    // pinned: write_transaction_at で固定した read_version
+    // ctx: 各実行の read_set / write_set などの確保先 (write_transaction_in を参照; 実行の間で容量を引き継ぐ)
+    fn write_transaction_waiting<F, R>(&self, f: F, wait_policy: WaitPolicy, audit: bool, pinned: Option<u64>, ctx: &mut TxContext<S>) -> Option<(R, TxTiming, Vec<AuditEntry>)>
+    where F: Fn(&mut WriteTrans<'_, S>) -> STMResult<R> {
+        let mut backoff = Backoff::new(&*self.retry_policy).with_wait_policy(wait_policy);
+        let mut attempts = Attempts::new(audit, pinned);
+        // tracing feature: トランザクション全体の span (runs は実行の回数; 以下の lock / validate / commit の event はこの span の中で出る)
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("write_transaction", runs = 0u64).entered();
+        #[cfg(feature = "tracing")]
+        let mut runs = 0u64;
+        loop {
+            // 前回の write_trans は drop 済み (= lock 解放済み) なので、ここで待機してよい
+            if !backoff.wait() {
+                self.record_stats(attempts.escalations, false);
+                #[cfg(feature = "tracing")]
+                tracing::debug!("retry policy gave up");
+                return None;        // retry policy が諦めた
+            }
+            #[cfg(feature = "tracing")]
+            {
+                runs += 1;
+                span.record("runs", runs);
+            }
+            match self.attempt(&mut attempts, &f, ctx) {
+                Attempt::Committed(result, timing, audit) => return Some((result, timing, audit)),
+                Attempt::Conflict => backoff.conflict(),
+                Attempt::Condition { read_version } => {
+                    if wait_policy == WaitPolicy::Block {
+                        self.block_on(&ctx.read_set, read_version);    // closure の実行中は lock を保持していないので、ここで park してよい
+                    }
+                    backoff.condition();    // 条件が満たされるまで再実行
+                }
+                Attempt::GiveUp => return None,
+            }
+        }
+    }
+
+    // 書き込みトランザクションを 1 回実行し、commit を試みる (write_transaction_waiting と RetryFuture が共有する)
+    // 待機と retry policy の判定は呼び出し側が行う。Conflict と Condition の場合は ctx に確保済みの領域を戻してある
+    fn attempt<F, R>(&self, attempts: &mut Attempts, f: &F, ctx: &mut TxContext<S>) -> Attempt<R>
+    where F: Fn(&mut WriteTrans<'_, S>) -> STMResult<R> {
+        if self.is_poisoned() {
+            return Attempt::GiveUp;
+        }
+        let started = self.speculation_limit.map(|limit| (Instant::now(), limit));
+        let hinted = ctx.read_hint.is_some();   // ReadHint を read_version とするかどうか
+        let read_version = match ctx.read_hint.take() {
+            Some(version) => {
+                self.record_hint(false);
+                version
+            }
+            None => self.mem.global_clock.load(Acquire),
+        };
+        let mut write_trans = self.begin_write_at(read_version).with_context(mem::take(ctx));
+        if attempts.audit {
+            write_trans.audit = Some(Vec::new());
+        }
+        if let Some(version) = attempts.pinned {
+            write_trans = write_trans.at_version(version);
+        }
+
+        // 投機的実行
+        let outcome = f(&mut write_trans);
+        if write_trans.abort_requested {
+            return Attempt::GiveUp;
+        }
+        let result = match outcome {
+            STMResult::Abort => return Attempt::GiveUp,
+            STMResult::RetryOk => {
+                if self.is_shutting_down() {
+                    return Attempt::GiveUp;
+                }
+                let read_version = write_trans.read_version;
+                *ctx = write_trans.take_context();
+                return Attempt::Condition { read_version };
+            }
+            _ if write_trans.conflict => return self.conflicted(attempts, write_trans, hinted, ctx),
+            STMResult::Retry => return Attempt::GiveUp,
+            STMResult::Ok(val) => val,
+        };
+        if let Some((started, limit)) = started {
+            if started.elapsed() > limit {
+                self.record_abort(AbortReason::Slow);
+                if hinted {
+                    self.record_hint(true);
+                }
+                *ctx = write_trans.take_context();
+                return Attempt::Conflict;
+            }
+        }
+
+        if !write_trans.write_set.is_empty() {
+            attempts.escalations += 1;      // 書き込みがなければ lock を獲得しない (try_commit を参照)
+        }
+        match self.try_commit(&mut write_trans) {
+            Some(new_version) => {
+                self.record_stats(attempts.escalations, true);
+                write_trans.run_callbacks();
+                let timing = TxTiming { begin: write_trans.read_version, commit: new_version, last_conflict: attempts.last_conflict, escalations: attempts.escalations };
+                let audit = write_trans.audit.take().unwrap_or_default();
+                *ctx = write_trans.take_context();
+                Attempt::Committed(result, timing, audit)
+            }
+            None => {
+                if write_trans.validation_failed && !attempts.advised && self.has_advisor.load(Acquire) {
+                    if let Some(addr) = write_trans.conflict_addr {
+                        *attempts.offenders.entry(addr).or_insert(0) += 1;
+                        attempts.advised = self.advise_split(write_trans.write_set.len(), &attempts.offenders);
+                    }
+                }
+                self.conflicted(attempts, write_trans, hinted, ctx)
+            }
+        }
+    }
+
+    // 競合した実行の後始末: 競合を記録し、固定した version では読めなくなった場合は諦める
+    fn conflicted<R>(&self, attempts: &mut Attempts, mut write_trans: WriteTrans<'_, S>, hinted: bool, ctx: &mut TxContext<S>) -> Attempt<R> {
+        attempts.last_conflict = write_trans.conflict_addr;
+        self.record_conflict(attempts.last_conflict);
+        if self.past_pinned(attempts.pinned, attempts.last_conflict) {
+            self.record_stats(attempts.escalations, false);
+            return Attempt::GiveUp;
+        }
+        if hinted {
+            self.record_hint(true);     // ReadHint の version が古かった
+        }
+        *ctx = write_trans.take_context();     // 獲得した lock もここで解放する
+        Attempt::Conflict
+    }
+
+    // 投機的実行を終えた write_trans の commit を試みる
+    // 成功すれば割り当てた version を返す; 競合した場合は None (獲得した lock は write_trans の drop 時に解放される)
+    pub(crate) fn try_commit(&self, write_trans: &mut WriteTrans<'_, S>) -> Option<u64> {
+        // 書き込みがない場合: 各読み込みは read_version 時点のスナップショットとして検証済みなので、
+        // read_version の時点で commit したものとして扱う (lock も global_clock の更新も行わない)
+        if write_trans.write_set.is_empty() {
+            return Some(write_trans.read_version);
+        }
+
+        if !self.lock_for_commit(write_trans) {
+            return None;
+        }   // 以下 write lock 獲得済み
+        if let Some(group_commit) = &self.group_commit {
+            return group_commit.commit(self, write_trans);
+        }
+        let new_version = self.stamp_commit(write_trans);
+        self.publish_commit(write_trans, new_version)
+    }
+
+    // try_commit の各段階 (DeterministicSTM::with_split_commit で段階の間に他のスレッドの commit を割り込ませるため分けてある)
+    // 書き込みのある write_trans に対して lock_for_commit, stamp_commit, publish_commit の順に呼ぶ
+
+    // write lock の獲得を試みる
+    pub(crate) fn lock_for_commit(&self, write_trans: &mut WriteTrans<'_, S>) -> bool {
+        let locked = match write_trans.single_stripe() {
+            Some(addr) => write_trans.lock_single_stripe(addr),     // lock と同時に read_set も検証する
+            None => write_trans.lock_write_set(),
+        };
+        #[cfg(feature = "tracing")]
+        tracing::trace!(locked, stripes = write_trans.write_set.len(), conflict = ?write_trans.conflict_addr, "lock");
+        locked
+    }
+
+    // try_commit と同様だが、stamp_commit の代わりに global_clock を version まで進めて version を割り当てる
+    // lock の獲得後に global_clock を進めるので、lock 中に他の commit が version 以上の値を割り当てていれば StaleVersion となる
+    // (read_set の検証に失敗した場合も global_clock は version まで進んでいる)
+    #[cfg(feature = "replay")]
+    pub(crate) fn commit_with_version(&self, write_trans: &mut WriteTrans<'_, S>, version: u64) -> ApplyOutcome {
+        if !self.lock_for_commit(write_trans) {
+            return ApplyOutcome::Conflict { addr: write_trans.conflict_addr };
+        }   // 以下 write lock 獲得済み
+        if let Err(current) = write_trans.mem.advance_global_clock_to(version) {
+            return ApplyOutcome::StaleVersion { current };
+        }
+        write_trans.mem.record_commit(version, write_trans.written_bits());
+        match self.publish_commit(write_trans, version) {
+            Some(version) => ApplyOutcome::Committed(version),
+            None => ApplyOutcome::Conflict { addr: write_trans.conflict_addr },
+        }
+    }
+
+    // lock の獲得後に version を割り当てる
+    pub(crate) fn stamp_commit(&self, write_trans: &WriteTrans<'_, S>) -> u64 {
+        let new_version = write_trans.mem.inc_global_clock();
+        write_trans.mem.record_commit(new_version, write_trans.written_bits());     // 他のトランザクションの差分検証のため
+        new_version
+    }
+
+    // read_set を検証して書き込みを公開する
+    // read_version + 1 == new_version であれば検証を省略する: read_version を読んでから自身が global_clock を進めるまでの間に、
+    // 他のトランザクションは version を割り当てていない。read_version 以下の version を割り当てた commit は、
+    // 読み込み時点で公開済みか (その値を読んだ)、lock 中 (読み込みが競合として失敗する) のいずれかであり、
+    // 自身より後の version を割り当てる commit は、自身の後に serialize されるので、読んだ値が上書きされていてもよい
+    // (lock の獲得から stamp_commit までの間に他の commit が version を割り当てた場合は new_version が進むので検証する)
+    pub(crate) fn publish_commit(&self, write_trans: &mut WriteTrans<'_, S>, new_version: u64) -> Option<u64> {
+        if write_trans.single_stripe().is_none() && (write_trans.read_version + 1 != new_version) {
+            let validated = write_trans.validate_read_set_since(new_version);
+            #[cfg(feature = "tracing")]
+            tracing::trace!(read_version = write_trans.read_version, version = new_version, reads = write_trans.read_set.len(), result = ?validated, "validate");
+            if let Err(addr) = validated {
+                write_trans.conflict_addr = Some(addr);
+                write_trans.validation_failed = true;
+                return None;
+            }
+        }
+
+        self.apply_commit(write_trans, new_version)
+    }
+
+    // 検証済みの書き込みを new_version として公開する
+    // commit が lock の不整合を検出して poison した場合は、何も書き込まずに None を返す
+    pub(crate) fn apply_commit(&self, write_trans: &mut WriteTrans<'_, S>, new_version: u64) -> Option<u64> {
+        if !write_trans.commit(new_version) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(version = new_version, "write set not locked at commit; STM poisoned");
+            return None;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::trace!(version = new_version, stripes = write_trans.write_set.len(), "commit");
+        self.wake_waiters(&write_trans.write_set);
+        self.notify(&write_trans.write_set, new_version);
+        Some(new_version)
+    }
+}
+
+// STM の設定をまとめて指定する
+// 各設定の意味は対応する STM::with_* を参照。指定しなかった設定は STM::new と同じ既定値となる
+// 競合時の振る舞い (contention management) は retry_policy で設定する。メモリの大きさは MEM_SIZE で固定 (build_from_memory を除く)
+pub struct StmBuilder<const S: usize = STRIPE_SIZE> {
+    retry_policy: Box<dyn RetryPolicy>,
+    read_capacity: usize,
+    write_capacity: usize,
+    hasher: SetHasher,
+    span_check: bool,
+    strict_init: bool,
+    commit_ordering: Ordering,
+    commit_order: CommitOrder,
+    lock_order: LockOrder,
+    prefault: bool,
+    last_writer: bool,
+    read_heat: bool,
+    stats: bool,
+    history: Option<usize>,
+    layout: Option<LayoutHint>,
+    adaptive_striping: Option<usize>,
+    group_commit: Option<Duration>,
+    speculation_limit: Option<Duration>,
+}
+
+impl<const S: usize> Default for StmBuilder<S> {
+    fn default() -> Self {
+        StmBuilder {
+            retry_policy: Box::new(Immediate),
+            read_capacity: 0,
+            write_capacity: 0,
+            hasher: SetHasher::default(),
+            span_check: false,
+            strict_init: false,
+            commit_ordering: Relaxed,
+            commit_order: CommitOrder::default(),
+            lock_order: LockOrder::default(),
+            prefault: false,
+            last_writer: false,
+            read_heat: false,
+            stats: false,
+            history: None,
+            layout: None,
+            adaptive_striping: None,
+            group_commit: None,
+            speculation_limit: None,
+        }
+    }
+}
+
+impl<const S: usize> StmBuilder<S> {
+    pub fn retry_policy<P: RetryPolicy + 'static>(mut self, policy: P) -> Self {
+        self.retry_policy = Box::new(policy);
+        self
+    }
+
+    pub fn set_capacity(mut self, read_capacity: usize, write_capacity: usize) -> Self {
+        self.read_capacity = read_capacity;
+        self.write_capacity = write_capacity;
+        self
+    }
+
+    pub fn hasher(mut self, hasher: SetHasher) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    pub fn span_check(mut self, span_check: bool) -> Self {
+        self.span_check = span_check;
+        self
+    }
+
+    pub fn strict_init(mut self, strict_init: bool) -> Self {
+        self.strict_init = strict_init;
+        self
+    }
+
+    pub fn commit_ordering(mut self, ordering: Ordering) -> Self {
+        self.commit_ordering = ordering;
+        self
+    }
+
+    pub fn commit_order(mut self, order: CommitOrder) -> Self {
+        self.commit_order = order;
+        self
+    }
+
+    pub fn lock_order(mut self, order: LockOrder) -> Self {
+        self.lock_order = order;
+        self
+    }
+
+    pub fn prefault(mut self, prefault: bool) -> Self {
+        self.prefault = prefault;
+        self
+    }
+
+    pub fn with_last_writer(mut self) -> Self {
+        self.last_writer = true;
+        self
+    }
+
+    pub fn with_read_heat(mut self) -> Self {
+        self.read_heat = true;
+        self
+    }
+
+    pub fn with_stats(mut self) -> Self {
+        self.stats = true;
+        self
+    }
+
+    pub fn history(mut self, len: usize) -> Self {
+        self.history = Some(len);
+        self
+    }
+
+    pub fn layout(mut self, hint: LayoutHint) -> Self {
+        self.layout = Some(hint);
+        self
+    }
+
+    pub fn adaptive_striping(mut self, region_len: usize) -> Self {
+        self.adaptive_striping = Some(region_len);
+        self
+    }
+
+    pub fn group_commit(mut self, window: Duration) -> Self {
+        self.group_commit = Some(window);
+        self
+    }
+
+    pub fn speculation_limit(mut self, limit: Duration) -> Self {
+        self.speculation_limit = Some(limit);
+        self
+    }
+
+    pub fn build(self) -> STM<S> {
+        self.configure(STM::new_sized())
+    }
+
+    // 初期値を与えて作成する (STM::from_bytes を参照)
+    pub fn build_from_bytes(self, initial: Vec<u8>) -> Result<STM<S>, MemoryError> {
+        Ok(self.configure(STM::from_bytes_sized(initial)?))
+    }
+
+    // 作成済みの Memory (Memory::from_mut_slice など) の上に STM を作成する
+    pub fn build_from_memory(self, mem: Memory<S>) -> STM<S> {
+        self.configure(STM::from_memory(mem))
+    }
+
+    fn configure(self, mut stm: STM<S>) -> STM<S> {
+        stm.retry_policy = self.retry_policy;
+        stm = stm
+            .with_set_capacity(self.read_capacity, self.write_capacity)
+            .with_hasher(self.hasher)
+            .with_span_check(self.span_check)
+            .with_strict_init(self.strict_init)
+            .with_commit_ordering(self.commit_ordering)
+            .with_commit_order(self.commit_order)
+            .with_lock_order(self.lock_order)
+            .with_prefault(self.prefault);
+        if self.last_writer {
+            stm = stm.with_last_writer();
+        }
+        if self.read_heat {
+            stm = stm.with_read_heat();
+        }
+        if self.stats {
+            stm = stm.with_stats();
+        }
+        if let Some(len) = self.history {
+            stm = stm.with_history(len);
+        }
+        if let Some(hint) = self.layout {
+            stm = stm.with_layout(hint);
+        }
+        if let Some(region_len) = self.adaptive_striping {
+            stm = stm.with_adaptive_striping(region_len);
+        }
+        if let Some(window) = self.group_commit {
+            stm = stm.with_group_commit(window);
+        }
+        if let Some(limit) = self.speculation_limit {
+            stm = stm.with_speculation_limit(limit);
+        }
+        stm
+    }
+}
+
+// STM::scope_write で作成する、1 回分の書き込みトランザクション
+// load / store などは Deref を通して WriteTrans のものを用いる。finish で commit を試みて結果を返し、
+// finish せずに drop した場合は abort する (lock は finish の中でのみ獲得するため、drop 時には何も書き込まれない)。
+// finish は self を消費するため、finish 後の scope は使えない。競合した場合も retry しない (呼び出し側が作り直す)
+// 例: let mut tx = stm.scope_write();
+//     let v = tx.load(0)?;
+//     tx.store(0, v);
+//     tx.finish()
+pub struct TxScope<'a, const S: usize = STRIPE_SIZE> {
+    stm: &'a STM<S>,
+    trans: WriteTrans<'a, S>,
+}
+
+impl<const S: usize> TxScope<'_, S> {
+    // 読み込みの競合 (load が None を返した場合を含む) も Conflict として返す
+    pub fn finish(mut self) -> ApplyOutcome {
+        if self.stm.is_poisoned() {
+            return ApplyOutcome::Poisoned;
+        }
+        if self.trans.abort_requested {
+            return ApplyOutcome::Aborted;
+        }
+        if self.trans.conflict {
+            return ApplyOutcome::Conflict { addr: self.trans.conflict_addr };
+        }
+        match self.stm.try_commit(&mut self.trans) {
+            Some(version) => {
+                self.trans.run_callbacks();
+                ApplyOutcome::Committed(version)
+            }
+            None => {
+                if let Some(addr) = self.trans.conflict_addr {
+                    self.stm.mem.record_contention(addr);
+                }
+                ApplyOutcome::Conflict { addr: self.trans.conflict_addr }
+            }
+        }
+    }
+}
+
+impl<'a, const S: usize> Deref for TxScope<'a, S> {
+    type Target = WriteTrans<'a, S>;
+
+    fn deref(&self) -> &WriteTrans<'a, S> {
+        &self.trans
+    }
+}
+
+impl<'a, const S: usize> DerefMut for TxScope<'a, S> {
+    fn deref_mut(&mut self) -> &mut WriteTrans<'a, S> {
+        &mut self.trans
+    }
+}
+
+// STM::scope 内でのスレッド起動用
+pub struct StmScope<'scope, 'env, const S: usize = STRIPE_SIZE> {
+    stm: &'env STM<S>,
+    scope: &'scope Scope<'scope, 'env>,
+}
+
+impl<'scope, 'env, const S: usize> StmScope<'scope, 'env, S> {
+    // STM の参照を受け取る closure を scoped thread として起動
+    pub fn spawn<F, T>(&self, f: F) -> ScopedJoinHandle<'scope, T>
+    where F: FnOnce(&'env STM<S>) -> T + Send + 'scope, T: Send + 'scope {
+        let stm = self.stm;
+        self.scope.spawn(move || f(stm))
+    }
+}
+
+// 連続する読み込みトランザクションのスナップショットの version が単調非減少であることを保証する
+// (複数スレッドから共有してもよい)
+pub struct MonotonicReader<'a, const S: usize = STRIPE_SIZE> {
+    stm: &'a STM<S>,
+    last: AtomicU64,        // これまでに返したスナップショットの最大の version
+}
+
+impl<'a, const S: usize> MonotonicReader<'a, S> {
+    // read_transaction と同様だが、前回返したものより古いスナップショットは返さない (古い場合は読み直す)
+    pub fn read<F, R>(&self, f: F) -> Option<R>
+    where F: Fn(&mut ReadTrans<'_, S>) -> STMResult<R> {
+        loop {
+            let last = self.last.load(Acquire);
+            let (result, version) = self.stm.read_transaction_versioned(&f)?;
+            if version >= last {
+                self.last.fetch_max(version, AcqRel);
+                return Some(result);
+            }
+        }
+    }
+
+    // これまでに返したスナップショットの最大の version
+    pub fn last_version(&self) -> u64 {
+        self.last.load(Acquire)
+    }
+}
+
+// STM::subscribe が返す購読 (Receiver として ChangeEvent を受け取る)
+// drop すると STM から購読の登録を取り除く
+pub struct Subscription<'a, const S: usize = STRIPE_SIZE> {
+    stm: &'a STM<S>,
+    id: u64,
+    receiver: Receiver<ChangeEvent<S>>,
+}
+
+impl<'a, const S: usize> Deref for Subscription<'a, S> {
+    type Target = Receiver<ChangeEvent<S>>;
+
+    fn deref(&self) -> &Receiver<ChangeEvent<S>> {
+        &self.receiver
+    }
+}
+
+impl<'a, const S: usize> Drop for Subscription<'a, S> {
+    fn drop(&mut self) {
+        self.stm.unsubscribe(self.id);
+    }
+}
+
+// STM::write_transaction_retry_async が返す Future
+// 結果は write_transaction と同様 (Abort, または retry policy が諦めた場合は None)
+pub struct RetryFuture<'a, F, const S: usize = STRIPE_SIZE> {
+    stm: &'a STM<S>,
+    f: F,
+    id: u64,                // waker を登録する Waiter の id
+    registered: bool,       // drop 時に登録を解除するかどうか
+    conflicts: usize,       // 競合による retry の回数
+    attempts: Attempts,
+    ctx: TxContext<S>,      // poll をまたいで次の実行に引き継ぐ
+}
+
+impl<F, const S: usize> RetryFuture<'_, F, S> {
+    // 競合した実行の後: retry policy が諦めなければ、wake して他のタスクに実行を譲ってから再実行する
+    fn conflict<R>(&mut self, cx: &mut Context<'_>) -> Poll<Option<R>> {
+        self.conflicts += 1;
+        if self.stm.retry_policy.delay(self.conflicts).is_none() {
+            self.stm.record_stats(self.attempts.escalations, false);
+            return Poll::Ready(None);
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+impl<F, R, const S: usize> Future for RetryFuture<'_, F, S>
+where F: Fn(&mut WriteTrans<'_, S>) -> STMResult<R> + Unpin {
+    type Output = Option<R>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<R>> {
+        let this = self.get_mut();
+        let stm = this.stm;
+        loop {
+            match stm.attempt(&mut this.attempts, &this.f, &mut this.ctx) {
+                Attempt::Committed(result, _, _) => return Poll::Ready(Some(result)),
+                Attempt::Conflict => return this.conflict(cx),
+                Attempt::Condition { read_version } => {
+                    let addrs: Vec<usize> = this.ctx.read_set.iter().copied().collect();
+                    if addrs.is_empty() {
+                        cx.waker().wake_by_ref();       // 待機するきっかけがない
+                        return Poll::Pending;
+                    }
+                    // 登録後に version を再検査し、登録前に commit されていればすぐに再実行する (STM::block_on と同様)
+                    this.registered = true;
+                    if stm.register_waiter(this.id, &addrs, WakeTarget::Task(cx.waker().clone()), read_version) && !stm.is_shutting_down() {
+                        return Poll::Pending;
+                    }
+                }
+                Attempt::GiveUp => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+impl<F, const S: usize> Drop for RetryFuture<'_, F, S> {
+    fn drop(&mut self) {
+        if self.registered {
+            self.stm.unregister_waiter(self.id);
         }
+    }
+}
 
-        fence(SeqCst);
-        // consistency check: 読み込みメモリがロックされておらず、かつ read_version 以下であるかどうか
-        if !self.mem.test_not_modify(addr, self.read_version) {
-            self.conflict = true;
-            return None;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{load, store};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // 初期値は version 1 で commit 済みの値として読め、次の commit は version 2 になる
+    #[test]
+    fn from_bytes_seeds_committed_contents() {
+        let initial: Vec<u8> = (0..MEM_SIZE).map(|i| i as u8).collect();
+        let stm = STM::from_bytes(initial.clone()).unwrap();
+        assert_eq!(stm.global_version(), 1);
+        assert!(stm.version_vector().iter().all(|&v| v == 1));
+        let seeded = stm.read_transaction(|tr| {
+            let mut seeded = Vec::new();
+            for addr in (0..MEM_SIZE).step_by(STRIPE_SIZE) {
+                seeded.extend_from_slice(&load!(tr, addr));
+            }
+            STMResult::Ok(seeded)
+        });
+        assert_eq!(seeded, Some(initial));
+
+        let (_, version) = stm.write_transaction_versioned(|tr| {
+            let v = u64::from_le_bytes(load!(tr, 0));
+            store!(tr, 0, (v + 1).to_le_bytes());
+            STMResult::Ok(())
+        }).unwrap();
+        assert_eq!(version, 2);
+    }
+
+    // 長さが MEM_SIZE と一致しない初期値は拒否する
+    #[test]
+    fn from_bytes_rejects_wrong_length() {
+        for len in [0, MEM_SIZE - 1, MEM_SIZE + STRIPE_SIZE] {
+            assert_eq!(STM::from_bytes(vec![0; len]).err(), Some(MemoryError::InvalidLength { expected: MEM_SIZE, actual: len }));
+            assert_eq!(Memory::<STRIPE_SIZE>::from_bytes(vec![0; len]).err(), Some(MemoryError::InvalidLength { expected: MEM_SIZE, actual: len }));
         }
+    }
 
-        Some(mem)
+    // retry 前の back-off (spin_loop / yield_now) を挟んでも、同じストライプを奪い合う全ての加算が commit され、失われない
+    #[test]
+    fn contended_increments_all_commit_with_backoff() {
+        const THREADS: u64 = 4;
+        const INCREMENTS: u64 = if cfg!(miri) { 20 } else { 2000 };
+        let stm = STM::new();
+        std::thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| for _ in 0..INCREMENTS {
+                    let committed = stm.write_transaction(|tr| {
+                        let v = u64::from_le_bytes(load!(tr, 0)) + 1;
+                        store!(tr, 0, v.to_le_bytes());
+                        STMResult::Ok(())
+                    });
+                    assert_eq!(committed, Some(()));
+                });
+            }
+        });
+        assert_eq!(u64::from_le_bytes(stm.read_raw(0)), THREADS * INCREMENTS);
     }
 
-    // write_set に対応するメモリをロックしようと試みる
-    fn lock_write_set(&mut self) -> bool {
-        for (addr, _) in self.write_set.iter() {
-            if self.mem.lock_addr(*addr) {      // lock 獲得に成功
-                self.locked.push(*addr);        // drop 時のために覚えておく
-            } else {
-                return false;
+    // future が完了するまで poll する (RetryFuture は競合の後に自身を wake してから Pending を返す)
+    fn poll_to_end<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
             }
         }
-        true
     }
 
-    fn validate_read_set(&self) -> bool {                               // read_set 検証
-        for addr in self.read_set.iter() {                          // メモリから読み込んだすべてのアドレスに対し
-            if self.write_set.contains_key(addr) {                          // write していたならば
-                let version = self.mem.get_version(*addr);             // 処理中に version が更新されていないか調べる
-                if version > self.read_version {
-                    return false;
-                }
-            } else {                                                        // write していないならば
-                if !self.mem.test_not_modify(*addr, self.read_version) {    // 処理中に version が更新されていないか調べる
-                    return false;
+    // 最初の実行で、読み込む前に他のトランザクションが同じストライプに commit する closure を返す
+    // 2 回目以降の実行は値を +1 して on_commit で記録する
+    fn conflicting_increment<'a>(stm: &'a STM, runs: &'a Cell<usize>, committed: &'a Rc<Cell<usize>>) -> impl Fn(&mut WriteTrans<'_>) -> STMResult<u64> + Unpin + 'a {
+        move |tr| {
+            runs.set(runs.get() + 1);
+            if runs.get() == 1 {
+                stm.write_transaction(|other| {
+                    store!(other, 0, 10u64.to_le_bytes());
+                    STMResult::Ok(())
+                });
+            }
+            let v = u64::from_le_bytes(load!(tr, 0)) + 1;
+            store!(tr, 0, v.to_le_bytes());
+            let committed = committed.clone();
+            tr.on_commit(move || committed.set(committed.get() + 1));
+            STMResult::Ok(v)
+        }
+    }
+
+    // tracing feature: event の message を記録する subscriber
+    #[cfg(feature = "tracing")]
+    struct Collect(Mutex<Vec<String>>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for Collect {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            self.0.lock().unwrap().push(format!("span {}", span.metadata().name()));
+            tracing::span::Id::from_u64(1)
+        }
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+        fn event(&self, event: &tracing::Event<'_>) {
+            struct Message(String);
+            impl tracing::field::Visit for Message {
+                fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+                    if field.name() == "message" {
+                        self.0 = format!("{:?}", value);
+                    }
                 }
             }
+            let mut message = Message(String::new());
+            event.record(&mut message);
+            self.0.lock().unwrap().push(message.0);
         }
-        true
+        fn enter(&self, _: &tracing::span::Id) {}
+        fn exit(&self, _: &tracing::span::Id) {}
     }
 
-    fn commit(&mut self, version: u64) {
-        // メモリに書き込み (copy)
-        for (addr, val) in self.write_set.iter() {
-            let addr = *addr as usize;
-            for i in 0..STRIPE_SIZE {
-                self.mem.mem[addr + i] = val[i];
+    // 複数のストライプに書き込むトランザクションは span の中で lock, validate, commit の順に event を出す
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_emits_commit_protocol_events() {
+        let stm = STM::new();
+        let collect = std::sync::Arc::new(Collect(Mutex::new(Vec::new())));
+        tracing::subscriber::with_default(collect.clone(), || {
+            stm.write_transaction(|tr| {
+                let v = u64::from_le_bytes(load!(tr, 0));
+                // 読み込みの後に他の commit が version を進めるため、検証を省略しない
+                if v == 0 {
+                    stm.write_transaction(|other| {
+                        store!(other, 16, 1u64.to_le_bytes());
+                        STMResult::Ok(())
+                    });
+                }
+                store!(tr, 0, (v + 1).to_le_bytes());
+                store!(tr, 8, (v + 1).to_le_bytes());
+                STMResult::Ok(())
+            });
+        });
+        let events = collect.0.lock().unwrap().clone();
+        // 内側のトランザクション (1 ストライプ: 検証なし) の後に外側のトランザクションが commit する
+        assert_eq!(events, ["span write_transaction", "span write_transaction", "lock", "commit", "lock", "validate", "commit"]);
+    }
+
+    // 同期版と async 版は同じ retry の手順をたどり、同じ統計を記録して on_commit の処理を実行する
+    #[test]
+    fn async_retry_matches_sync_retry() {
+        let outcomes: Vec<_> = [false, true].into_iter().map(|async_path| {
+            let stm = STM::new().with_stats();
+            let runs = Cell::new(0);
+            let committed = Rc::new(Cell::new(0));
+            let f = conflicting_increment(&stm, &runs, &committed);
+            let result = if async_path { poll_to_end(stm.write_transaction_retry_async(f)) } else { stm.write_transaction(f) };
+            let stats = stm.stats().unwrap();
+            (result, runs.get(), committed.get(), stats.commits, stats.conflict_aborts, stats.escalations)
+        }).collect();
+        // 競合した実行 1 回と commit した実行 1 回 (commits には他のトランザクションの分も含む)
+        assert_eq!(outcomes[0], (Some(11), 2, 1, 2, 1, 2));
+        assert_eq!(outcomes[1], outcomes[0]);
+    }
+
+    // 書き込み先の lock を保持していない write_trans の公開: poison して None を返し、何も書き込まない
+    #[test]
+    fn apply_commit_without_locks_poisons() {
+        let stm = STM::new();
+        let mut write_trans = stm.begin_write();
+        write_trans.store(0, 7u64.to_le_bytes());
+        assert_eq!(stm.apply_commit(&mut write_trans, 1), None);
+        assert!(stm.is_poisoned());
+        assert_eq!(stm.read_raw(0), [0; 8]);
+        assert_eq!(stm.version_vector()[0], 0);
+    }
+
+    // 2 つのストライプの値を u64 として読む
+    fn pair(tr: &mut impl Loadable) -> Option<(u64, u64)> {
+        Some((u64::from_le_bytes(tr.load(0)?), u64::from_le_bytes(tr.load(8)?)))
+    }
+
+    fn read_pair(stm: &STM) -> Option<(u64, u64)> {
+        stm.read_transaction(|tr| match pair(tr) {
+            Some(p) => STMResult::Ok(p),
+            None => STMResult::Retry,
+        })
+    }
+
+    // 1 ストライプの commit が複数ストライプのトランザクションの読み込みの間に入ると、後者は検証に失敗して retry する
+    #[test]
+    fn single_stripe_commit_invalidates_multi_stripe_reader() {
+        let stm = STM::new();
+        let runs = Cell::new(0);
+        stm.write_transaction(|tr| {
+            runs.set(runs.get() + 1);
+            let a = u64::from_le_bytes(load!(tr, 0));
+            if runs.get() == 1 {
+                stm.write_transaction(|other| {
+                    let v = u64::from_le_bytes(load!(other, 0));
+                    store!(other, 0, (v + 5).to_le_bytes());
+                    STMResult::Ok(())
+                });
+            }
+            let b = u64::from_le_bytes(load!(tr, 8));
+            store!(tr, 0, 0u64.to_le_bytes());
+            store!(tr, 8, (a + b).to_le_bytes());
+            STMResult::Ok(())
+        }).unwrap();
+        assert_eq!(runs.get(), 2);
+        assert_eq!(read_pair(&stm), Some((0, 5)));
+    }
+
+    // 逆に、1 ストライプのトランザクションの読み込みの後に複数ストライプの commit が入ると、前者の CAS が失敗して retry する
+    #[test]
+    fn multi_stripe_commit_invalidates_single_stripe_writer() {
+        let stm = STM::new();
+        let runs = Cell::new(0);
+        stm.write_transaction(|tr| {
+            runs.set(runs.get() + 1);
+            let a = u64::from_le_bytes(load!(tr, 0));
+            if runs.get() == 1 {
+                stm.write_transaction(|other| {
+                    store!(other, 0, 3u64.to_le_bytes());
+                    store!(other, 8, 3u64.to_le_bytes());
+                    STMResult::Ok(())
+                });
+            }
+            store!(tr, 0, (a + 1).to_le_bytes());
+            STMResult::Ok(())
+        }).unwrap();
+        assert_eq!(runs.get(), 2);
+        assert_eq!(read_pair(&stm), Some((4, 3)));
+    }
+
+    // 1 ストライプの加算と、2 ストライプ間の移動を並行に行っても、合計は加算の回数に一致し、reader から見た合計は減らない
+    #[test]
+    fn single_stripe_commits_interleave_with_multi_stripe_commits() {
+        const N: u64 = if cfg!(miri) { 20 } else { 2000 };
+        let stm = STM::new();
+        std::thread::scope(|s| {
+            s.spawn(|| for _ in 0..N {
+                stm.write_transaction(|tr| {
+                    let v = u64::from_le_bytes(load!(tr, 0));
+                    store!(tr, 0, (v + 1).to_le_bytes());
+                    STMResult::Ok(())
+                }).unwrap();
+            });
+            s.spawn(|| for _ in 0..N {
+                stm.write_transaction(|tr| {
+                    let (a, b) = match pair(tr) { Some(p) => p, None => return STMResult::Retry };
+                    store!(tr, 0, 0u64.to_le_bytes());
+                    store!(tr, 8, (a + b).to_le_bytes());
+                    STMResult::Ok(())
+                }).unwrap();
+            });
+            s.spawn(|| {
+                let mut last = 0;
+                for _ in 0..N {
+                    let Some((a, b)) = read_pair(&stm) else { continue };
+                    assert!(a + b >= last, "total went backwards: {} after {}", a + b, last);
+                    last = a + b;
+                }
+            });
+        });
+        let (a, b) = read_pair(&stm).unwrap();
+        assert_eq!(a + b, N);
+    }
+
+    // closure が途中で panic しても lock は残らず、同じストライプへの後続のトランザクションが commit できる
+    #[test]
+    fn panicking_transaction_leaves_no_locks() {
+        let stm = STM::new();
+        let result = stm.write_transaction_catch(|tr| {
+            store!(tr, 0, 1u64.to_le_bytes());
+            store!(tr, 8, 1u64.to_le_bytes());
+            if u64::from_le_bytes(load!(tr, 16)) == 0 {
+                panic!("injected");
             }
+            STMResult::Ok(())
+        });
+        assert_eq!(result.unwrap_err().message(), Some("injected"));
+        assert!(stm.locked_stripes().is_empty());
+
+        // write_transaction から unwind した場合も同様
+        let unwound = panic::catch_unwind(AssertUnwindSafe(|| stm.write_transaction(|tr| -> STMResult<()> {
+            store!(tr, 0, 2u64.to_le_bytes());
+            panic!("unwound");
+        })));
+        assert!(unwound.is_err());
+        assert!(stm.locked_stripes().is_empty());
+        assert!(!stm.is_poisoned());
+
+        stm.write_transaction(|tr| {
+            store!(tr, 0, 3u64.to_le_bytes());
+            store!(tr, 8, 3u64.to_le_bytes());
+            STMResult::Ok(())
+        }).unwrap();
+        assert_eq!(read_pair(&stm), Some((3, 3)));
+    }
+
+    // 並行に commit されるストライプを読んでも、途中まで書き込まれた値 (torn write) は観測されない
+    // cargo +nightly miri test で data race がないことも検査できるよう、ヒープは 2 ストライプとし、回数も減らす
+    #[test]
+    fn concurrent_reader_never_sees_torn_stripes() {
+        const N: u8 = if cfg!(miri) { 10 } else { 200 };
+        static mut BUF: [u8; 2 * STRIPE_SIZE] = [0; 2 * STRIPE_SIZE];
+        // SAFETY: BUF はこのテストでのみ用いる
+        let stm = STM::from_memory(Memory::from_mut_slice(unsafe { &mut *std::ptr::addr_of_mut!(BUF) }).unwrap());
+        std::thread::scope(|s| {
+            s.spawn(|| for i in 1..=N {
+                stm.write_transaction(|tr| {
+                    store!(tr, 0, [i; STRIPE_SIZE]);
+                    store!(tr, STRIPE_SIZE, [i; STRIPE_SIZE]);
+                    STMResult::Ok(())
+                }).unwrap();
+            });
+            s.spawn(|| for _ in 0..N {
+                let Some((a, b)) = stm.read_transaction(|tr| STMResult::Ok((load!(tr, 0), load!(tr, STRIPE_SIZE)))) else { continue };
+                assert!(a.iter().chain(&b).all(|byte| *byte == a[0]), "torn read: {:?} {:?}", a, b);
+            });
+        });
+        assert_eq!(stm.read_transaction(|tr| STMResult::Ok(load!(tr, 0))), Some([N; STRIPE_SIZE]));
+    }
+
+    // 先頭 4 ストライプの各先頭バイト
+    fn first_bytes(tr: &mut impl Loadable) -> Option<[u8; 4]> {
+        let mut firsts = [0; 4];
+        for (i, first) in firsts.iter_mut().enumerate() {
+            *first = tr.load(i * STRIPE_SIZE)?[0];
         }
-        fence(Release);
+        Some(firsts)
+    }
 
-        for (addr, _) in self.write_set.iter() {
-            let stripe = addr >> self.mem.shift_size;               // ストライプの index
-            self.mem.lock_ver[stripe].store(version, Relaxed);  // version 更新
+    fn read_first_bytes(stm: &STM) -> Option<[u8; 4]> {
+        stm.read_transaction(|tr| match first_bytes(tr) {
+            Some(firsts) => STMResult::Ok(firsts),
+            None => STMResult::Retry,
+        })
+    }
+
+    // ops を 1 つのトランザクションで stage した後の first_bytes
+    // (トランザクション内で読んだ値, commit 後に読んだ値) を返す
+    fn staged(ops: impl Fn(&mut WriteTrans<'_>)) -> ([u8; 4], [u8; 4]) {
+        let stm = STM::new();
+        let inside = stm.write_transaction(|tr| {
+            ops(tr);
+            match first_bytes(tr) {
+                Some(firsts) => STMResult::Ok(firsts),
+                None => STMResult::Retry,
+            }
+        }).unwrap();
+        (inside, read_first_bytes(&stm).unwrap())
+    }
+
+    fn stripes(tags: &[u8]) -> Vec<u8> {
+        tags.iter().flat_map(|tag| [*tag; STRIPE_SIZE]).collect()
+    }
+
+    // store / store_bytes の書き込みはストライプごとに後勝ちで、覆う各ストライプを丸ごと置き換える
+    // トランザクション内の load も、commit 後の値も同じ結果になる
+    #[test]
+    fn overlapping_stores_replace_whole_stripes() {
+        type Ops = fn(&mut WriteTrans<'_>);
+        let cases: [(&str, Ops, [u8; 4]); 6] = [
+            ("store then covering store_bytes", |tr| {
+                tr.store(8, [1; STRIPE_SIZE]);
+                tr.store_bytes(0, &stripes(&[2, 2, 2]));
+            }, [2, 2, 2, 0]),
+            ("store_bytes then store inside it", |tr| {
+                tr.store_bytes(0, &stripes(&[1, 1, 1]));
+                tr.store(8, [2; STRIPE_SIZE]);
+            }, [1, 2, 1, 0]),
+            ("store_bytes partially overlapping a prior store_bytes", |tr| {
+                tr.store_bytes(0, &stripes(&[1, 1]));
+                tr.store_bytes(8, &stripes(&[2, 2]));
+            }, [1, 2, 2, 0]),
+            ("store_bytes partially overlapping a later store_bytes", |tr| {
+                tr.store_bytes(8, &stripes(&[1, 1, 1]));
+                tr.store_bytes(0, &stripes(&[2, 2]));
+            }, [2, 2, 1, 1]),
+            ("stores to the same stripe", |tr| {
+                tr.store(0, [1; STRIPE_SIZE]);
+                tr.store(0, [2; STRIPE_SIZE]);
+            }, [2, 0, 0, 0]),
+            ("store after store_bytes of the same range", |tr| {
+                tr.store_bytes(0, &stripes(&[1, 1]));
+                tr.store_bytes(0, &stripes(&[2, 2]));
+                tr.store(0, [3; STRIPE_SIZE]);
+            }, [3, 2, 0, 0]),
+        ];
+        for (name, ops, expected) in cases {
+            assert_eq!(staged(ops), (expected, expected), "{}", name);
         }
-        self.locked.clear();    // lock flag 解除
     }
-}
 
-impl<'a> Drop for WriteTrans<'a> {
-    fn drop(&mut self) {    // locked に記録されたメモリのロックを解除
-        for addr in self.locked.iter() {
-            self.mem.unlock_addr(*addr);
+    // ストライプの途中のバイトだけを書き換えることはない: 読んだ値の一部を変えて store すると、残りは読んだ値のまま
+    #[test]
+    fn partial_stripe_update_keeps_the_rest_of_the_read_value() {
+        let stm = STM::new();
+        stm.write_transaction(|tr| {
+            tr.store_bytes(0, &stripes(&[1, 1]));
+            let mut stripe = load!(tr, 8);
+            stripe[STRIPE_SIZE - 1] = 9;
+            store!(tr, 8, stripe);
+            STMResult::Ok(())
+        }).unwrap();
+        let mut expected = [1; STRIPE_SIZE];
+        expected[STRIPE_SIZE - 1] = 9;
+        assert_eq!(stm.read_transaction(|tr| STMResult::Ok((load!(tr, 0), load!(tr, 8)))), Some(([1; STRIPE_SIZE], expected)));
+    }
+
+    // span_check が有効な場合、複数ストライプの書き込みの一部だけを上書きすると panic する (全体を覆う上書きは許す)
+    #[test]
+    fn span_check_rejects_partial_overwrites() {
+        let stm = STM::builder().span_check(true).build();
+        stm.write_transaction(|tr| {
+            tr.store_bytes(0, &stripes(&[1, 1]));
+            tr.store_bytes(0, &stripes(&[2, 2, 2]));
+            STMResult::Ok(())
+        }).unwrap();
+        let result = stm.write_transaction_catch(|tr| {
+            tr.store_bytes(0, &stripes(&[1, 1]));
+            tr.store(8, [2; STRIPE_SIZE]);
+            STMResult::Ok(())
+        });
+        assert!(result.unwrap_err().message().unwrap().contains("partially overwrites"));
+    }
+
+    // 書き込んでから読んだアドレスは read_set に入らず、書き込む前にメモリから読んだアドレスは read_set に残る
+    #[test]
+    fn loads_of_own_writes_stay_out_of_read_set() {
+        let stm = STM::new();
+        stm.write_transaction(|tr| {
+            store!(tr, 0, [1; STRIPE_SIZE]);
+            assert_eq!(load!(tr, 0), [1; STRIPE_SIZE]);
+            let v = load!(tr, 8);
+            store!(tr, 8, v);
+            assert_eq!(load!(tr, 8), v);
+            assert!(!tr.read_set.contains(&0));
+            assert!(tr.read_set.contains(&8));
+            STMResult::Ok(())
+        }).unwrap();
+    }
+
+    // 読み込んだアドレスが他のトランザクションに更新されるまでは commit できる状態で、would_commit は共有状態を変えない
+    #[test]
+    fn would_commit_detects_external_modification() {
+        let stm = STM::new();
+        let runs = Cell::new(0);
+        stm.write_transaction(|tr| {
+            runs.set(runs.get() + 1);
+            let v = load!(tr, 0);
+            store!(tr, 8, v);
+            if runs.get() == 1 {
+                assert!(tr.would_commit());
+                assert!(stm.locked_stripes().is_empty());
+                stm.write_transaction(|other| {
+                    store!(other, 0, [1; STRIPE_SIZE]);
+                    STMResult::Ok(())
+                });
+                assert!(!tr.would_commit());
+            } else {
+                assert!(tr.would_commit());
+            }
+            STMResult::Ok(())
+        }).unwrap();
+        assert_eq!(runs.get(), 2);
+    }
+
+    // 同じアドレスの 2 回目の load は cache から同じ値を返し、メモリからは読まない
+    // 競合した後は、cache 済みのアドレスの load も None を返す
+    #[test]
+    fn repeated_loads_hit_the_read_cache() {
+        let stm = STM::new().with_read_heat();
+        stm.write_transaction(|tr| {
+            store!(tr, 0, [7; STRIPE_SIZE]);
+            STMResult::Ok(())
+        }).unwrap();
+        let runs = Cell::new(0);
+        let pair = stm.read_transaction(|tr| {
+            runs.set(runs.get() + 1);
+            let first = load!(tr, 0);
+            let second = load!(tr, 0);
+            assert!(tr.cache.contains_key(&0));
+            if runs.get() == 1 {
+                stm.write_transaction(|other| {
+                    store!(other, 8, [1; STRIPE_SIZE]);
+                    STMResult::Ok(())
+                });
+                assert_eq!(tr.load(8), None);
+                assert_eq!(tr.load(0), None);
+            }
+            STMResult::Ok((first, second))
+        });
+        assert_eq!(pair, Some(([7; STRIPE_SIZE], [7; STRIPE_SIZE])));
+        // 競合した実行と再実行で 1 回ずつ
+        assert_eq!(stm.read_heat().into_iter().find(|(addr, _)| *addr == 0), Some((0, 2)));
+    }
+
+    // 同じアドレスに対する load_into の結果は load と一致する
+    #[test]
+    fn load_into_matches_load() {
+        let initial: Vec<u8> = (0..MEM_SIZE).map(|i| (i * 7) as u8).collect();
+        let stm = STM::from_bytes(initial).unwrap();
+        let matched = stm.read_transaction(|tr| {
+            for addr in (0..MEM_SIZE).step_by(STRIPE_SIZE) {
+                let mut buf = [0xff; STRIPE_SIZE];
+                if tr.load_into(addr, &mut buf).is_none() {
+                    return STMResult::Retry;
+                }
+                if Some(buf) != tr.load(addr) {
+                    return STMResult::Ok(false);
+                }
+            }
+            STMResult::Ok(true)
+        });
+        assert_eq!(matched, Some(true));
+    }
+
+    // commit したストライプの version は、書き込んでいないストライプの version を超える
+    #[test]
+    fn version_vector_reflects_commits() {
+        let stm = STM::new();
+        for addr in [0, 2 * STRIPE_SIZE] {
+            stm.write_transaction(|tr| {
+                store!(tr, addr, [1; STRIPE_SIZE]);
+                STMResult::Ok(())
+            }).unwrap();
         }
+        let versions = stm.version_vector();
+        assert_eq!(versions.len(), MEM_SIZE / STRIPE_SIZE);
+        let untouched = versions.iter().enumerate().filter(|(i, _)| *i != 0 && *i != 2).map(|(_, v)| *v).max().unwrap();
+        assert!(versions[0] > untouched && versions[2] > untouched, "{:?}", versions);
+        assert!(versions[2] > versions[0]);
     }
-}
 
-pub enum STMResult<T> {
-    Ok(T),
-    Retry,
-    Abort,
-}
+    // 購読したストライプへの他スレッドからの commit は、新しい値と version とともに通知され、
+    // 購読していないストライプへの commit は通知されない。drop すると購読は解除される
+    #[test]
+    fn subscription_receives_committed_bytes() {
+        let stm = STM::new();
+        let sub = stm.subscribe(&[8]);
+        let version = std::thread::scope(|s| s.spawn(|| {
+            stm.write_transaction_versioned(|tr| {
+                store!(tr, 0, [1; STRIPE_SIZE]);
+                STMResult::Ok(())
+            }).unwrap();
+            stm.write_transaction_versioned(|tr| {
+                store!(tr, 8, [2; STRIPE_SIZE]);
+                STMResult::Ok(())
+            }).unwrap().1
+        }).join().unwrap());
+        let event = sub.recv_timeout(std::time::Duration::from_secs(1)).unwrap();
+        assert_eq!(event, ChangeEvent { addr: 8, version, bytes: [2; STRIPE_SIZE] });
+        assert!(sub.try_recv().is_err());
+        drop(sub);
+        assert_eq!(stm.subscriptions(), 0);
+    }
 
-pub struct STM {
-    mem: UnsafeCell<Memory>
-}
+    // panic した実行の書き込みは破棄され、panic しない closure は write_transaction と同じ結果を返す
+    #[test]
+    fn write_transaction_catch_turns_panics_into_aborts() {
+        let stm = STM::new();
+        let result = stm.write_transaction_catch(|tr| -> STMResult<()> {
+            store!(tr, 0, [1; STRIPE_SIZE]);
+            panic!("bug in {}", "closure");
+        });
+        assert_eq!(result.unwrap_err().message(), Some("bug in closure"));
+        assert_eq!(stm.read_transaction(|tr| STMResult::Ok(load!(tr, 0))), Some([0; STRIPE_SIZE]));
 
-unsafe impl Sync for STM {}
-unsafe impl Send for STM {}
+        let result = stm.write_transaction_catch(|tr| {
+            store!(tr, 0, [2; STRIPE_SIZE]);
+            STMResult::Ok(5)
+        });
+        assert_eq!(result.ok(), Some(Some(5)));
+        assert_eq!(stm.read_transaction(|tr| STMResult::Ok(load!(tr, 0))), Some([2; STRIPE_SIZE]));
+    }
 
-impl STM {
-    pub fn new() -> Self {
-        STM {mem: UnsafeCell::new(Memory::new())}
+    // 書き込みと並行に読んでも、MonotonicReader が返すスナップショットの version と値は後戻りしない
+    #[test]
+    fn monotonic_reader_never_goes_back() {
+        const N: u64 = if cfg!(miri) { 20 } else { 2000 };
+        let stm = STM::new();
+        let reader = stm.monotonic_reader();
+        std::thread::scope(|s| {
+            s.spawn(|| for _ in 0..N {
+                stm.write_transaction(|tr| {
+                    let v = u64::from_le_bytes(load!(tr, 0));
+                    store!(tr, 0, (v + 1).to_le_bytes());
+                    STMResult::Ok(())
+                }).unwrap();
+            });
+            for _ in 0..2 {
+                s.spawn(|| {
+                    let (mut version, mut value) = (0, 0);
+                    for _ in 0..N {
+                        let Some(v) = reader.read(|tr| STMResult::Ok(u64::from_le_bytes(load!(tr, 0)))) else { continue };
+                        assert!(reader.last_version() >= version && v >= value);
+                        (version, value) = (reader.last_version(), v);
+                    }
+                });
+            }
+        });
+        assert_eq!(reader.read(|tr| STMResult::Ok(u64::from_le_bytes(load!(tr, 0)))), Some(N));
+        assert_eq!(reader.last_version(), stm.global_version());
     }
 
-    pub fn read_transaction<F, R>(&self, f: F) -> Option<R> 
-    where F: Fn(&mut ReadTrans) -> STMResult<R> {
-        loop {
-            let mut read_trans = ReadTrans::new(unsafe {&*self.mem.get()});     // 排他的でないメモリの参照を与える
+    // 範囲が重なる copy_within も、全ての読み込みを先に行うため memmove と同じ結果になる
+    #[test]
+    fn copy_within_handles_overlapping_ranges() {
+        let initial = stripes(&[1, 2, 3, 4]);
+        // (src, dst, len, 先頭 4 ストライプの期待値)
+        let cases = [
+            (0, STRIPE_SIZE, 3 * STRIPE_SIZE, [1, 1, 2, 3]),
+            (STRIPE_SIZE, 0, 3 * STRIPE_SIZE, [2, 3, 4, 4]),
+            (0, 0, 4 * STRIPE_SIZE, [1, 2, 3, 4]),
+        ];
+        for (src, dst, len, expected) in cases {
+            let stm = STM::new();
+            let (inside, after) = staged(|tr| {
+                tr.store_bytes(0, &initial);
+                tr.copy_within(src, dst, len).unwrap();
+            });
+            assert_eq!((inside, after), (expected, expected), "copy_within({}, {}, {})", src, dst, len);
+            // commit 済みの値からのコピー
+            stm.write_transaction(|tr| {
+                tr.store_bytes(0, &initial);
+                STMResult::Ok(())
+            }).unwrap();
+            stm.write_transaction(|tr| match tr.copy_within(src, dst, len) {
+                Some(()) => STMResult::Ok(()),
+                None => STMResult::Retry,
+            }).unwrap();
+            assert_eq!(read_first_bytes(&stm), Some(expected));
+        }
+    }
 
-            // 投機的実行
-            match f(&mut read_trans) {
-                STMResult::Abort => return None,
-                STMResult::Retry => {
-                    if read_trans.conflict {
-                        continue;       // retry
-                    } else {
-                        return None;
+    // 2 つのストライプの読み込みの間に commit が入った場合: Linearizable と Snapshot は retry して同じ時点の値を返し、
+    // Eventual は retry せずに異なる時点の値の組み合わせを返す
+    #[test]
+    fn read_consistency_levels_under_interleaved_commit() {
+        for (consistency, expected_runs, expected) in [
+            (ReadConsistency::Linearizable, 2, (1, 1)),
+            (ReadConsistency::Snapshot, 2, (1, 1)),
+            (ReadConsistency::Eventual, 1, (0, 1)),
+        ] {
+            let stm = STM::new();
+            let runs = Cell::new(0);
+            let result = stm.read_transaction_with(consistency, |tr| {
+                runs.set(runs.get() + 1);
+                let a = u64::from_le_bytes(load!(tr, 0));
+                if runs.get() == 1 {
+                    stm.write_transaction(|other| {
+                        store!(other, 0, 1u64.to_le_bytes());
+                        store!(other, 8, 1u64.to_le_bytes());
+                        STMResult::Ok(())
+                    });
+                }
+                STMResult::Ok((a, u64::from_le_bytes(load!(tr, 8))))
+            });
+            assert_eq!((runs.get(), result), (expected_runs, Some(expected)), "{:?}", consistency);
+        }
+    }
+
+    // 2 つのストライプに常に同じ値を書き込む writer と並行に読んでも、Linearizable の読み込みは不一致を観測しない
+    #[test]
+    fn linearizable_reads_stay_consistent_under_contention() {
+        const N: u64 = if cfg!(miri) { 20 } else { 2000 };
+        let stm = STM::new();
+        std::thread::scope(|s| {
+            s.spawn(|| for i in 1..=N {
+                stm.write_transaction(|tr| {
+                    store!(tr, 0, i.to_le_bytes());
+                    store!(tr, 8, i.to_le_bytes());
+                    STMResult::Ok(())
+                }).unwrap();
+            });
+            s.spawn(|| for _ in 0..N {
+                let Some((a, b)) = stm.read_transaction_with(ReadConsistency::Linearizable, |tr| match pair(tr) {
+                    Some(p) => STMResult::Ok(p),
+                    None => STMResult::Retry,
+                }) else { continue };
+                assert_eq!(a, b);
+            });
+        });
+    }
+
+    // 16 byte の store_bytes の前半だけを store で上書きすると、span_check が有効な場合は panic し、何も commit されない
+    // 無効な場合はストライプごとの後勝ちで commit される
+    #[test]
+    fn span_check_catches_overwrite_of_first_half() {
+        let overwrite_first_half = |tr: &mut WriteTrans<'_>| {
+            tr.store_bytes(0, &[1; 2 * STRIPE_SIZE]);
+            tr.store(0, [2; STRIPE_SIZE]);
+            STMResult::Ok(())
+        };
+        let stm = STM::new().with_span_check(true);
+        let err = stm.write_transaction_catch(overwrite_first_half).unwrap_err();
+        assert!(err.message().unwrap().starts_with("store to [0x0, 0x8) partially overwrites"), "{:?}", err.message());
+        assert_eq!(read_first_bytes(&stm), Some([0; 4]));
+
+        let stm = STM::new();
+        stm.write_transaction(overwrite_first_half).unwrap();
+        assert_eq!(read_first_bytes(&stm), Some([2, 1, 0, 0]));
+    }
+
+    // 4 つのストライプに同じ値を書き込む writer と並行に、借用したスナップショットの checksum を計算する
+    // checksum は常に 1 つの commit の状態 (4 * 値) に一致する
+    #[test]
+    fn read_snapshot_checksum_is_from_one_state() {
+        const N: u64 = if cfg!(miri) { 20 } else { 2000 };
+        let stm = STM::new();
+        let addrs: Vec<usize> = (0..4).map(|i| i * STRIPE_SIZE).collect();
+        std::thread::scope(|s| {
+            s.spawn(|| for i in 1..=N {
+                stm.write_transaction(|tr| {
+                    for addr in &addrs {
+                        store!(tr, *addr, i.to_le_bytes());
                     }
-                },
-                STMResult::Ok(val) => {
-                    if read_trans.conflict {
-                        continue;
-                    } else {
-                        return Some(val);
+                    STMResult::Ok(())
+                }).unwrap();
+            });
+            s.spawn(|| for _ in 0..N {
+                let mut buffer = [[0; STRIPE_SIZE]; 4];
+                let Some((sum, first)) = stm.with_read_snapshot(&addrs, &mut buffer, |snapshot| {
+                    let sum: u64 = snapshot.iter().map(|stripe| u64::from_le_bytes(*stripe)).sum();
+                    (sum, u64::from_le_bytes(snapshot[0]))
+                }) else { continue };
+                assert_eq!(sum, 4 * first);
+            });
+        });
+        let mut buffer = [[0; STRIPE_SIZE]; 8];
+        assert_eq!(stm.with_read_snapshot(&addrs, &mut buffer, |snapshot| snapshot.len()), Some(4));
+    }
+
+    // 箸を拾う closure の dry run: 2 つの load と、両方とも置かれていれば 2 つの store を記録し、メモリは変更しない
+    #[test]
+    fn dry_run_records_chopstick_pickup() {
+        let stm = STM::new();
+        let pick_chopsticks = |tr: &mut WriteTrans<'_>| {
+            let mut left = load!(tr, 0);
+            let mut right = load!(tr, 8);
+            if left[0] != 0 || right[0] != 0 {
+                return STMResult::RetryOk;
+            }
+            left[0] = 1;
+            right[0] = 1;
+            store!(tr, 0, left);
+            store!(tr, 8, right);
+            STMResult::Ok(())
+        };
+        let mut picked = [0; STRIPE_SIZE];
+        picked[0] = 1;
+        let (result, ops) = stm.write_transaction_dry_run(pick_chopsticks);
+        assert_eq!(result, Some(()));
+        assert_eq!(ops, [
+            Operation::Load { addr: 0 },
+            Operation::Load { addr: 8 },
+            Operation::Store { addr: 0, bytes: picked },
+            Operation::Store { addr: 8, bytes: picked },
+        ]);
+        assert_eq!(read_first_bytes(&stm), Some([0; 4]));
+        assert_eq!(stm.global_version(), 0);
+
+        // 箸が拾われている場合は load のみ
+        stm.write_transaction(|tr| {
+            store!(tr, 8, picked);
+            STMResult::Ok(())
+        }).unwrap();
+        let (result, ops) = stm.write_transaction_dry_run(pick_chopsticks);
+        assert_eq!(result, None);
+        assert_eq!(ops, [Operation::Load { addr: 0 }, Operation::Load { addr: 8 }]);
+    }
+
+    // 一方の値だけが expected と一致する multi_cas は何も stage せずに Some(false) を返し、両方一致すれば両方を書き込む
+    #[test]
+    fn multi_cas_is_all_or_nothing() {
+        let stm = STM::new();
+        stm.write_transaction(|tr| {
+            store!(tr, 8, [1; STRIPE_SIZE]);
+            STMResult::Ok(())
+        }).unwrap();
+        let version = stm.global_version();
+        let swapped = stm.write_transaction(|tr| {
+            let swapped = tr.multi_cas(&[(0, [0; STRIPE_SIZE], [5; STRIPE_SIZE]), (8, [0; STRIPE_SIZE], [5; STRIPE_SIZE])]);
+            assert!(tr.write_set.is_empty());
+            STMResult::Ok(swapped)
+        });
+        assert_eq!(swapped, Some(Some(false)));
+        assert_eq!(stm.global_version(), version);
+        assert_eq!(read_first_bytes(&stm), Some([0, 1, 0, 0]));
+
+        let swapped = stm.write_transaction(|tr| {
+            STMResult::Ok(tr.multi_cas(&[(0, [0; STRIPE_SIZE], [5; STRIPE_SIZE]), (8, [1; STRIPE_SIZE], [5; STRIPE_SIZE])]))
+        });
+        assert_eq!(swapped, Some(Some(true)));
+        assert_eq!(read_first_bytes(&stm), Some([5, 5, 0, 0]));
+    }
+
+    // strict_init では一度も commit されていないストライプの読み込みは Uninitialized になり、既定では 0 が読める
+    // commit した後は strict_init でも読める
+    #[test]
+    fn strict_init_rejects_loads_of_unstored_stripes() {
+        let strict = STM::new().with_strict_init(true);
+        assert_eq!(strict.read_transaction(|tr| STMResult::Ok(tr.try_load(0))), Some(Err(LoadError::Uninitialized)));
+        assert_eq!(strict.write_transaction(|tr| STMResult::Ok(tr.try_load(0))), Some(Err(LoadError::Uninitialized)));
+        strict.write_transaction(|tr| {
+            store!(tr, 0, [3; STRIPE_SIZE]);
+            STMResult::Ok(())
+        }).unwrap();
+        assert_eq!(strict.read_transaction(|tr| STMResult::Ok(tr.try_load(0))), Some(Ok([3; STRIPE_SIZE])));
+        assert_eq!(strict.read_transaction(|tr| STMResult::Ok(tr.load(8))), Some(None));
+
+        let lenient = STM::new();
+        assert_eq!(lenient.read_transaction(|tr| STMResult::Ok(tr.try_load(0))), Some(Ok([0; STRIPE_SIZE])));
+    }
+
+    // atomically で合成した 2 つの store は一緒に commit され、途中の op が Abort すると後続の op も finalize も実行されない
+    #[test]
+    fn atomically_commits_composed_ops_together() {
+        let stm = STM::new();
+        let store_to = |addr: usize, tag: u8| -> TxOp {
+            Box::new(move |tr| {
+                store!(tr, addr, [tag; STRIPE_SIZE]);
+                STMResult::Ok(())
+            })
+        };
+        let total = stm.atomically(vec![store_to(0, 1), store_to(8, 2)], |tr| {
+            STMResult::Ok(load!(tr, 0)[0] + load!(tr, 8)[0])
+        });
+        assert_eq!(total, Some(3));
+        assert_eq!(read_first_bytes(&stm), Some([1, 2, 0, 0]));
+
+        let finalized = Cell::new(false);
+        let abort: TxOp = Box::new(|_| STMResult::Abort);
+        let result = stm.atomically(vec![store_to(0, 7), abort, store_to(8, 7)], |_| {
+            finalized.set(true);
+            STMResult::Ok(())
+        });
+        assert_eq!(result, None);
+        assert!(!finalized.get());
+        assert_eq!(read_first_bytes(&stm), Some([1, 2, 0, 0]));
+    }
+
+    // 他のスレッドが commit したストライプの last_writer はそのスレッドの writer_id になる
+    // 書き込んでいないストライプ、記録を有効にしていない STM では 0
+    #[test]
+    fn last_writer_records_committing_thread() {
+        let stm = STM::new().with_last_writer();
+        let id = std::thread::scope(|s| s.spawn(|| {
+            stm.write_transaction(|tr| {
+                store!(tr, 8, [1; STRIPE_SIZE]);
+                STMResult::Ok(())
+            }).unwrap();
+            writer_id()
+        }).join().unwrap());
+        assert_ne!(id, writer_id());
+        assert_eq!(stm.last_writer(8), id);
+        assert_eq!(stm.last_writer(0), 0);
+
+        let untracked = STM::new();
+        untracked.write_transaction(|tr| {
+            store!(tr, 8, [1; STRIPE_SIZE]);
+            STMResult::Ok(())
+        }).unwrap();
+        assert_eq!(untracked.last_writer(8), 0);
+    }
+
+    // 条件 (ストライプ 0 が 0 でない) が満たされるまで待つ retry_until は、どの WaitPolicy でも最終的に条件を観測する
+    // Block は読み込んだストライプへの commit まで park するため、待っている間に closure を繰り返し実行しない
+    #[test]
+    fn retry_until_observes_the_condition_with_each_policy() {
+        for policy in [WaitPolicy::Spin, WaitPolicy::SpinThenYield { spins: 4 }, WaitPolicy::Block] {
+            let stm = STM::new();
+            let runs = AtomicUsize::new(0);
+            let observed = std::thread::scope(|s| {
+                let waiter = s.spawn(|| stm.retry_until(|tr| {
+                    runs.fetch_add(1, Relaxed);
+                    match load!(tr, 0)[0] {
+                        0 => STMResult::RetryOk,
+                        v => {
+                            store!(tr, 0, [0; STRIPE_SIZE]);
+                            STMResult::Ok(v)
+                        }
                     }
+                }, policy));
+                while runs.load(Relaxed) == 0 {
+                    thread::yield_now();
+                }
+                thread::sleep(Duration::from_millis(20));
+                // 読み込んでいないストライプへの commit では Block の待機は起こされない
+                stm.write_transaction(|tr| {
+                    store!(tr, 8, [1; STRIPE_SIZE]);
+                    STMResult::Ok(())
+                }).unwrap();
+                if policy == WaitPolicy::Block {
+                    assert!(runs.load(Relaxed) <= 2, "Block re-ran the closure {} times while waiting", runs.load(Relaxed));
                 }
+                stm.write_transaction(|tr| {
+                    store!(tr, 0, [9; STRIPE_SIZE]);
+                    STMResult::Ok(())
+                }).unwrap();
+                waiter.join().unwrap()
+            });
+            assert_eq!(observed, Some(9), "{:?}", policy);
+            if policy == WaitPolicy::Block {
+                assert!(runs.load(Relaxed) <= 3, "{}", runs.load(Relaxed));
             }
         }
     }
 
-    pub fn write_transaction<F, R>(&self, f: F) -> Option<R>
-    where F: Fn(&mut WriteTrans) -> STMResult<R> {
-        loop {
-            let mut write_trans = WriteTrans::new(unsafe {&mut *self.mem.get()});   // 排他的でないメモリの参照を与える
+    // 事前に作成した write_set を apply で commit し、書き込んだ値を読み出す
+    #[test]
+    fn apply_commits_prepared_write_set() {
+        let stm = STM::new();
+        let write_set = HashMap::from([(0, 1u64.to_le_bytes()), (16, 2u64.to_le_bytes())]);
+        assert_eq!(stm.apply(write_set, HashSet::new(), stm.global_version()), ApplyOutcome::Committed(1));
+        assert_eq!(stm.read_raw(0), 1u64.to_le_bytes());
+        assert_eq!(stm.read_raw(16), 2u64.to_le_bytes());
+        assert_eq!(stm.version_vector()[2], 1);
+    }
 
-            // 投機的実行
-            let result;
-            match f(&mut write_trans) {
-                STMResult::Abort => return None,
-                STMResult::Retry => {
-                    if write_trans.conflict {
-                        continue;
-                    } else {
-                        return None;
+    // read_set のストライプが expected_version より後に更新されていれば、書き込みの有無にかかわらず Conflict となる
+    #[test]
+    fn apply_rejects_read_set_modified_after_expected_version() {
+        let stm = STM::new();
+        let expected_version = stm.global_version();
+        stm.write_transaction(|tr| {
+            store!(tr, 8, 5u64.to_le_bytes());
+            STMResult::Ok(())
+        });
+
+        let write_set = HashMap::from([(0, 1u64.to_le_bytes())]);
+        assert_eq!(stm.apply(write_set, HashSet::from([8]), expected_version), ApplyOutcome::Conflict { addr: Some(8) });
+        assert_eq!(stm.read_raw(0), [0; 8]);
+        // 書き込みのない (読み込みだけの) apply も検証する
+        assert_eq!(stm.apply(HashMap::new(), HashSet::from([8]), expected_version), ApplyOutcome::Conflict { addr: Some(8) });
+        // 更新後の version を指定すれば commit できる
+        let current = stm.global_version();
+        assert_eq!(stm.apply(HashMap::new(), HashSet::from([8]), current), ApplyOutcome::Committed(current));
+    }
+
+    // prefault したメモリでも通常どおり書き込み、読み出せる
+    #[test]
+    fn prefaulted_stm_reads_and_writes() {
+        let stm = STM::new().with_prefault(true);
+        stm.write_transaction(|tr| {
+            store!(tr, 0, 3u64.to_le_bytes());
+            store!(tr, MEM_SIZE - STRIPE_SIZE, 4u64.to_le_bytes());
+            STMResult::Ok(())
+        }).unwrap();
+        let read = stm.read_transaction(|tr| {
+            STMResult::Ok((u64::from_le_bytes(load!(tr, 0)), u64::from_le_bytes(load!(tr, MEM_SIZE - STRIPE_SIZE))))
+        });
+        assert_eq!(read, Some((3, 4)));
+    }
+
+    // bit の添字の範囲は MEM_SIZE ではなく、実行時に指定したデータ本体の大きさで決まる
+    #[test]
+    fn bit_range_follows_runtime_heap_size() {
+        let large = STM::from_memory(Memory::<STRIPE_SIZE>::with_capacity(2 * MEM_SIZE).unwrap());
+        let bit = MEM_SIZE * 8 + 3;
+        assert_eq!(large.write_transaction(|tr| STMResult::Ok(tr.test_and_set_bit(bit))), Some(Some(false)));
+        assert_eq!(large.read_raw(MEM_SIZE)[0], 1 << 3);
+
+        let small = STM::from_memory(Memory::<STRIPE_SIZE>::with_capacity(64).unwrap());
+        let out_of_range = panic::catch_unwind(AssertUnwindSafe(|| {
+            small.write_transaction(|tr| STMResult::Ok(tr.test_and_set_bit(64 * 8)))
+        }));
+        assert!(out_of_range.is_err(), "a bit beyond a small heap must be rejected");
+    }
+
+    // with_capacity で MEM_SIZE より大きくしたデータ本体でも、全てのストライプを 1 つのスナップショットとして読める
+    #[test]
+    fn read_snapshot_covers_a_runtime_sized_heap() {
+        let stm = STM::from_memory(Memory::<STRIPE_SIZE>::with_capacity(2 * MEM_SIZE).unwrap());
+        let last = 2 * MEM_SIZE - STRIPE_SIZE;
+        stm.write_transaction(|tr| {
+            store!(tr, last, 7u64.to_le_bytes());
+            STMResult::Ok(())
+        }).unwrap();
+        let addrs: Vec<usize> = (0..2 * MEM_SIZE).step_by(STRIPE_SIZE).collect();
+        let mut buffer = vec![[0; STRIPE_SIZE]; addrs.len()];
+        let sum = stm.with_read_snapshot(&addrs, &mut buffer, |snapshot| snapshot.iter().map(|stripe| u64::from_le_bytes(*stripe)).sum::<u64>());
+        assert_eq!(sum, Some(7));
+    }
+
+    // from == to の move_if は panic せず、値も変えずに cond の結果だけを返す
+    #[test]
+    fn move_if_onto_itself_is_a_no_op() {
+        let stm = STM::new();
+        stm.write_transaction(|tr| {
+            store!(tr, 0, 5u64.to_le_bytes());
+            STMResult::Ok(())
+        }).unwrap();
+        let version = stm.global_version();
+        let moved = stm.write_transaction(|tr| match (tr.move_if(0, 0, |v| v[0] == 5), tr.move_if(0, 0, |v| v[0] == 6)) {
+            (Some(held), Some(not_held)) => STMResult::Ok((held, not_held, tr.write_addresses().count())),
+            _ => STMResult::Retry,
+        });
+        assert_eq!(moved, Some((true, false, 0)));
+        assert_eq!(stm.read_raw(0), 5u64.to_le_bytes());
+        assert_eq!(stm.global_version(), version);
+    }
+
+    // poison された STM はトランザクションを実行せず、clear_poison の後は再び実行できる
+    #[test]
+    fn poisoned_stm_runs_no_transactions() {
+        fn increment(tr: &mut WriteTrans<'_>) -> STMResult<u64> {
+            let v = u64::from_le_bytes(load!(tr, 0)) + 1;
+            store!(tr, 0, v.to_le_bytes());
+            STMResult::Ok(v)
+        }
+
+        fn read(tr: &mut ReadTrans<'_>) -> STMResult<u64> {
+            STMResult::Ok(u64::from_le_bytes(load!(tr, 0)))
+        }
+
+        let stm = STM::new();
+        assert_eq!(stm.try_write_transaction(increment), Ok(Some(1)));
+        assert!(!stm.is_poisoned());
+
+        stm.poison();
+        assert!(stm.is_poisoned());
+        assert_eq!(stm.try_write_transaction(increment), Err(Poisoned));
+        assert_eq!(stm.try_read_transaction(read), Err(Poisoned));
+        assert_eq!(stm.write_transaction(increment), None);
+        assert_eq!(stm.read_transaction(read), None);
+        let write_set = HashMap::from([(0, 100u64.to_le_bytes())]);
+        assert_eq!(stm.apply(write_set, HashSet::new(), stm.global_version()), ApplyOutcome::Poisoned);
+
+        // poison されている間はメモリを変更していない
+        assert_eq!(u64::from_le_bytes(stm.read_raw(0)), 1);
+
+        stm.clear_poison();
+        assert_eq!(stm.try_write_transaction(increment), Ok(Some(2)));
+        assert_eq!(stm.try_read_transaction(read), Ok(Some(2)));
+    }
+
+    // fold_range の合計は、送金を続ける writer と並行でも常に 1 つのスナップショットのもの
+    #[test]
+    fn sums_come_from_consistent_snapshots() {
+        const STRIPES: usize = MEM_SIZE / STRIPE_SIZE;
+        const WRITERS: usize = 3;
+        const OBSERVATIONS: usize = 200;
+
+        let initial: Vec<u8> = (0..STRIPES).flat_map(|i| (i as u64 * 10).to_le_bytes()).collect();
+        let expected: u64 = (0..STRIPES as u64).map(|i| i * 10).sum();
+        let stm = STM::from_bytes(initial).unwrap();
+        let done = AtomicBool::new(false);
+
+        thread::scope(|s| {
+            let writers: Vec<_> = (0..WRITERS).map(|w| {
+                let (stm, done) = (&stm, &done);
+                s.spawn(move || {
+                    let mut i: usize = 0;
+                    while !done.load(Relaxed) {
+                        i += 1;
+                        let from = (i * 7 + w) % STRIPES * STRIPE_SIZE;
+                        let to = (i * 13 + w + 1) % STRIPES * STRIPE_SIZE;
+                        if from == to {
+                            continue;
+                        }
+                        stm.write_transaction(|tr| {
+                            let a = u64::from_le_bytes(load!(tr, from));
+                            let b = u64::from_le_bytes(load!(tr, to));
+                            let amount = a.min(3);
+                            store!(tr, from, (a - amount).to_le_bytes());
+                            store!(tr, to, (b + amount).to_le_bytes());
+                            STMResult::Ok(())
+                        });
                     }
-                }
-                STMResult::Ok(val) => {
-                    if write_trans.conflict {
-                        continue;
-                    } else {
-                        result = val;
+                })
+            }).collect();
+
+            for _ in 0..OBSERVATIONS {
+                let total = stm.read_transaction(|tr| {
+                    match tr.fold_range(0..MEM_SIZE, STRIPE_SIZE, 0u64, |acc, s| acc + u64::from_le_bytes(s)) {
+                        Some(total) => STMResult::Ok(total),
+                        None => STMResult::Retry,
                     }
+                }).unwrap();
+                assert_eq!(total, expected, "fold_range observed an inconsistent snapshot");
+                thread::yield_now();    // writer に実行の機会を与える
+            }
+            done.store(true, Relaxed);
+            for w in writers {
+                w.join().unwrap();
+            }
+        });
+    }
+
+    // Loadable / Storable に対する checksum は読み込み・書き込みトランザクションで一致する
+    #[test]
+    fn checksum_agrees_across_transaction_kinds() {
+        const STRIPES: usize = 16;
+
+        // ストライプ 0..STRIPES の checksum (load! は Loadable に対しても使える)
+        fn checksum(tr: &mut impl Loadable) -> STMResult<u64> {
+            let mut sum = 0u64;
+            for i in 0..STRIPES {
+                let v = u64::from_le_bytes(load!(tr, i * STRIPE_SIZE));
+                sum = sum.rotate_left(5) ^ v;
+            }
+            STMResult::Ok(sum)
+        }
+
+        fn fill(tr: &mut impl Storable, seed: u64) {
+            for i in 0..STRIPES {
+                store!(tr, i * STRIPE_SIZE, (seed * (i as u64 + 1)).to_le_bytes());
+            }
+        }
+
+        let stm = STM::new();
+        stm.write_transaction(|tr| {
+            fill(tr, 7);
+            STMResult::Ok(())
+        });
+
+        let read = stm.read_transaction(|tr| checksum(tr)).unwrap();
+        let write = stm.write_transaction(|tr| checksum(tr)).unwrap();
+        assert_eq!(read, write);
+
+        // 書き込みトランザクション内では、まだ commit していない store も checksum に反映される
+        let (before, after) = stm.write_transaction(|tr| {
+            let STMResult::Ok(before) = checksum(tr) else {
+                return STMResult::Retry;
+            };
+            fill(tr, 11);
+            let STMResult::Ok(after) = checksum(tr) else {
+                return STMResult::Retry;
+            };
+            STMResult::Ok((before, after))
+        }).unwrap();
+        assert_eq!(before, read);
+        assert_ne!(after, before);
+        assert_eq!(stm.read_transaction(|tr| checksum(tr)).unwrap(), after);
+    }
+
+    // with_capacity は 2^n でない大きさやストライプより小さい大きさを拒否し、MEM_SIZE と異なる大きさでもトランザクションを実行できる
+    #[test]
+    fn runtime_sized_memories() {
+        for size in [0, 4, 24, 100, 513] {
+            let err = Memory::<8>::with_capacity(size).err();
+            assert_eq!(err, Some(MemoryError::InvalidCapacity { size, stripe_size: STRIPE_SIZE }));
+        }
+        // S が大きい場合は S 未満を拒否する
+        assert!(Memory::<16>::with_capacity(8).is_err());
+
+        for size in [64, 2048] {
+            let mem: Memory = Memory::with_capacity(size).unwrap();
+            assert_eq!(mem.size(), size);
+            let stm = STM::from_memory(mem);
+            assert_eq!(stm.version_vector().len(), size / STRIPE_SIZE);
+
+            let last = size - STRIPE_SIZE;
+            stm.write_transaction(|tr| {
+                store!(tr, 0, 1u64.to_le_bytes());
+                store!(tr, last, 2u64.to_le_bytes());
+                STMResult::Ok(())
+            });
+            let sum = stm.read_transaction(|tr| {
+                let a = u64::from_le_bytes(load!(tr, 0));
+                let b = u64::from_le_bytes(load!(tr, last));
+                STMResult::Ok(a + b)
+            }).unwrap();
+            assert_eq!(sum, 3);
+        }
+    }
+
+    // reset_stripe したストライプは未初期化として読まれ、後の store で初期化済みに戻る
+    #[test]
+    fn reset_stripes_read_as_uninitialized() {
+        let stm = STM::new().with_strict_init(true);
+        stm.write_transaction(|tr| {
+            store!(tr, 0, 7u64.to_le_bytes());
+            store!(tr, 8, 9u64.to_le_bytes());
+            STMResult::Ok(())
+        });
+        let read = |addr| stm.read_transaction(|tr| STMResult::Ok(tr.try_load(addr))).unwrap();
+        assert_eq!(read(0), Ok(7u64.to_le_bytes()));
+
+        // 解放して消去する: 同じトランザクション内でも以降の読み込みは未初期化
+        stm.write_transaction(|tr| {
+            let _ = load!(tr, 0);
+            tr.reset_stripe(0);
+            assert_eq!(tr.try_load(0), Err(LoadError::Uninitialized));
+            STMResult::Ok(())
+        });
+        assert_eq!(read(0), Err(LoadError::Uninitialized));
+        assert_eq!(stm.read_raw(0), [0; 8], "a reset stripe must read back as zeros");
+        assert_eq!(read(8), Ok(9u64.to_le_bytes()), "other stripes are unaffected");
+
+        // reset の後の store は reset を取り消す
+        stm.write_transaction(|tr| {
+            tr.reset_stripe(8);
+            store!(tr, 8, 11u64.to_le_bytes());
+            STMResult::Ok(())
+        });
+        assert_eq!(read(8), Ok(11u64.to_le_bytes()));
+
+        // 再び store すれば初期化済みに戻る
+        stm.write_transaction(|tr| {
+            store!(tr, 0, 1u64.to_le_bytes());
+            STMResult::Ok(())
+        });
+        assert_eq!(read(0), Ok(1u64.to_le_bytes()));
+    }
+
+    // CommitOrder::AddressAscending ではアドレスの昇順に書き込み、既定の順序でも書き込む内容は同じ
+    #[test]
+    fn address_ascending_order_writes_stripes_in_order() {
+        const STRIPES: usize = 16;
+
+        fn run(stm: &STM) -> Vec<usize> {
+            let (_, audit) = stm.write_transaction_audit(|tr| {
+                for i in 0..STRIPES {
+                    let addr = (i * 7) % STRIPES * STRIPE_SIZE;     // 0, 56, 112, ... (アドレス順ではない)
+                    store!(tr, addr, (i as u64).to_le_bytes());
                 }
+                STMResult::Ok(())
+            }).unwrap();
+            audit.into_iter().map(|(addr, _, _)| addr).collect()
+        }
+
+        let ascending: Vec<usize> = (0..STRIPES).map(|i| i * STRIPE_SIZE).collect();
+
+        let stm = STM::new().with_commit_order(CommitOrder::AddressAscending);
+        for _ in 0..3 {
+            assert_eq!(run(&stm), ascending);
+        }
+
+        let stm_default = STM::builder().commit_order(CommitOrder::Unspecified).build();
+        let mut written = run(&stm_default);
+        written.sort_unstable();
+        assert_eq!(written, ascending);
+        for addr in ascending {
+            assert_eq!(stm.read_raw(addr), stm_default.read_raw(addr));
+        }
+    }
+
+    // Bytes / Words のどちらのデータ本体も cache line 境界から始まり、ストライプは cache line をまたがない
+    #[test]
+    fn stripes_are_cache_line_aligned() {
+        fn check<const S: usize>(mem: &Memory<S>) {
+            let base = mem.stripe_ptr(0);
+            assert!((base as usize).is_multiple_of(CACHE_LINE), "data is not cache-line aligned: {:p} (stripe size {})", base, S);
+            for addr in (0..mem.size()).step_by(S) {
+                let start = mem.stripe_ptr(addr) as usize;
+                assert_eq!(start / CACHE_LINE, (start + S - 1) / CACHE_LINE, "stripe at {} straddles a cache line", addr);
             }
+        }
 
-            // version update
-            if !write_trans.lock_write_set() {      // write lock 獲得を試みる
-                continue;                          
-            }   // 以下 write lock 獲得済み
+        check(&Memory::<8>::new());
+        check(&Memory::<4>::new());     // Bytes
+        check(&Memory::<64>::new());
+        check(&Memory::<8>::with_capacity(8).unwrap());     // cache line より小さい
+        check(&Memory::<16>::with_capacity(1 << 12).unwrap());
+        check(&Memory::<8>::from_bytes(vec![1; MEM_SIZE]).unwrap());
 
-            // version と 整合性を検証
-            let new_version = write_trans.mem.inc_global_clock();
-            if (write_trans.read_version + 1 != new_version) && !write_trans.validate_read_set() {
-                continue;
+        // with_layout で配置を変えても、ストライプ 0 はデータ本体の先頭に置かれる
+        let mem = Memory::<8>::new().with_layout(LayoutHint::new().group(&[0, 128]));
+        check(&mem);
+        assert_eq!(mem.stripe_ptr(128) as usize, mem.stripe_ptr(0) as usize + 8);
+    }
+
+    // 並行な update の加算は失われず、返り値は書き込んだ値
+    #[test]
+    fn concurrent_updates_lose_nothing() {
+        const THREADS: u64 = 4;
+        const INCREMENTS: u64 = 1000;
+        const COUNTER: usize = 0;
+
+        fn add(n: u64) -> impl Fn([u8; 8]) -> [u8; 8] {
+            move |val| (u64::from_le_bytes(val) + n).to_le_bytes()
+        }
+
+        let stm = STM::new();
+        assert_eq!(stm.update(COUNTER, add(5)), Some(5u64.to_le_bytes()));
+        assert_eq!(stm.update(COUNTER, add(0)), Some(5u64.to_le_bytes()));
+
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    let mut last = 0;
+                    for _ in 0..INCREMENTS {
+                        let new = u64::from_le_bytes(stm.update(COUNTER, add(1)).unwrap());
+                        assert!(new > last, "update returned a value older than this thread's previous write");
+                        last = new;
+                    }
+                });
+            }
+        });
+        let total = u64::from_le_bytes(stm.read_raw(COUNTER));
+        assert_eq!(total, 5 + THREADS * INCREMENTS, "an update was lost");
+    }
+
+    // drop した Subscription の登録は取り除かれ、以降の commit は通知されない
+    #[test]
+    fn dropped_subscription_is_unregistered() {
+        use std::sync::mpsc::TryRecvError;
+
+        const WATCHED: usize = 0;
+        const OTHER: usize = 8;
+
+        fn commit(stm: &STM, addr: usize, value: u64) {
+            stm.write_transaction(|tr| {
+                store!(tr, addr, value.to_le_bytes());
+                STMResult::Ok(())
+            });
+        }
+
+        let stm = STM::new();
+        let watched = stm.subscribe(&[WATCHED]);
+        let other = stm.subscribe(&[OTHER]);
+        assert_eq!(stm.subscriptions(), 2);
+
+        commit(&stm, WATCHED, 1);
+        let event = watched.try_recv().unwrap();
+        assert_eq!((event.addr, event.bytes), (WATCHED, 1u64.to_le_bytes()));
+
+        // drop した時点で登録が取り除かれる (commit を待たない)
+        drop(watched);
+        assert_eq!(stm.subscriptions(), 1);
+        commit(&stm, WATCHED, 2);
+        assert_eq!(stm.subscriptions(), 1, "a dropped subscription must not be re-registered or notified");
+        assert_eq!(other.try_recv(), Err(TryRecvError::Empty));
+
+        commit(&stm, OTHER, 3);
+        assert_eq!(other.try_recv().unwrap().bytes, 3u64.to_le_bytes());
+        drop(other);
+        assert_eq!(stm.subscriptions(), 0);
+        commit(&stm, OTHER, 4);
+    }
+
+    // checksum は同じ操作列なら一致し、既知の値と一致し、1 つのストライプの違いでも変わる
+    #[test]
+    fn checksum_matches_known_state() {
+        const STEPS: u64 = 200;
+        const EMPTY: u64 = 0x7da1_44b9_7d05_4b25;      // MEM_SIZE byte の 0 の hash
+        const EXPECTED: u64 = 0x532b_7e45_6ba3_f674;    // run の後の hash
+
+        fn run(stm: &STM) {
+            for step in 0..STEPS {
+                let addr = (step as usize * 7 % (MEM_SIZE / STRIPE_SIZE)) * STRIPE_SIZE;
+                stm.write_transaction(|tr| {
+                    store!(tr, addr, (step * step).to_le_bytes());
+                    STMResult::Ok(())
+                });
             }
+        }
+
+        assert_eq!(STM::new().heap_checksum(), Some(EMPTY));
+        assert_eq!(STM::new().with_strict_init(true).heap_checksum(), Some(EMPTY), "uninitialized stripes hash as zeros");
 
-            // commit と return result
-            write_trans.commit(new_version);
-            return Some(result);
+        let (a, b) = (STM::new(), STM::new());
+        run(&a);
+        run(&b);
+        let checksum = a.heap_checksum().unwrap();
+        assert_eq!(b.heap_checksum(), Some(checksum));
+        assert_eq!(checksum, EXPECTED);
+
+        // ストライプの値が 1 つでも異なれば checksum も異なる
+        b.write_transaction(|tr| {
+            store!(tr, MEM_SIZE - STRIPE_SIZE, 1u64.to_le_bytes());
+            STMResult::Ok(())
+        });
+        assert_ne!(b.heap_checksum(), Some(checksum));
+    }
+
+    // 頻繁に読まれるストライプが先頭に報告され、トランザクション内の再読み込みは数えない
+    #[test]
+    fn read_heat_separates_hot_reads() {
+        const CONFIG: usize = 0;    // read-hot, write-cold
+        const COUNTER: usize = 8;
+        const READS: u64 = 50;
+
+        assert!(STM::new().read_heat().is_empty(), "read heat is off by default");
+
+        let stm = STM::new().with_read_heat();
+        stm.write_transaction(|tr| {
+            store!(tr, CONFIG, 42u64.to_le_bytes());
+            STMResult::Ok(())
+        });
+        for i in 0..READS {
+            let config = stm.read_transaction(|tr| {
+                let first = load!(tr, CONFIG);
+                let again = load!(tr, CONFIG);      // cache から返すので数えない
+                assert_eq!(first, again);
+                STMResult::Ok(u64::from_le_bytes(first))
+            });
+            assert_eq!(config, Some(42));
+            if i % 10 == 0 {
+                stm.write_transaction(|tr| {
+                    let v = u64::from_le_bytes(load!(tr, COUNTER));
+                    store!(tr, COUNTER, (v + 1).to_le_bytes());
+                    STMResult::Ok(())
+                });
+            }
         }
+
+        let heat = stm.read_heat();
+        assert_eq!(heat, [(CONFIG, READS), (COUNTER, READS / 10)]);
+        assert_eq!(stm.lock_conflicts(CONFIG), 0, "a read-hot stripe need not be contended");
     }
-}
\ No newline at end of file
+}
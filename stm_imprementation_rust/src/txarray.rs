@@ -0,0 +1,114 @@
+// ストライプ上に構築した、要素数 N のトランザクショナルな配列
+// 要素 i はアドレス base + i * STRIPE_SIZE のストライプに格納する (1 要素 = 1 ストライプ)。
+// 添字は get / set のたびに検査し、範囲外であれば配列の外のアドレスを計算せずに OutOfBounds を返す
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::tl2::{Loadable, Storable, MEM_SIZE, STRIPE_SIZE};
+use crate::txmap::StripeCodec;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxArrayError {
+    Conflict,                               // 競合が発生した (トランザクションは retry される)
+    OutOfBounds { index: usize, len: usize },   // 添字が N 以上
+}
+
+impl fmt::Display for TxArrayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxArrayError::Conflict => write!(f, "transaction conflicted"),
+            TxArrayError::OutOfBounds { index, len } => write!(f, "index {} is out of bounds for an array of length {}", index, len),
+        }
+    }
+}
+
+impl std::error::Error for TxArrayError {}
+
+pub struct TxArray<const N: usize, V = [u8; STRIPE_SIZE]> {
+    base: usize,            // 要素 0 のアドレス
+    _marker: PhantomData<V>,
+}
+
+impl<const N: usize, V: StripeCodec> TxArray<N, V> {
+    // [base, base + N * STRIPE_SIZE) を使用する (他の用途と重ならないようにすること)
+    pub fn new(base: usize) -> Self {
+        assert_eq!(base & (STRIPE_SIZE - 1), 0);
+        assert!(N > 0);
+        assert!(base + N * STRIPE_SIZE <= MEM_SIZE);
+        TxArray { base, _marker: PhantomData }
+    }
+
+    pub fn len(&self) -> usize {
+        N
+    }
+
+    pub fn is_empty(&self) -> bool {
+        N == 0
+    }
+
+    // 要素 index のアドレス
+    pub fn addr(&self, index: usize) -> Result<usize, TxArrayError> {
+        if index >= N {
+            return Err(TxArrayError::OutOfBounds { index, len: N });
+        }
+        Ok(self.base + index * STRIPE_SIZE)
+    }
+
+    pub fn get<T: Loadable>(&self, tr: &mut T, index: usize) -> Result<V, TxArrayError> {
+        let addr = self.addr(index)?;
+        tr.load(addr).map(V::decode).ok_or(TxArrayError::Conflict)
+    }
+
+    pub fn set<T: Storable>(&self, tr: &mut T, index: usize, value: V) -> Result<(), TxArrayError> {
+        let addr = self.addr(index)?;
+        tr.store(addr, value.encode());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tl2::{STMResult, STM};
+
+    // 要素 i は base + i * STRIPE_SIZE に格納され、範囲外の添字は配列の外に触れずに OutOfBounds となる
+    #[test]
+    fn txarray_indexes_stripes() {
+        const BASE: usize = 64;
+
+        let stm = STM::new();
+        let array: TxArray<8, u64> = TxArray::new(BASE);
+        assert_eq!(array.len(), 8);
+
+        stm.write_transaction(|tr| {
+            for i in 0..array.len() {
+                if array.set(tr, i, (i as u64 + 1) * 10) == Err(TxArrayError::Conflict) {
+                    return STMResult::Retry;
+                }
+            }
+            STMResult::Ok(())
+        });
+        let values = stm.read_transaction(|tr| {
+            let mut values = Vec::new();
+            for i in 0..array.len() {
+                match array.get(tr, i) {
+                    Ok(v) => values.push(v),
+                    Err(_) => return STMResult::Retry,
+                }
+            }
+            STMResult::Ok(values)
+        }).unwrap();
+        assert_eq!(values, [10, 20, 30, 40, 50, 60, 70, 80]);
+        assert_eq!(u64::from_le_bytes(stm.read_raw(BASE + 3 * STRIPE_SIZE)), 40);
+
+        // 範囲外の添字
+        let out_of_bounds = TxArrayError::OutOfBounds { index: 8, len: 8 };
+        stm.write_transaction(|tr| {
+            assert_eq!(array.set(tr, 8, 1), Err(out_of_bounds));
+            assert_eq!(array.get(tr, 8), Err(out_of_bounds));
+            STMResult::Ok(())
+        });
+        assert_eq!(stm.read_raw(BASE + 8 * STRIPE_SIZE), [0; STRIPE_SIZE], "an out-of-bounds set must not write past the array");
+    }
+}
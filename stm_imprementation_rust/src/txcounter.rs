@@ -0,0 +1,127 @@
+// 1 つのストライプに u64 (little endian) として格納するカウンタ
+// 各操作は内部で 1 つのトランザクションとして実行する (retry policy が諦めた場合は None)
+
+use std::fmt;
+
+use crate::tl2::{STMResult, STM, STRIPE_SIZE};
+use crate::load;
+
+// u64 を 1 つのストライプに格納するため、STRIPE_SIZE は 8 以上でなければならない
+const _: () = assert!(STRIPE_SIZE >= 8);
+
+// 加えた結果が u64 の範囲を超える場合の扱い (TxCounter::increment_with を参照)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntOverflow {
+    #[default]
+    Wrap,       // 2^64 を法として wrap する
+    Saturate,   // u64::MAX で止める
+    Abort,      // 何も書き込まずに Err(CounterOverflow) を返す
+}
+
+// IntOverflow::Abort で、加えた結果が u64 の範囲を超えたことを表す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CounterOverflow {
+    pub value: u64,     // 加える前の値 (カウンタはこの値のまま)
+    pub by: u64,
+}
+
+impl fmt::Display for CounterOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "adding {} to counter value {} overflows u64", self.by, self.value)
+    }
+}
+
+impl std::error::Error for CounterOverflow {}
+
+pub struct TxCounter {
+    addr: usize,
+}
+
+impl TxCounter {
+    pub fn new(addr: usize) -> Self {
+        assert_eq!(addr & (STRIPE_SIZE - 1), 0);
+        TxCounter { addr }
+    }
+
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    // by を加え、加えた後の値を返す (u64 の範囲を超える場合は wrap する)
+    pub fn increment(&self, stm: &STM, by: u64) -> Option<u64> {
+        self.increment_with(stm, by, IntOverflow::Wrap).map(|result| result.unwrap())
+    }
+
+    // increment と同様だが、u64 の範囲を超える場合の扱いを overflow で指定する
+    // Abort ではカウンタを変更せずに Some(Err(CounterOverflow)) を返す
+    pub fn increment_with(&self, stm: &STM, by: u64, overflow: IntOverflow) -> Option<Result<u64, CounterOverflow>> {
+        stm.write_transaction(|tr| {
+            let value = u64::from_le_bytes(load!(tr, self.addr));
+            let added = match overflow {
+                IntOverflow::Wrap => value.wrapping_add(by),
+                IntOverflow::Saturate => value.saturating_add(by),
+                IntOverflow::Abort => match value.checked_add(by) {
+                    Some(added) => added,
+                    None => return STMResult::Ok(Err(CounterOverflow { value, by })),
+                },
+            };
+            tr.store(self.addr, added.to_le_bytes());
+            STMResult::Ok(Ok(added))
+        })
+    }
+
+    pub fn get(&self, stm: &STM) -> Option<u64> {
+        stm.read_transaction(|tr| STMResult::Ok(u64::from_le_bytes(load!(tr, self.addr))))
+    }
+
+    // 加えた結果が limit 以下である場合に限り by を加える (加えた場合は true)
+    // 読み込み・判定・書き込みが 1 つのトランザクションなので、並行に呼ばれても limit を超えることはない
+    pub fn add_if_below(&self, stm: &STM, by: u64, limit: u64) -> Option<bool> {
+        stm.write_transaction(|tr| {
+            let value = u64::from_le_bytes(load!(tr, self.addr));
+            match value.checked_add(by) {
+                Some(added) if added <= limit => {
+                    tr.store(self.addr, added.to_le_bytes());
+                    STMResult::Ok(true)
+                }
+                _ => STMResult::Ok(false),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // u64 の範囲を超える加算は Wrap で 0 側に回り、Saturate で u64::MAX に止まり、Abort でカウンタを変更しない
+    #[test]
+    fn increment_overflow_follows_policy() {
+        let stm = STM::new();
+        let counter = TxCounter::new(0);
+        let set = |value: u64| stm.write_transaction(|tr| {
+            tr.store(0, value.to_le_bytes());
+            STMResult::Ok(())
+        });
+
+        set(u64::MAX - 1);
+        assert_eq!(counter.increment_with(&stm, 1, IntOverflow::Abort), Some(Ok(u64::MAX)), "an add that fits is applied");
+
+        set(u64::MAX);
+        assert_eq!(counter.increment_with(&stm, 1, IntOverflow::Wrap), Some(Ok(0)));
+        assert_eq!(counter.get(&stm), Some(0));
+
+        set(u64::MAX - 1);
+        assert_eq!(counter.increment_with(&stm, 5, IntOverflow::Saturate), Some(Ok(u64::MAX)));
+        assert_eq!(counter.increment_with(&stm, 5, IntOverflow::Saturate), Some(Ok(u64::MAX)));
+
+        set(u64::MAX - 1);
+        let version = stm.global_version();
+        assert_eq!(counter.increment_with(&stm, 2, IntOverflow::Abort), Some(Err(CounterOverflow { value: u64::MAX - 1, by: 2 })));
+        assert_eq!(counter.get(&stm), Some(u64::MAX - 1), "an aborted add must leave the counter unchanged");
+        assert_eq!(stm.global_version(), version, "an aborted add must not commit");
+
+        // increment は従来どおり wrap する
+        assert_eq!(counter.increment(&stm, 3), Some(1));
+    }
+}
@@ -0,0 +1,201 @@
+// ストライプ上に構築したトランザクショナルなハッシュマップ
+// 1 つのエントリは 3 つのストライプ (使用中の印, key, value) からなり、base から capacity 個のエントリを並べる。
+// key のハッシュ値から決まるエントリから順に調べる (線形探索のオープンアドレス法)。
+// 探索で読み込んだエントリは全て read_set に入るため、同じ探索列に他のトランザクションが挿入すれば競合として検出される。
+// todo: remove (削除の印を用いる)
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+
+use crate::tl2::{Loadable, WriteTrans, MEM_SIZE, STRIPE_SIZE};
+
+const ENTRY_SIZE: usize = 3 * STRIPE_SIZE;
+const OCCUPIED: [u8; STRIPE_SIZE] = {
+    let mut bytes = [0; STRIPE_SIZE];
+    bytes[0] = 1;
+    bytes
+};
+
+// 1 つのストライプに格納できる値
+pub trait StripeCodec: Sized {
+    fn encode(&self) -> [u8; STRIPE_SIZE];
+    fn decode(bytes: [u8; STRIPE_SIZE]) -> Self;
+}
+
+macro_rules! impl_stripe_codec {
+    ($($t: ty),*) => {
+        $(
+            impl StripeCodec for $t {
+                fn encode(&self) -> [u8; STRIPE_SIZE] {
+                    let mut bytes = [0; STRIPE_SIZE];
+                    bytes[..std::mem::size_of::<$t>()].copy_from_slice(&self.to_le_bytes());
+                    bytes
+                }
+
+                fn decode(bytes: [u8; STRIPE_SIZE]) -> Self {
+                    <$t>::from_le_bytes(bytes[..std::mem::size_of::<$t>()].try_into().unwrap())
+                }
+            }
+        )*
+    };
+}
+
+impl_stripe_codec!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl StripeCodec for [u8; STRIPE_SIZE] {
+    fn encode(&self) -> [u8; STRIPE_SIZE] {
+        *self
+    }
+
+    fn decode(bytes: [u8; STRIPE_SIZE]) -> Self {
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxMapError {
+    Conflict,   // 競合が発生した (トランザクションは retry される)
+    Full,       // 空いているエントリがない
+}
+
+impl fmt::Display for TxMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxMapError::Conflict => write!(f, "transaction conflicted"),
+            TxMapError::Full => write!(f, "map is full"),
+        }
+    }
+}
+
+impl std::error::Error for TxMapError {}
+
+pub struct TxMap<K, V> {
+    base: usize,            // 先頭のエントリのアドレス
+    capacity: usize,        // エントリ数
+    hasher: RandomState,    // 全てのスレッドで同じマップを共有するため、ハッシュ関数はマップごとに 1 つ
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K: Hash + Eq + StripeCodec, V: StripeCodec> TxMap<K, V> {
+    // [base, base + capacity * 3 * STRIPE_SIZE) を使用する (他の用途と重ならないようにすること)
+    // 使用する領域は 0 で初期化されていなければならない
+    pub fn new(base: usize, capacity: usize) -> Self {
+        assert_eq!(base & (STRIPE_SIZE - 1), 0);
+        assert!(capacity > 0);
+        assert!(base + capacity * ENTRY_SIZE <= MEM_SIZE);
+        TxMap { base, capacity, hasher: RandomState::new(), _marker: PhantomData }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // 使用するメモリの大きさ (バイト)
+    pub fn size_in_bytes(&self) -> usize {
+        self.capacity * ENTRY_SIZE
+    }
+
+    // key に対応する値を返す (競合した場合は None)
+    pub fn get<T: Loadable>(&self, tr: &mut T, key: &K) -> Option<Option<V>> {
+        match self.find(tr, key)? {
+            Slot::Found(entry) => Some(Some(V::decode(tr.load(entry + 2 * STRIPE_SIZE)?))),
+            Slot::Vacant(_) | Slot::Full => Some(None),
+        }
+    }
+
+    // key に value を対応させ、以前の値を返す
+    pub fn insert(&self, tr: &mut WriteTrans, key: K, value: V) -> Result<Option<V>, TxMapError> {
+        let (entry, previous) = match self.find(tr, &key).ok_or(TxMapError::Conflict)? {
+            Slot::Found(entry) => {
+                let previous = tr.load(entry + 2 * STRIPE_SIZE).ok_or(TxMapError::Conflict)?;
+                (entry, Some(V::decode(previous)))
+            }
+            Slot::Vacant(entry) => {
+                tr.store(entry, OCCUPIED);
+                tr.store(entry + STRIPE_SIZE, key.encode());
+                (entry, None)
+            }
+            Slot::Full => return Err(TxMapError::Full),
+        };
+        tr.store(entry + 2 * STRIPE_SIZE, value.encode());
+        Ok(previous)
+    }
+
+    // key のエントリ、または key がない場合に挿入すべき空きエントリを探す (競合した場合は None)
+    fn find<T: Loadable>(&self, tr: &mut T, key: &K) -> Option<Slot> {
+        let start = self.hasher.hash_one(key) as usize % self.capacity;
+        for i in 0..self.capacity {
+            let entry = self.base + (start + i) % self.capacity * ENTRY_SIZE;
+            if tr.load(entry)? != OCCUPIED {
+                return Some(Slot::Vacant(entry));
+            }
+            if K::decode(tr.load(entry + STRIPE_SIZE)?) == *key {
+                return Some(Slot::Found(entry));
+            }
+        }
+        Some(Slot::Full)
+    }
+}
+
+// 探索の結果 (エントリのアドレス)
+enum Slot {
+    Found(usize),
+    Vacant(usize),
+    Full,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tl2::{STMResult, STM};
+
+    fn insert(stm: &STM, map: &TxMap<u64, u64>, key: u64, value: u64) -> Result<Option<u64>, TxMapError> {
+        stm.write_transaction(|tr| match map.insert(tr, key, value) {
+            Err(TxMapError::Conflict) => STMResult::Retry,
+            result => STMResult::Ok(result),
+        }).unwrap()
+    }
+
+    fn get(stm: &STM, map: &TxMap<u64, u64>, key: u64) -> Option<u64> {
+        stm.read_transaction(|tr| match map.get(tr, &key) {
+            Some(value) => STMResult::Ok(value),
+            None => STMResult::Retry,
+        }).unwrap()
+    }
+
+    // 挿入した値を読め、同じ key への挿入は以前の値を返して上書きする。空きがなければ Full
+    #[test]
+    fn insert_get_and_overwrite() {
+        let stm = STM::new();
+        let map = TxMap::new(0, 2);
+        assert_eq!(get(&stm, &map, 1), None);
+        assert_eq!(insert(&stm, &map, 1, 10), Ok(None));
+        assert_eq!(insert(&stm, &map, 2, 20), Ok(None));
+        assert_eq!(insert(&stm, &map, 1, 11), Ok(Some(10)));
+        assert_eq!((get(&stm, &map, 1), get(&stm, &map, 2)), (Some(11), Some(20)));
+        assert_eq!(insert(&stm, &map, 3, 30), Err(TxMapError::Full));
+        assert_eq!(get(&stm, &map, 3), None);
+    }
+
+    // 2 つのスレッドが異なる key を (探索列が衝突するほど詰まったマップに) 並行に挿入しても、全ての key を読める
+    #[test]
+    fn concurrent_inserts_of_distinct_keys() {
+        const KEYS: u64 = 10;
+        let stm = STM::new();
+        let map = TxMap::new(0, 2 * KEYS as usize);
+        std::thread::scope(|s| {
+            for t in 0..2 {
+                let (stm, map) = (&stm, &map);
+                s.spawn(move || for k in 0..KEYS {
+                    let key = t * KEYS + k;
+                    assert_eq!(insert(stm, map, key, key * 100), Ok(None));
+                });
+            }
+        });
+        for key in 0..2 * KEYS {
+            assert_eq!(get(&stm, &map, key), Some(key * 100));
+        }
+    }
+}
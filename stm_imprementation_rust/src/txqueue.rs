@@ -0,0 +1,187 @@
+// ストライプ上に構築したトランザクショナルな有界キュー (リングバッファ)
+// base に head (取り出した要素の累計), base + STRIPE_SIZE に tail (入れた要素の累計) を u64 として置き、
+// その後に capacity 個の要素のストライプを並べる。要素 i (累計での番号) は i % capacity 番目のストライプに入る。
+// キューが満杯 / 空の場合、try_enqueue / try_dequeue は RetryOk を返す (条件が満たされるまでトランザクションを再実行する)。
+// enqueue / dequeue はこれを STM::retry_until で待つ (既定は WaitPolicy::Block: head / tail が更新されるまで park する)
+
+use std::marker::PhantomData;
+
+use crate::tl2::{Loadable, STMResult, WaitPolicy, WriteTrans, STM, STRIPE_SIZE};
+use crate::txmap::StripeCodec;
+
+// u64 を 1 つのストライプに格納するため、STRIPE_SIZE は 8 以上でなければならない
+const _: () = assert!(STRIPE_SIZE >= 8);
+
+pub struct TxQueue<V> {
+    base: usize,
+    capacity: usize,
+    wait_policy: WaitPolicy,
+    _marker: PhantomData<V>,
+}
+
+impl<V: StripeCodec> TxQueue<V> {
+    // stm の [base, base + (capacity + 2) * STRIPE_SIZE) を使用する (0 で初期化されていなければならない)
+    // 範囲は stm のデータ本体の大きさ (with_capacity などで実行時に指定した大きさ) で検査する
+    pub fn new(stm: &STM, base: usize, capacity: usize) -> Self {
+        assert_eq!(base & (STRIPE_SIZE - 1), 0);
+        assert!(capacity > 0);
+        assert!(base + (capacity + 2) * STRIPE_SIZE <= stm.mem.size());
+        TxQueue { base, capacity, wait_policy: WaitPolicy::Block, _marker: PhantomData }
+    }
+
+    // enqueue / dequeue が満杯 / 空のときに待つ方法を設定する
+    pub fn with_wait_policy(mut self, wait_policy: WaitPolicy) -> Self {
+        self.wait_policy = wait_policy;
+        self
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn head_addr(&self) -> usize {
+        self.base
+    }
+
+    fn tail_addr(&self) -> usize {
+        self.base + STRIPE_SIZE
+    }
+
+    fn slot_addr(&self, index: u64) -> usize {
+        self.base + (2 + (index % self.capacity as u64) as usize) * STRIPE_SIZE
+    }
+
+    // (head, tail) を読む (競合した場合は None)
+    fn indices<T: Loadable>(&self, tr: &mut T) -> Option<(u64, u64)> {
+        let head = u64::from_le_bytes(tr.load(self.head_addr())?);
+        let tail = u64::from_le_bytes(tr.load(self.tail_addr())?);
+        Some((head, tail))
+    }
+
+    // 現在の要素数 (競合した場合は None)
+    pub fn len<T: Loadable>(&self, tr: &mut T) -> Option<usize> {
+        let (head, tail) = self.indices(tr)?;
+        Some((tail - head) as usize)
+    }
+
+    // トランザクションの一部として末尾に value を入れる (満杯の場合は RetryOk)
+    pub fn try_enqueue(&self, tr: &mut WriteTrans, value: &V) -> STMResult<()> {
+        let Some((head, tail)) = self.indices(tr) else { return STMResult::Retry };
+        if tail - head == self.capacity as u64 {
+            return STMResult::RetryOk;
+        }
+        tr.store(self.slot_addr(tail), value.encode());
+        tr.store(self.tail_addr(), (tail + 1).to_le_bytes());
+        STMResult::Ok(())
+    }
+
+    // トランザクションの一部として先頭の要素を取り出す (空の場合は RetryOk)
+    pub fn try_dequeue(&self, tr: &mut WriteTrans) -> STMResult<V> {
+        let Some((head, tail)) = self.indices(tr) else { return STMResult::Retry };
+        if head == tail {
+            return STMResult::RetryOk;
+        }
+        let Some(bytes) = tr.load(self.slot_addr(head)) else { return STMResult::Retry };
+        tr.store(self.head_addr(), (head + 1).to_le_bytes());
+        STMResult::Ok(V::decode(bytes))
+    }
+
+    // 空きができるまで待ってから value を入れる (retry policy が諦めた場合は None)
+    pub fn enqueue(&self, stm: &STM, value: V) -> Option<()> {
+        stm.retry_until(|tr| self.try_enqueue(tr, &value), self.wait_policy)
+    }
+
+    // 要素が入るまで待ってから先頭の要素を取り出す (retry policy が諦めた場合は None)
+    pub fn dequeue(&self, stm: &STM) -> Option<V> {
+        stm.retry_until(|tr| self.try_dequeue(tr), self.wait_policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::thread;
+    use std::time::Duration;
+
+    fn len(stm: &STM, queue: &TxQueue<u64>) -> usize {
+        stm.read_transaction(|tr| match queue.len(tr) {
+            Some(len) => STMResult::Ok(len),
+            None => STMResult::Retry,
+        }).unwrap()
+    }
+
+    // 入れた順に取り出され、リングバッファを一巡しても順序が保たれる
+    #[test]
+    fn dequeues_in_fifo_order() {
+        let stm = STM::new();
+        let queue = TxQueue::new(&stm, 0, 3);
+        for round in 0..3 {
+            for v in 0..3 {
+                queue.enqueue(&stm, round * 10 + v).unwrap();
+            }
+            assert_eq!(len(&stm, &queue), 3);
+            for v in 0..3 {
+                assert_eq!(queue.dequeue(&stm), Some(round * 10 + v));
+            }
+            assert_eq!(len(&stm, &queue), 0);
+        }
+    }
+
+    // WaitPolicy::Block: 満杯のキューへの enqueue は dequeue まで、空のキューからの dequeue は enqueue まで待つ
+    #[test]
+    fn full_and_empty_queues_block_until_the_other_side_commits() {
+        let stm = STM::new();
+        let queue = TxQueue::new(&stm, 0, 1).with_wait_policy(WaitPolicy::Block);
+        queue.enqueue(&stm, 1).unwrap();
+        thread::scope(|s| {
+            let producer = s.spawn(|| queue.enqueue(&stm, 2));
+            thread::sleep(Duration::from_millis(20));
+            assert!(!producer.is_finished(), "enqueue into a full queue must wait");
+            assert_eq!(queue.dequeue(&stm), Some(1));
+            assert_eq!(producer.join().unwrap(), Some(()));
+        });
+        assert_eq!(queue.dequeue(&stm), Some(2));
+
+        thread::scope(|s| {
+            let consumer = s.spawn(|| queue.dequeue(&stm));
+            thread::sleep(Duration::from_millis(20));
+            assert!(!consumer.is_finished(), "dequeue from an empty queue must wait");
+            queue.enqueue(&stm, 3).unwrap();
+            assert_eq!(consumer.join().unwrap(), Some(3));
+        });
+    }
+
+    // 複数の producer / consumer が小さなキューを並行に使っても、要素は失われも複製もされず、
+    // 各 producer の要素はそれぞれの consumer に入れた順で届く
+    #[test]
+    fn concurrent_producers_and_consumers_lose_and_duplicate_nothing() {
+        const PRODUCERS: u64 = 2;
+        const CONSUMERS: u64 = 2;
+        const ITEMS: u64 = 200;     // producer ごと (PRODUCERS * ITEMS は CONSUMERS で割り切れる)
+        let stm = STM::new();
+        let queue = TxQueue::new(&stm, 0, 4).with_wait_policy(WaitPolicy::Block);
+        let received: Vec<Vec<u64>> = thread::scope(|s| {
+            for p in 0..PRODUCERS {
+                let (stm, queue) = (&stm, &queue);
+                s.spawn(move || for i in 0..ITEMS {
+                    queue.enqueue(stm, p * ITEMS + i).unwrap();
+                });
+            }
+            let consumers: Vec<_> = (0..CONSUMERS).map(|_| s.spawn(|| {
+                (0..PRODUCERS * ITEMS / CONSUMERS).map(|_| queue.dequeue(&stm).unwrap()).collect()
+            })).collect();
+            consumers.into_iter().map(|c| c.join().unwrap()).collect()
+        });
+
+        let all: HashSet<u64> = received.iter().flatten().copied().collect();
+        assert_eq!(all.len() as u64, PRODUCERS * ITEMS, "an element was lost or duplicated");
+        for values in &received {
+            for p in 0..PRODUCERS {
+                let from_p: Vec<u64> = values.iter().copied().filter(|v| v / ITEMS == p).collect();
+                assert!(from_p.windows(2).all(|w| w[0] < w[1]), "elements of one producer arrived out of order");
+            }
+        }
+        assert_eq!(len(&stm, &queue), 0);
+    }
+}
@@ -0,0 +1,56 @@
+// abort したトランザクション (Abort / request_abort) はメモリ・version・global_clock を変えず、lock も残さない
+
+use std::cell::Cell;
+
+use stm_rust::tl2::{self, STM};
+use stm_rust::{load, store};
+
+#[test]
+fn aborted_transactions_leave_no_trace() {
+    let stm = STM::new();
+    stm.write_transaction(|tr| {
+        store!(tr, 0, 1u64.to_le_bytes());
+        store!(tr, 8, 2u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+
+    let memory = || (u64::from_le_bytes(stm.read_raw(0)), u64::from_le_bytes(stm.read_raw(8)));
+    let before = (memory(), stm.version_vector(), stm.global_version());
+
+    // store 後に STMResult::Abort を返す
+    let runs = Cell::new(0);
+    let result: Option<()> = stm.write_transaction(|tr| {
+        runs.set(runs.get() + 1);
+        let a = u64::from_le_bytes(load!(tr, 0));
+        store!(tr, 0, (a + 100).to_le_bytes());
+        tl2::STMResult::Abort
+    });
+    assert_eq!(result, None);
+    assert_eq!(runs.get(), 1, "an aborted transaction must not be retried");
+
+    // 命令的に条件を調べて request_abort する (closure の戻り値は Ok でも commit されない)
+    runs.set(0);
+    let result = stm.write_transaction(|tr| {
+        runs.set(runs.get() + 1);
+        let b = u64::from_le_bytes(load!(tr, 8));
+        store!(tr, 8, (b + 100).to_le_bytes());
+        if b < 10 {
+            tr.request_abort();
+        }
+        assert!(tr.abort_requested());
+        tl2::STMResult::Ok(b)
+    });
+    assert_eq!(result, None);
+    assert_eq!(runs.get(), 1);
+
+    let after = (memory(), stm.version_vector(), stm.global_version());
+    assert_eq!(before, after, "an aborted transaction must not change memory, versions or the clock");
+    assert!(stm.locked_stripes().is_empty());
+
+    // abort した後も通常どおり commit できる
+    stm.write_transaction(|tr| {
+        store!(tr, 0, 3u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+    assert_eq!(memory(), (3, 2));
+}
@@ -0,0 +1,96 @@
+// with_adaptive_striping で競合しない region の lock_ver をまとめても、値と送金の合計は保たれる
+
+use std::thread;
+
+use stm_rust::tl2::{self, ApplyOutcome, Memory, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const HEAP: usize = 64 * 1024;
+const STRIPES: usize = HEAP / STRIPE_SIZE;
+const REGION: usize = 64;
+const THREADS: usize = 4;
+const TRANSFERS: usize = 5000;
+const INITIAL: u64 = 100;
+
+fn total(stm: &STM) -> u64 {
+    stm.read_transaction(|tr| {
+        match tr.fold_range(0..HEAP, STRIPE_SIZE, 0u64, |acc, s| acc + u64::from_le_bytes(s)) {
+            Some(total) => tl2::STMResult::Ok(total),
+            None => tl2::STMResult::Retry,
+        }
+    }).unwrap()
+}
+
+#[test]
+fn merged_regions_keep_values_and_totals() {
+    let buffer: &'static mut [u8] = Box::leak((0..STRIPES).flat_map(|_| INITIAL.to_le_bytes()).collect());
+    let mem: Memory = Memory::from_mut_slice(buffer).unwrap().with_adaptive_striping(REGION);
+    let mut stm = STM::from_memory(mem);
+
+    // 先頭の region (アドレス 0) で競合を起こす
+    let mut first = stm.scope_write();
+    let mut second = stm.scope_write();
+    for tx in [&mut first, &mut second] {
+        let v = u64::from_le_bytes(tx.load(0).unwrap());
+        tx.store(0, v.to_le_bytes());
+    }
+    assert!(matches!(first.finish(), ApplyOutcome::Committed(_)));
+    assert_eq!(second.finish(), ApplyOutcome::Conflict { addr: Some(0) });
+
+    // 競合した先頭の region 以外をまとめる
+    let merged = stm.merge_cold_regions();
+    assert_eq!(merged, STRIPES / REGION - 1);
+    assert_eq!(stm.lock_words(), REGION + merged);
+    assert_eq!(total(&stm), STRIPES as u64 * INITIAL);
+
+    // まとめた region の複数のストライプに 1 つのトランザクションで書き込む (自身の lock と競合しない)
+    let cold = 5 * REGION * STRIPE_SIZE;
+    stm.write_transaction(|tr| {
+        let a = u64::from_le_bytes(load!(tr, cold));
+        let b = u64::from_le_bytes(load!(tr, cold + STRIPE_SIZE));
+        store!(tr, cold, (a - 1).to_le_bytes());
+        store!(tr, cold + STRIPE_SIZE, (b + 1).to_le_bytes());
+        tl2::STMResult::Ok(())
+    }).unwrap();
+    assert_eq!(u64::from_le_bytes(stm.read_raw(cold)), INITIAL - 1);
+    assert_eq!(u64::from_le_bytes(stm.read_raw(cold + STRIPE_SIZE)), INITIAL + 1);
+
+    // 全体に散らばった送金 (まとめた region とそうでない region の両方を含む)
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let stm = &stm;
+            s.spawn(move || {
+                let mut x = t as u64 + 1;
+                for _ in 0..TRANSFERS {
+                    x ^= x << 13;
+                    x ^= x >> 7;
+                    x ^= x << 17;
+                    let from = (x as usize % STRIPES) * STRIPE_SIZE;
+                    let to = ((x >> 32) as usize % STRIPES) * STRIPE_SIZE;
+                    stm.write_transaction(|tr| {
+                        let a = u64::from_le_bytes(load!(tr, from));
+                        if a == 0 || from == to {
+                            return tl2::STMResult::Ok(());
+                        }
+                        let b = u64::from_le_bytes(load!(tr, to));
+                        store!(tr, from, (a - 1).to_le_bytes());
+                        store!(tr, to, (b + 1).to_le_bytes());
+                        tl2::STMResult::Ok(())
+                    }).unwrap();
+                }
+            });
+        }
+        // 並行に合計を読む
+        for _ in 0..20 {
+            assert_eq!(total(&stm), STRIPES as u64 * INITIAL);
+        }
+    });
+    assert_eq!(total(&stm), STRIPES as u64 * INITIAL);
+
+    // 再び merge しても (競合した region は分け直される) 値は変わらない
+    let before: Vec<u64> = (0..STRIPES).map(|i| u64::from_le_bytes(stm.read_raw(i * STRIPE_SIZE))).collect();
+    stm.merge_cold_regions();
+    let after: Vec<u64> = (0..STRIPES).map(|i| u64::from_le_bytes(stm.read_raw(i * STRIPE_SIZE))).collect();
+    assert_eq!(before, after);
+    assert!(stm.locked_stripes().is_empty());
+}
@@ -0,0 +1,78 @@
+// write_transaction_retry_async で待つタスクは、待っているストライプへの commit でのみ wake される
+
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+use std::thread::{self, Thread};
+use std::time::Duration;
+
+use stm_rust::tl2::{self, WriteTrans, STRIPE_SIZE, STM};
+use stm_rust::{load, store};
+
+const BUFFER: usize = 0;        // 0 ならば空
+const OTHER: usize = STRIPE_SIZE;
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// future が完了するまで現在のスレッドで poll し、Pending の間は park する
+// (poll 回数, 結果) を返す
+fn block_on<F: Future>(future: F) -> (usize, F::Output) {
+    let mut future = pin!(future);
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut polls = 0;
+    loop {
+        polls += 1;
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return (polls, output);
+        }
+        thread::park();
+    }
+}
+
+#[test]
+fn consumer_is_woken_by_producer_commit() {
+    let stm = STM::new();
+    let runs = Cell::new(0);
+
+    let consume = |tr: &mut WriteTrans<'_>| {
+        runs.set(runs.get() + 1);
+        let val = u64::from_le_bytes(load!(tr, BUFFER));
+        if val == 0 {
+            return tl2::STMResult::RetryOk;
+        }
+        store!(tr, BUFFER, 0u64.to_le_bytes());
+        tl2::STMResult::Ok(val)
+    };
+
+    let (polls, consumed) = thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(50));
+            // 待機しているストライプとは無関係な commit では wake されない
+            stm.write_transaction(|tr| {
+                store!(tr, OTHER, 1u64.to_le_bytes());
+                tl2::STMResult::Ok(())
+            });
+            thread::sleep(Duration::from_millis(50));
+            stm.write_transaction(|tr| {
+                store!(tr, BUFFER, 42u64.to_le_bytes());
+                tl2::STMResult::Ok(())
+            });
+        });
+        block_on(stm.write_transaction_retry_async(consume))
+    });
+
+    assert_eq!(consumed, Some(42));
+    assert_eq!(u64::from_le_bytes(stm.read_raw(BUFFER)), 0);
+    // 1 回目の poll で Pending、producer の commit で wake された poll で完了する (park の spurious wakeup の分は許容する)
+    assert!((2..=4).contains(&polls), "polls = {polls}");
+    assert!(runs.get() >= polls);     // 登録直後の再検査で commit を観測した場合は同じ poll の中で再実行する
+}
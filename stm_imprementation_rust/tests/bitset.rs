@@ -0,0 +1,84 @@
+// ビット操作は同じストライプの他の bit を壊さず、並行に箸の bit を拾って置いても全て 0 に戻る
+
+use stm_rust::tl2::{self, WriteTrans, STM, STRIPE_SIZE};
+use stm_rust::load;
+
+const BITS: usize = STRIPE_SIZE * 8;
+const BASE: usize = STRIPE_SIZE;        // 2 番目のストライプの先頭 bit = BASE * 8
+const PHILOSOPHERS: usize = 64;
+const ITERATIONS: usize = 200;
+
+// load! と同様に、読み込みが競合した場合は closure から Retry を返す
+macro_rules! bit {
+    ($e: expr) => {
+        if let Some(v) = $e {
+            v
+        } else {
+            return tl2::STMResult::Retry;
+        }
+    };
+}
+
+fn stripe_value(stm: &STM, addr: usize) -> u128 {
+    let bytes = stm.read_transaction(|tr| tl2::STMResult::Ok(load!(tr, addr))).unwrap();
+    bytes.iter().rev().fold(0u128, |acc, b| (acc << 8) | *b as u128)
+}
+
+#[test]
+fn toggle_in_one_transaction() {
+    let stm = &STM::new();
+    let last = BITS - 1;
+    let olds = stm.write_transaction(|tr| {
+        let mut olds = Vec::new();
+        for bit in [0, 3, 9, last] {
+            olds.push(bit!(tr.test_and_set_bit(BASE * 8 + bit)));
+        }
+        olds.push(bit!(tr.test_and_set_bit(BASE * 8 + 3)));     // 同じトランザクション内で既に 1
+        olds.push(bit!(tr.clear_bit(BASE * 8 + 3)));
+        olds.push(bit!(tr.clear_bit(BASE * 8 + 5)));            // 元から 0
+        olds.push(bit!(tr.test_bit(BASE * 8 + 9)));
+        tl2::STMResult::Ok(olds)
+    }).unwrap();
+    assert_eq!(olds, vec![false, false, false, false, true, true, false, true]);
+
+    let expected = 1u128 | 1 << 9 | 1 << last;
+    assert_eq!(stripe_value(stm, BASE), expected);
+    assert_eq!(stripe_value(stm, 0), 0);
+    assert_eq!(stripe_value(stm, 2 * STRIPE_SIZE), 0);
+}
+
+#[test]
+fn packed_philosophers() {
+    let stm = &STM::new();
+    // 哲学者 n の箸は bit n と bit (n + 1) % PHILOSOPHERS (ストライプ 0 から詰めて並べる)
+    let picks = stm.scope(|s| {
+        let handles: Vec<_> = (0..PHILOSOPHERS)
+            .map(|n| s.spawn(move |stm| {
+                let (left, right) = (n, (n + 1) % PHILOSOPHERS);
+                let mut picks = 0;
+                for _ in 0..ITERATIONS {
+                    let pick = |tr: &mut WriteTrans<'_>| {
+                        if bit!(tr.test_bit(left)) || bit!(tr.test_bit(right)) {
+                            return tl2::STMResult::RetryOk;
+                        }
+                        bit!(tr.test_and_set_bit(left));
+                        bit!(tr.test_and_set_bit(right));
+                        tl2::STMResult::Ok(())
+                    };
+                    stm.write_transaction(pick).unwrap();
+                    stm.write_transaction(|tr| {
+                        assert!(bit!(tr.clear_bit(left)) && bit!(tr.clear_bit(right)), "chopstick was not held");
+                        tl2::STMResult::Ok(())
+                    }).unwrap();
+                    picks += 1;
+                }
+                picks
+            }))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).sum::<usize>()
+    });
+    assert_eq!(picks, PHILOSOPHERS * ITERATIONS);
+    for stripe in 0..PHILOSOPHERS.div_ceil(BITS) {
+        assert_eq!(stripe_value(stm, stripe * STRIPE_SIZE), 0);
+    }
+}
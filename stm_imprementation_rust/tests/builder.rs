@@ -0,0 +1,66 @@
+// StmBuilder で与えた各設定は、作成した STM に反映される
+
+use std::time::Duration;
+
+use stm_rust::retry::ExponentialJitter;
+use stm_rust::tl2::{self, writer_id, SetHasher, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const THREADS: usize = 4;
+const ITERATIONS: u64 = 1000;
+const COUNTER: usize = 0;
+const UNINITIALIZED: usize = 8 * STRIPE_SIZE;
+
+#[test]
+fn builder_settings_take_effect() {
+    let stm = STM::builder()
+        .retry_policy(ExponentialJitter::new(Duration::from_micros(1), Duration::from_micros(50)))
+        .set_capacity(4, 4)
+        .hasher(SetHasher::Fast)
+        .strict_init(true)
+        .with_stats()
+        .with_last_writer()
+        .build();
+
+    // strict_init: 初期化してから increment する
+    stm.write_transaction(|tr| {
+        store!(tr, COUNTER, 0u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    }).unwrap();
+
+    let increment = |tr: &mut tl2::WriteTrans<'_>| {
+        let val = u64::from_le_bytes(load!(tr, COUNTER));
+        store!(tr, COUNTER, (val + 1).to_le_bytes());
+        tl2::STMResult::Ok(())
+    };
+    let writers: Vec<u64> = stm.scope(|s| {
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| s.spawn(move |stm| {
+                for _ in 0..ITERATIONS {
+                    stm.write_transaction(increment).unwrap();
+                }
+                writer_id()
+            }))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    let counter = stm.read_transaction(|tr| tl2::STMResult::Ok(u64::from_le_bytes(load!(tr, COUNTER)))).unwrap();
+    assert_eq!(counter, THREADS as u64 * ITERATIONS);
+
+    // with_stats
+    let stats = stm.stats().unwrap();
+    assert_eq!(stats.commits, THREADS as u64 * ITERATIONS + 1);
+    assert_eq!(stats.gave_up, 0);
+
+    // with_last_writer
+    assert!(writers.contains(&stm.last_writer(COUNTER)));
+
+    // strict_init: 一度も commit されていないストライプは読み込めない
+    let uninitialized = stm.read_transaction(|tr| match tr.try_load(UNINITIALIZED) {
+        Err(tl2::LoadError::Uninitialized) => tl2::STMResult::Ok(true),
+        _ => tl2::STMResult::Ok(false),
+    }).unwrap();
+    assert!(uninitialized);
+
+}
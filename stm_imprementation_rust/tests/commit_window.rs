@@ -0,0 +1,84 @@
+// commit の各段階の間に他の commit を割り込ませても、検証の省略は serializability を壊さない
+
+use std::cell::Cell;
+use std::thread;
+
+use stm_rust::deterministic::DeterministicSTM;
+use stm_rust::tl2::{self, WriteTrans, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const A: usize = 0;
+const B: usize = STRIPE_SIZE;
+const C: usize = 2 * STRIPE_SIZE;
+
+// (スレッド 0 の実行回数, B の値)
+fn run(schedule: Vec<usize>) -> (usize, u64) {
+    let stm = DeterministicSTM::new(STM::new(), schedule).with_split_commit();
+
+    let runs = thread::scope(|s| {
+        let reader = s.spawn(|| {
+            let runs = Cell::new(0);
+            stm.write_transaction(0, |tr: &mut WriteTrans<'_>| {
+                runs.set(runs.get() + 1);
+                let a = u64::from_le_bytes(load!(tr, A));
+                store!(tr, B, (a + 1).to_le_bytes());
+                store!(tr, C, (a + 1).to_le_bytes());
+                tl2::STMResult::Ok(())
+            }).unwrap();
+            runs.get()
+        });
+        s.spawn(|| {
+            stm.write_transaction(1, |tr: &mut WriteTrans<'_>| {
+                store!(tr, A, 100u64.to_le_bytes());
+                tl2::STMResult::Ok(())
+            }).unwrap();
+        });
+        reader.join().unwrap()
+    });
+    assert_eq!(stm.remaining(), 0, "schedule was not consumed exactly");
+
+    let stm = stm.stm();
+    let (a, b, c) = stm.read_transaction(|tr| {
+        let a = u64::from_le_bytes(load!(tr, A));
+        let b = u64::from_le_bytes(load!(tr, B));
+        let c = u64::from_le_bytes(load!(tr, C));
+        tl2::STMResult::Ok((a, b, c))
+    }).unwrap();
+    assert_eq!(a, 100);
+    assert_eq!(b, c);
+    (runs, b)
+}
+
+fn check(name: &str, schedule: Vec<usize>, expected_runs: usize, expected_b: u64) {
+    let (runs, b) = run(schedule);
+    assert_eq!(b, expected_b, "{}: result is not serializable", name);
+    assert_eq!(runs, expected_runs, "{}: unexpected number of runs", name);
+}
+
+#[test]
+fn no_intervening_commit() {
+    // 割り込みなし: 0 が先に commit する (検証は省略される)
+    check("no intervening commit", vec![0, 0, 0, 0, 1, 1, 1, 1], 1, 1);
+}
+
+#[test]
+fn commit_between_lock_and_stamp() {
+    // 0 の lock の獲得と version の割り当ての間に 1 が commit する:
+    // new_version = read_version + 2 となるので検証が行われ、古い A を読んだ 0 は再実行される
+    check("commit between lock and stamp", vec![0, 0, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0], 2, 101);
+}
+
+#[test]
+fn commit_between_stamp_and_publish() {
+    // 0 が version を割り当てた後に 1 が commit する: 検証は省略されるが、0 は 1 より前に serialize されるので
+    // 1 の書き込み前の A を読んでいてよい (検証していれば A の version が新しいため再実行される)
+    check("commit between stamp and publish", vec![0, 0, 0, 1, 1, 1, 1, 0], 1, 1);
+}
+
+#[test]
+fn read_version_taken_inside_another_commit() {
+    // 1 が version を割り当てた後、書き込みを公開する前に 0 が read_version を取得する:
+    // 0 の read_version は 1 の version 以上になるが、lock 中の A の読み込みが競合として失敗するので、
+    // 検証の省略によって公開前の値を読んだまま commit することはない
+    check("read version taken inside another commit", vec![1, 1, 1, 0, 1, 0, 0, 0, 0], 2, 101);
+}
@@ -0,0 +1,48 @@
+// poll_flag で flag を観測した時点で、publish_flag の前に commit された値が見える
+
+use std::thread;
+
+use stm_rust::tl2::{self, STM};
+use stm_rust::{load, store};
+
+const DATA: usize = 0;
+const READY: usize = 8;     // producer -> consumer
+const DONE: usize = 16;     // consumer -> producer
+const ROUNDS: u64 = 2000;
+
+#[test]
+fn flag_publishes_the_preceding_commit() {
+    let stm = STM::new();
+    assert!(!stm.poll_flag(READY));
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            for round in 1..=ROUNDS {
+                stm.write_transaction(|tr| {
+                    let v = u64::from_le_bytes(load!(tr, DATA));
+                    store!(tr, DATA, (v + 1).to_le_bytes());
+                    tl2::STMResult::Ok(())
+                });
+                stm.publish_flag(READY);
+                while !stm.clear_flag(DONE) {
+                    thread::yield_now();
+                }
+                assert_eq!(u64::from_le_bytes(stm.read_raw(DATA)), round);
+            }
+        });
+
+        for round in 1..=ROUNDS {
+            while !stm.poll_flag(READY) {
+                thread::yield_now();
+            }
+            assert!(stm.clear_flag(READY));
+            // flag を観測した時点で、publish 前の commit は見えている
+            assert_eq!(u64::from_le_bytes(stm.read_raw(DATA)), round, "publish observed before the commit it follows");
+            stm.publish_flag(DONE);
+        }
+    });
+
+    // flag はデータ本体とは別の領域: ストライプの値にも version にも影響しない
+    assert_eq!(u64::from_le_bytes(stm.read_raw(READY)), 0);
+    assert_eq!(stm.version_vector()[READY / 8], 0);
+}
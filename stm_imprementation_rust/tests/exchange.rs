@@ -0,0 +1,84 @@
+// exchange は 2 つの値を入れ替え、並行に入れ替え続けても値の集合は保たれて見える
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::thread;
+
+use stm_rust::store;
+use stm_rust::tl2::{self, STM};
+
+const SLOTS: usize = 8;
+const THREADS: usize = 3;
+const SWAPS: usize = 2000;
+
+#[test]
+fn exchanges_preserve_all_values() {
+    // 1. 単純な入れ替え
+    let stm = STM::new();
+    stm.write_transaction(|tr| {
+        store!(tr, 0, 1u64.to_le_bytes());
+        store!(tr, 8, 2u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+    stm.write_transaction(|tr| {
+        let Some(()) = tr.exchange(0, 8) else {
+            return tl2::STMResult::Retry;
+        };
+        tl2::STMResult::Ok(())
+    });
+    assert_eq!((u64::from_le_bytes(stm.read_raw(0)), u64::from_le_bytes(stm.read_raw(8))), (2, 1));
+
+    // 2. 競合する入れ替え
+    let stm = STM::new();
+    stm.write_transaction(|tr| {
+        for i in 0..SLOTS {
+            store!(tr, i * 8, (i as u64).to_le_bytes());
+        }
+        tl2::STMResult::Ok(())
+    });
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+        let workers: Vec<_> = (0..THREADS).map(|t| {
+            let stm = &stm;
+            s.spawn(move || {
+                for i in 0..SWAPS {
+                    let a = (i * 3 + t) % SLOTS;
+                    let b = (i * 5 + t + 1) % SLOTS;
+                    stm.write_transaction(|tr| {
+                        let Some(()) = tr.exchange(a * 8, b * 8) else {
+                            return tl2::STMResult::Retry;
+                        };
+                        tl2::STMResult::Ok(())
+                    });
+                }
+            })
+        }).collect();
+
+        s.spawn(|| {
+            while !done.load(Relaxed) {
+                let mut seen = stm.read_transaction(|tr| {
+                    let mut values = Vec::with_capacity(SLOTS);
+                    for i in 0..SLOTS {
+                        let Some(v) = tr.load(i * 8) else {
+                            return tl2::STMResult::Retry;
+                        };
+                        values.push(u64::from_le_bytes(v));
+                    }
+                    tl2::STMResult::Ok(values)
+                }).unwrap();
+                seen.sort();
+                assert_eq!(seen, (0..SLOTS as u64).collect::<Vec<_>>(), "an exchange was observed half-applied");
+                thread::yield_now();
+            }
+        });
+
+        for w in workers {
+            w.join().unwrap();
+        }
+        done.store(true, Relaxed);
+    });
+
+    let mut values: Vec<u64> = (0..SLOTS).map(|i| u64::from_le_bytes(stm.read_raw(i * 8))).collect();
+    values.sort();
+    assert_eq!(values, (0..SLOTS as u64).collect::<Vec<_>>());
+}
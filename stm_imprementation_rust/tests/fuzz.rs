@@ -0,0 +1,127 @@
+// ランダムな increment と transfer を並行に繰り返しても、全ストライプの合計は commit された increment の回数と一致する
+
+use std::env;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use stm_rust::tl2::{self, ReadTrans, WriteTrans, MEM_SIZE, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const NUM_STRIPES: usize = MEM_SIZE / STRIPE_SIZE;
+
+// 再現性のための xorshift64* 乱数生成器
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)     // 0 にならないようにする
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // ランダムなストライプのアドレス
+    fn addr(&mut self) -> usize {
+        (self.next() as usize % NUM_STRIPES) * STRIPE_SIZE
+    }
+}
+
+// seed・スレッド数・回数は環境変数で変えられる (STM_FUZZ_SEED=1 STM_FUZZ_THREADS=8 STM_FUZZ_ITERATIONS=100000 cargo test --release --test fuzz)
+fn var(name: &str, default: u64) -> u64 {
+    env::var(name).map(|v| v.parse().expect("numeric environment variable")).unwrap_or(default)
+}
+
+#[test]
+fn conservation_law_holds() {
+    let seed = var("STM_FUZZ_SEED", 0);
+    let threads = var("STM_FUZZ_THREADS", 4) as usize;
+    let iterations = var("STM_FUZZ_ITERATIONS", 2000) as usize;
+
+    let stm = Arc::new(tl2::STM::new());
+    let increments = Arc::new(AtomicU64::new(0));
+    let done = Arc::new(AtomicBool::new(false));
+
+    let mut workers = Vec::new();
+    for t in 0..threads {
+        let stm = stm.clone();
+        let increments = increments.clone();
+        workers.push(std::thread::spawn(move || worker(&stm, &increments, seed ^ t as u64, iterations)));
+    }
+
+    let obs = {
+        let stm = stm.clone();
+        let done = done.clone();
+        std::thread::spawn(move || observer(&stm, &done))
+    };
+
+    for w in workers {
+        w.join().unwrap();
+    }
+    done.store(true, Ordering::Relaxed);
+    obs.join().unwrap();
+
+    let total = snapshot_sum(&stm);
+    let expected = increments.load(Ordering::Relaxed);
+    assert_eq!(total, expected, "conservation law violated (seed = {})", seed);
+}
+
+fn worker(stm: &tl2::STM, increments: &AtomicU64, seed: u64, iterations: usize) {
+    let mut rng = XorShift::new(seed);
+    for _ in 0..iterations {
+        match rng.next() % 3 {
+            // 1 ~ 3 個のランダムなストライプをそれぞれ +1 する
+            0 | 1 => {
+                let n = 1 + (rng.next() % 3) as usize;
+                let addrs: Vec<usize> = (0..n).map(|_| rng.addr()).collect();
+                let committed = stm.write_transaction(|tr: &mut WriteTrans<'_>| {
+                    let mut count = 0;
+                    for &a in addrs.iter() {
+                        let v = u64::from_le_bytes(load!(tr, a)) + 1;
+                        store!(tr, a, v.to_le_bytes());
+                        count += 1;
+                    }
+                    tl2::STMResult::Ok(count)
+                });
+                increments.fetch_add(committed.unwrap(), Ordering::Relaxed);
+            }
+            // from から to へ値を 1 移す (合計は変わらない)
+            _ => {
+                let from = rng.addr();
+                let to = rng.addr();
+                stm.write_transaction(|tr: &mut WriteTrans<'_>| {
+                    let f = u64::from_le_bytes(load!(tr, from));
+                    if f == 0 || from == to {
+                        return tl2::STMResult::Ok(());
+                    }
+                    let t = u64::from_le_bytes(load!(tr, to));
+                    store!(tr, from, (f - 1).to_le_bytes());
+                    store!(tr, to, (t + 1).to_le_bytes());
+                    tl2::STMResult::Ok(())
+                });
+            }
+        }
+    }
+}
+
+fn snapshot_sum(stm: &tl2::STM) -> u64 {
+    stm.read_transaction(|tr: &mut ReadTrans<'_>| {
+        let mut sum = 0;
+        for i in 0..NUM_STRIPES {
+            sum += u64::from_le_bytes(load!(tr, i * STRIPE_SIZE));
+        }
+        tl2::STMResult::Ok(sum)
+    }).unwrap()
+}
+
+fn observer(stm: &tl2::STM, done: &AtomicBool) {
+    let mut last = 0;
+    while !done.load(Ordering::Relaxed) {
+        let sum = snapshot_sum(stm);
+        assert!(sum >= last, "snapshot went back in time: {} -> {}", last, sum);
+        last = sum;
+    }
+}
@@ -0,0 +1,123 @@
+// group commit の batch は serializability を保ち、競合しない commit をまとめる
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+use std::sync::Barrier;
+use std::time::Duration;
+
+use stm_rust::tl2::{self, WriteTrans, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const THREADS: usize = 8;
+const ITERATIONS: usize = 2000;
+
+fn count_ones(tr: &mut WriteTrans<'_>, stripes: usize) -> Option<usize> {
+    let mut ones = 0;
+    for i in 0..stripes {
+        ones += tr.load(i * STRIPE_SIZE)?[0] as usize;
+    }
+    Some(ones)
+}
+
+// 値が 1 のストライプがなければ自分のストライプを 1 にし、自分のストライプが 1 ならば 0 に戻す
+fn toggle(tr: &mut WriteTrans<'_>, mine: usize, stripes: usize) -> tl2::STMResult<()> {
+    let ones = match count_ones(tr, stripes) {
+        Some(ones) => ones,
+        None => return tl2::STMResult::Retry,
+    };
+    let mut stripe = load!(tr, mine);
+    if stripe[0] == 1 {
+        stripe[0] = 0;
+    } else if ones == 0 {
+        stripe[0] = 1;
+    }
+    store!(tr, mine, stripe);
+    tl2::STMResult::Ok(())
+}
+
+#[test]
+fn members_reading_each_other() {
+    let stm = STM::builder().group_commit(Duration::from_millis(10)).build();
+    let barrier = Barrier::new(2);
+    let runs = AtomicUsize::new(0);
+    stm.scope(|s| {
+        for t in 0..2 {
+            let (barrier, runs) = (&barrier, &runs);
+            s.spawn(move |stm| {
+                let first = Cell::new(true);
+                stm.write_transaction(|tr| {
+                    runs.fetch_add(1, Relaxed);
+                    let ones = match count_ones(tr, 2) {
+                        Some(ones) => ones,
+                        None => return tl2::STMResult::Retry,
+                    };
+                    if first.replace(false) {
+                        barrier.wait();     // 両方が読み終えてから書き込み、commit に進む
+                    }
+                    store!(tr, t * STRIPE_SIZE, [(ones == 0) as u8; STRIPE_SIZE]);
+                    tl2::STMResult::Ok(())
+                }).unwrap();
+            });
+        }
+    });
+    let ones = stm.read_transaction(|tr| {
+        tl2::STMResult::Ok(load!(tr, 0)[0] as usize + load!(tr, STRIPE_SIZE)[0] as usize)
+    }).unwrap();
+    assert_eq!(ones, 1, "both members committed values based on each other's old values");
+    assert!(runs.load(Relaxed) >= 4, "both members should have been retried");
+}
+
+#[test]
+fn toggles() {
+    let stm = STM::builder().group_commit(Duration::from_micros(50)).build();
+    let done = AtomicBool::new(false);
+    stm.scope(|s| {
+        let workers: Vec<_> = (0..THREADS).map(|t| s.spawn(move |stm| {
+            for _ in 0..ITERATIONS {
+                stm.write_transaction(|tr| toggle(tr, t * STRIPE_SIZE, THREADS)).unwrap();
+            }
+        })).collect();
+        let observer = s.spawn(|stm| {
+            while !done.load(Relaxed) {
+                let ones = stm.read_transaction(|tr| {
+                    let mut ones = 0;
+                    for i in 0..THREADS {
+                        ones += load!(tr, i * STRIPE_SIZE)[0] as usize;
+                    }
+                    tl2::STMResult::Ok(ones)
+                }).unwrap();
+                assert!(ones <= 1, "observed {} stripes set to 1", ones);
+            }
+        });
+        for w in workers {
+            w.join().unwrap();
+        }
+        done.store(true, Relaxed);
+        observer.join().unwrap();
+    });
+}
+
+#[test]
+fn disjoint_increments() {
+    let stm = STM::builder().group_commit(Duration::from_micros(50)).with_stats().build();
+    stm.scope(|s| {
+        for t in 0..THREADS {
+            s.spawn(move |stm| {
+                let mine = t * STRIPE_SIZE;
+                for _ in 0..ITERATIONS {
+                    stm.write_transaction(|tr| {
+                        let val = u64::from_le_bytes(load!(tr, mine));
+                        store!(tr, mine, (val + 1).to_le_bytes());
+                        tl2::STMResult::Ok(())
+                    }).unwrap();
+                }
+            });
+        }
+    });
+    for t in 0..THREADS {
+        assert_eq!(u64::from_le_bytes(stm.read_raw(t * STRIPE_SIZE)), ITERATIONS as u64);
+    }
+    let commits = stm.stats().unwrap().commits;
+    assert_eq!(commits, (THREADS * ITERATIONS) as u64);
+    assert!(stm.global_version() < commits, "no commits were grouped");
+}
@@ -0,0 +1,54 @@
+// read_at_version は各 commit の時点の値を返し、保持する数を超えて古い version は読めない
+
+use stm_rust::tl2::{self, LoadError, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const A: usize = 0;
+const B: usize = STRIPE_SIZE;
+const HISTORY: usize = 4;
+const WRITES: u64 = 10;
+
+fn read_pair(stm: &STM, version: u64) -> Option<(u64, u64)> {
+    stm.read_at_version(version, |tr| {
+        let a = u64::from_le_bytes(load!(tr, A));
+        let b = u64::from_le_bytes(load!(tr, B));
+        tl2::STMResult::Ok((a, b))
+    })
+}
+
+#[test]
+fn reads_past_versions() {
+    let stm = STM::builder().history(HISTORY).build();
+
+    // B に 1 回書き込んでから、A に 1, 2, ..., WRITES を書き込む
+    let b_version = stm.write_transaction_versioned(|tr| {
+        store!(tr, B, 7u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    }).unwrap().1;
+    let versions: Vec<u64> = (1..=WRITES).map(|i| {
+        stm.write_transaction_versioned(|tr| {
+            store!(tr, A, i.to_le_bytes());
+            tl2::STMResult::Ok(())
+        }).unwrap().1
+    }).collect();
+
+    // 記録が残っている直近 HISTORY 回分の version
+    for (i, version) in versions.iter().enumerate().skip(versions.len() - HISTORY) {
+        assert_eq!(read_pair(&stm, *version), Some((i as u64 + 1, 7)), "version {}", version);
+    }
+
+    // 古い version: A の記録は上書きされているが、B は 1 回しか書き込んでいないので読める
+    let old = versions[0];
+    let evicted = stm.read_at_version(old, |tr| tl2::STMResult::Ok(tr.try_load(A).err())).unwrap();
+    assert_eq!(evicted, Some(LoadError::Evicted));
+    assert_eq!(read_pair(&stm, old), None);
+    let b = stm.read_at_version(b_version, |tr| tl2::STMResult::Ok(u64::from_le_bytes(load!(tr, B)))).unwrap();
+    assert_eq!(b, 7);
+
+    // B の commit より前の version では初期値 (0) が見える
+    let b = stm.read_at_version(b_version - 1, |tr| tl2::STMResult::Ok(u64::from_le_bytes(load!(tr, B)))).unwrap();
+    assert_eq!(b, 0);
+
+    // まだ割り当てられていない version は読めない
+    assert_eq!(read_pair(&stm, stm.global_version() + 1), None);
+}
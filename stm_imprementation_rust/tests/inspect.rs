@@ -0,0 +1,63 @@
+// 箸を拾う closure の中で、read set と write set には触れたアドレスが入る
+
+use std::sync::Mutex;
+use std::collections::HashSet;
+
+use stm_rust::tl2::{self, WaitPolicy, WriteTrans, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const LEFT: usize = 0;
+const RIGHT: usize = STRIPE_SIZE;
+
+// (読み込み後の read set, 最後の write set) を実行ごとに記録する
+type Observed = Vec<(HashSet<usize>, HashSet<usize>)>;
+
+fn pick_chopsticks(observed: &Mutex<Observed>) -> impl Fn(&mut WriteTrans<'_>) -> tl2::STMResult<()> + '_ {
+    move |tr| {
+        let mut left = load!(tr, LEFT);
+        let mut right = load!(tr, RIGHT);
+        let reads = tr.read_addresses().collect();
+        if left[0] != 0 || right[0] != 0 {
+            observed.lock().unwrap().push((reads, tr.write_addresses().collect()));
+            return tl2::STMResult::RetryOk;
+        }
+        left[0] = 1;
+        right[0] = 1;
+        store!(tr, LEFT, left);
+        store!(tr, RIGHT, right);
+        observed.lock().unwrap().push((reads, tr.write_addresses().collect()));
+        tl2::STMResult::Ok(())
+    }
+}
+
+#[test]
+fn read_and_write_sets_while_picking_chopsticks() {
+    let stm = STM::new();
+    let both: HashSet<usize> = [LEFT, RIGHT].into();
+
+    // 箸が拾える場合
+    let observed = Mutex::new(Vec::new());
+    stm.write_transaction(pick_chopsticks(&observed)).unwrap();
+    let observed = observed.into_inner().unwrap();
+    assert_eq!(observed, vec![(both.clone(), both.clone())]);
+
+    // 箸が拾われている場合: 1 回目の実行は何も書き込まずに RetryOk を返す
+    // 別スレッドが箸を置いた後の 2 回目の実行で拾える
+    let observed = Mutex::new(Vec::new());
+    stm.scope(|s| {
+        s.spawn(|stm| {
+            stm.retry_until(pick_chopsticks(&observed), WaitPolicy::Block).unwrap();
+        });
+        while observed.lock().unwrap().is_empty() {
+            std::thread::yield_now();
+        }
+        stm.write_transaction(|tr| {
+            store!(tr, LEFT, [0; STRIPE_SIZE]);
+            store!(tr, RIGHT, [0; STRIPE_SIZE]);
+            tl2::STMResult::Ok(())
+        }).unwrap();
+    });
+    let observed = observed.into_inner().unwrap();
+    assert_eq!(observed.first(), Some(&(both.clone(), HashSet::new())));
+    assert_eq!(observed.last(), Some(&(both.clone(), both.clone())));
+}
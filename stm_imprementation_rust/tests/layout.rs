@@ -0,0 +1,61 @@
+// with_layout で物理配置を変えても、論理アドレスから見た値と version は変わらない
+
+use std::thread;
+
+use stm_rust::tl2::{self, LayoutHint, MEM_SIZE, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const STRIPES: usize = MEM_SIZE / STRIPE_SIZE;
+const GROUP: [usize; 4] = [0, 128, 256, 384];     // 同時にアクセスする、別々の cache line にあるアドレス
+const THREADS: u64 = 4;
+const INCREMENTS: u64 = 1000;
+
+#[test]
+fn layout_preserves_logical_addresses() {
+    let initial: Vec<u8> = (0..STRIPES as u64).flat_map(|i| (i * 3).to_le_bytes()).collect();
+    let stm = STM::builder()
+        .layout(LayoutHint::new().group(&GROUP).group(&[504, 8]))
+        .build_from_bytes(initial)
+        .unwrap();
+
+    // 配置を変えても、論理アドレスから見た初期値と version はそのまま
+    for i in 0..STRIPES {
+        assert_eq!(u64::from_le_bytes(stm.read_raw(i * STRIPE_SIZE)), i as u64 * 3);
+    }
+    assert!(stm.version_vector().iter().all(|v| *v == 1));
+
+    // グループのアドレスをまとめて increment する
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                for _ in 0..INCREMENTS {
+                    stm.write_transaction(|tr| {
+                        for addr in GROUP {
+                            let v = u64::from_le_bytes(load!(tr, addr));
+                            store!(tr, addr, (v + 1).to_le_bytes());
+                        }
+                        tl2::STMResult::Ok(())
+                    });
+                }
+            });
+        }
+    });
+
+    let values = stm.read_transaction(|tr| {
+        let mut values = Vec::new();
+        for i in 0..STRIPES {
+            values.push(u64::from_le_bytes(load!(tr, i * STRIPE_SIZE)));
+        }
+        tl2::STMResult::Ok(values)
+    }).unwrap();
+    for (i, v) in values.iter().enumerate() {
+        let expected = i as u64 * 3 + if GROUP.contains(&(i * STRIPE_SIZE)) { THREADS * INCREMENTS } else { 0 };
+        assert_eq!(*v, expected, "stripe {}", i);
+    }
+    // グループ外のストライプの version は変わらない
+    let versions = stm.version_vector();
+    for (i, v) in versions.iter().enumerate() {
+        assert_eq!(*v > 1, GROUP.contains(&(i * STRIPE_SIZE)), "stripe {}", i);
+    }
+    assert!(stm.locked_stripes().is_empty());
+}
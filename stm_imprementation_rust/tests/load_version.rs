@@ -0,0 +1,66 @@
+// load_version で読んだ version は、commit までに他の commit があれば競合として retry される
+
+use std::cell::Cell;
+
+use stm_rust::tl2::{self, STM};
+use stm_rust::{load, store};
+
+const RECORD: usize = 0;
+const LOG: usize = 8;
+
+// token が現在の version と一致すれば value を書き込む (一致しなければ Ok(false))
+fn update_if_current(stm: &STM, token: u64, value: u64) -> bool {
+    stm.write_transaction(|tr| {
+        let Some(version) = tr.load_version(RECORD) else {
+            return tl2::STMResult::Retry;
+        };
+        if version != token {
+            return tl2::STMResult::Ok(false);
+        }
+        store!(tr, RECORD, value.to_le_bytes());
+        tl2::STMResult::Ok(true)
+    }).unwrap()
+}
+
+#[test]
+fn version_tokens_detect_lost_updates() {
+    let stm = STM::new();
+    stm.write_transaction(|tr| {
+        store!(tr, RECORD, 1u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+
+    // トークンの払い出し
+    let token = stm.write_transaction(|tr| {
+        let _ = load!(tr, RECORD);
+        match tr.load_version(RECORD) {
+            Some(version) => tl2::STMResult::Ok(version),
+            None => tl2::STMResult::Retry,
+        }
+    }).unwrap();
+    assert_eq!(token, stm.version_vector()[RECORD / 8]);
+
+    assert!(update_if_current(&stm, token, 2));
+    assert!(!update_if_current(&stm, token, 3), "a stale token must be rejected");
+    assert_eq!(u64::from_le_bytes(stm.read_raw(RECORD)), 2);
+
+    // version を読んだ後に他のトランザクションが RECORD に commit する (初回の実行のみ)
+    let runs = Cell::new(0);
+    let seen = stm.write_transaction(|tr| {
+        runs.set(runs.get() + 1);
+        let Some(version) = tr.load_version(RECORD) else {
+            return tl2::STMResult::Retry;
+        };
+        if runs.get() == 1 {
+            stm.write_transaction(|other| {
+                store!(other, RECORD, 10u64.to_le_bytes());
+                tl2::STMResult::Ok(())
+            });
+        }
+        store!(tr, LOG, version.to_le_bytes());     // RECORD のデータには触れない
+        tl2::STMResult::Ok(version)
+    }).unwrap();
+    assert_eq!(runs.get(), 2, "a version change before commit must cause a retry");
+    assert_eq!(seen, stm.version_vector()[RECORD / 8]);
+    assert_eq!(u64::from_le_bytes(stm.read_raw(LOG)), seen);
+}
@@ -0,0 +1,83 @@
+// どの LockOrder でも送金の合計は保たれ、lock 中のストライプへの commit は lock_conflicts に数えられる
+
+use std::thread;
+use std::time::Duration;
+
+use stm_rust::retry::FixedDelay;
+use stm_rust::stepper::Phase;
+use stm_rust::tl2::{self, LockOrder, STM};
+use stm_rust::{load, store};
+
+const ACCOUNTS: usize = 16;
+const THREADS: usize = 3;
+const TRANSFERS: usize = 2000;
+const INITIAL: u64 = 1000;
+const HOT: usize = 64;
+
+fn transfers(order: LockOrder) {
+    let stm = STM::new().with_lock_order(order);
+    assert_eq!(stm.lock_order(), order);
+    stm.write_transaction(|tr| {
+        for i in 0..ACCOUNTS {
+            store!(tr, i * 8, INITIAL.to_le_bytes());
+        }
+        tl2::STMResult::Ok(())
+    });
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let stm = &stm;
+            s.spawn(move || {
+                for i in 0..TRANSFERS {
+                    // 4 つの口座から 1 ずつ、次の口座へ移す (書き込みは 4 ストライプ以上)
+                    let from: Vec<usize> = (0..4).map(|k| (i * 5 + t * 3 + k * 4) % ACCOUNTS).collect();
+                    stm.write_transaction(|tr| {
+                        for f in from.iter() {
+                            let to = (f + 1) % ACCOUNTS;
+                            let a = u64::from_le_bytes(load!(tr, f * 8));
+                            let b = u64::from_le_bytes(load!(tr, to * 8));
+                            if a > 0 {
+                                store!(tr, f * 8, (a - 1).to_le_bytes());
+                                store!(tr, to * 8, (b + 1).to_le_bytes());
+                            }
+                        }
+                        tl2::STMResult::Ok(())
+                    });
+                }
+            });
+        }
+    });
+    let total: u64 = (0..ACCOUNTS).map(|i| u64::from_le_bytes(stm.read_raw(i * 8))).sum();
+    assert_eq!(total, INITIAL * ACCOUNTS as u64, "{:?} lost an update", order);
+}
+
+#[test]
+fn lock_orders_keep_totals_and_count_conflicts() {
+    for order in [LockOrder::HashOrder, LockOrder::AddressAscending, LockOrder::ContentionDescending] {
+        transfers(order);
+    }
+
+    // HOT を lock した状態で止めておき、HOT を含む write_set の commit を 1 回だけ試みる
+    let stm = STM::new()
+        .with_lock_order(LockOrder::ContentionDescending)
+        .with_retry_policy(FixedDelay::new(Duration::ZERO).max_attempts(3));
+    let mut holder = stm.steppable(|tr| {
+        store!(tr, HOT, 1u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+    holder.next();
+    assert_eq!(holder.next(), Some(Phase::LockAcquire(HOT)));
+    let write = || stm.write_transaction(|tr| {
+        for addr in [0, 8, 16, HOT] {
+            store!(tr, addr, 2u64.to_le_bytes());
+        }
+        tl2::STMResult::Ok(())
+    });
+    assert_eq!(write(), None, "the held lock must make every attempt fail");
+    assert!(stm.lock_conflicts(HOT) > 0);
+    assert_eq!(stm.lock_conflicts(0), 0);
+    assert_eq!(stm.locked_stripes(), [HOT], "a failed attempt must release the locks it took");
+
+    for _ in holder.by_ref() {}
+    assert_eq!(write(), Some(()));
+    assert_eq!(u64::from_le_bytes(stm.read_raw(HOT)), 2);
+}
@@ -0,0 +1,78 @@
+// move_if は条件を満たす場合だけ値を移し、並行に移し続けてもトークンは複製も消失もしない
+
+use std::thread;
+
+use stm_rust::store;
+use stm_rust::tl2::{self, STM};
+
+const SLOTS: usize = 8;
+const TOKENS: u64 = 3;      // スロット 0..TOKENS に 1..=TOKENS を置く
+const THREADS: usize = 3;
+const MOVES: usize = 2000;
+
+#[test]
+fn conditional_moves_keep_tokens_unique() {
+    // 条件を満たす場合だけ移し、満たさなければどちらも変更しない
+    let stm = STM::new();
+    stm.write_transaction(|tr| {
+        store!(tr, 0, 5u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+    let is_odd = |v: [u8; 8]| u64::from_le_bytes(v) % 2 == 1;
+    let moved = stm.write_transaction(|tr| {
+        let Some(moved) = tr.move_if(0, 8, is_odd) else {
+            return tl2::STMResult::Retry;
+        };
+        tl2::STMResult::Ok(moved)
+    });
+    assert_eq!(moved, Some(true));
+    assert_eq!((stm.read_raw(0), stm.read_raw(8)), ([0; 8], 5u64.to_le_bytes()));
+
+    let version = stm.global_version();
+    let moved = stm.write_transaction(|tr| {
+        let Some(moved) = tr.move_if(8, 16, |v| u64::from_le_bytes(v) > 10) else {
+            return tl2::STMResult::Retry;
+        };
+        tl2::STMResult::Ok(moved)
+    });
+    assert_eq!(moved, Some(false));
+    assert_eq!((stm.read_raw(8), stm.read_raw(16)), (5u64.to_le_bytes(), [0; 8]), "a failed move must leave both stripes untouched");
+    assert_eq!(stm.global_version(), version);
+
+    // 2. 空いているスロットへのトークンの移動
+    let stm = STM::new();
+    stm.write_transaction(|tr| {
+        for t in 0..TOKENS {
+            store!(tr, t as usize * 8, (t + 1).to_le_bytes());
+        }
+        tl2::STMResult::Ok(())
+    });
+    thread::scope(|s| {
+        for w in 0..THREADS {
+            let stm = &stm;
+            s.spawn(move || {
+                for i in 0..MOVES {
+                    let from = (i * 3 + w) % SLOTS;
+                    let to = (i * 5 + w + 1) % SLOTS;
+                    if from == to {
+                        continue;
+                    }
+                    stm.write_transaction(|tr| {
+                        // 移動先が空いている場合だけ移す
+                        let Some(dest) = tr.load(to * 8) else {
+                            return tl2::STMResult::Retry;
+                        };
+                        let free = dest == [0; 8];
+                        let Some(moved) = tr.move_if(from * 8, to * 8, |v| free && v != [0; 8]) else {
+                            return tl2::STMResult::Retry;
+                        };
+                        tl2::STMResult::Ok(moved)
+                    });
+                }
+            });
+        }
+    });
+    let mut tokens: Vec<u64> = (0..SLOTS).map(|i| u64::from_le_bytes(stm.read_raw(i * 8))).filter(|v| *v != 0).collect();
+    tokens.sort();
+    assert_eq!(tokens, (1..=TOKENS).collect::<Vec<_>>(), "a token was duplicated or lost");
+}
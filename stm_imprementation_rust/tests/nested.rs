@@ -0,0 +1,72 @@
+// Abort した入れ子のトランザクションの書き込みと on_commit だけが破棄され、親の書き込みは commit される
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use stm_rust::tl2::{self, STM};
+use stm_rust::{load, store};
+
+const PARENT: usize = 0;
+const NESTED: usize = 8;
+const SHARED: usize = 16;
+const MERGED: usize = 24;
+
+#[test]
+fn aborted_nested_writes_are_discarded() {
+    let stm = STM::new();
+    let callbacks = Rc::new(Cell::new(0));
+
+    let (aborted, merged) = stm.write_transaction(|tr| {
+        store!(tr, PARENT, 1u64.to_le_bytes());
+        store!(tr, SHARED, 1u64.to_le_bytes());
+
+        // 入れ子のトランザクションが Abort する: 親の書き込み (SHARED = 1) は残る
+        let aborted = match tr.nested(|ntr| {
+            store!(ntr, NESTED, 2u64.to_le_bytes());
+            store!(ntr, SHARED, 2u64.to_le_bytes());
+            let callbacks = callbacks.clone();
+            ntr.on_commit(move || callbacks.set(callbacks.get() + 1));
+            assert_eq!(u64::from_le_bytes(load!(ntr, SHARED)), 2);
+            tl2::STMResult::<()>::Abort
+        }) {
+            tl2::STMResult::Ok(r) => r,
+            _ => return tl2::STMResult::Retry,
+        };
+        assert_eq!(u64::from_le_bytes(load!(tr, SHARED)), 1, "the nested write must be discarded");
+        assert_eq!(tr.pending_callbacks(), 0);
+
+        // 入れ子のトランザクションが Ok を返す: 書き込みは親に加わる
+        let merged = match tr.nested(|ntr| {
+            let v = u64::from_le_bytes(load!(ntr, SHARED));
+            store!(ntr, MERGED, (v + 10).to_le_bytes());
+            tl2::STMResult::Ok(v)
+        }) {
+            tl2::STMResult::Ok(r) => r,
+            _ => return tl2::STMResult::Retry,
+        };
+        tl2::STMResult::Ok((aborted, merged))
+    }).unwrap();
+
+    assert_eq!(aborted, None);
+    assert_eq!(merged, Some(1));
+    assert_eq!(u64::from_le_bytes(stm.read_raw(PARENT)), 1);
+    assert_eq!(u64::from_le_bytes(stm.read_raw(SHARED)), 1);
+    assert_eq!(stm.read_raw(NESTED), [0; 8], "writes of the aborted nested transaction must vanish");
+    assert_eq!(u64::from_le_bytes(stm.read_raw(MERGED)), 11);
+    assert_eq!(callbacks.get(), 0, "callbacks of the aborted nested transaction must not run");
+    assert_eq!(stm.version_vector()[NESTED / 8], 0);
+
+    // 入れ子の中の request_abort は親全体を abort する
+    let result = stm.write_transaction(|tr| {
+        store!(tr, PARENT, 5u64.to_le_bytes());
+        match tr.nested(|ntr| {
+            ntr.request_abort();
+            tl2::STMResult::Ok(())
+        }) {
+            tl2::STMResult::Abort => tl2::STMResult::Abort,
+            _ => tl2::STMResult::Ok(()),
+        }
+    });
+    assert_eq!(result, None);
+    assert_eq!(u64::from_le_bytes(stm.read_raw(PARENT)), 1);
+}
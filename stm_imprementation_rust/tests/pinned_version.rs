@@ -0,0 +1,57 @@
+// write_transaction_at で固定した version より後に更新されたストライプの読み込みは None になる
+
+use std::cell::Cell;
+
+use stm_rust::tl2::{self, STM};
+use stm_rust::{load, store};
+
+const A: usize = 0;
+const B: usize = 8;
+const C: usize = 16;
+const D: usize = 24;
+
+#[test]
+fn pinned_reads_fail_past_newer_commits() {
+    let stm = STM::new();
+    stm.write_transaction(|tr| {
+        store!(tr, A, 1u64.to_le_bytes());
+        store!(tr, B, 10u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+    let pinned = stm.global_version();
+
+    // 固定した version より後の commit
+    stm.write_transaction(|tr| {
+        store!(tr, B, 20u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+    stm.write_transaction(|tr| {
+        store!(tr, C, 30u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+    assert!(stm.global_version() > pinned);
+
+    // a は pinned の時点から変わっていないため、pinned の時点の値を読んで commit できる
+    let a = stm.write_transaction_at(pinned, |tr| {
+        let a = u64::from_le_bytes(load!(tr, A));
+        store!(tr, D, (a * 100).to_le_bytes());
+        tl2::STMResult::Ok(a)
+    });
+    assert_eq!(a, Some(1));
+    assert_eq!(u64::from_le_bytes(stm.read_raw(D)), 100);
+
+    // b は pinned の後に更新されたため、固定した version では読めない (retry しても変わらないので 1 回で諦める)
+    let runs = Cell::new(0);
+    let b = stm.write_transaction_at(pinned, |tr| {
+        runs.set(runs.get() + 1);
+        let b = u64::from_le_bytes(load!(tr, B));
+        store!(tr, D, b.to_le_bytes());
+        tl2::STMResult::Ok(b)
+    });
+    assert_eq!(b, None);
+    assert_eq!(runs.get(), 1);
+    assert_eq!(u64::from_le_bytes(stm.read_raw(D)), 100, "an aborted pinned transaction must not write");
+
+    // まだ割り当てられていない version には固定できない
+    assert_eq!(stm.write_transaction_at(stm.global_version() + 1, |_| tl2::STMResult::Ok(())), None);
+}
@@ -0,0 +1,92 @@
+// promote したトランザクションは読み込みを引き継いで commit し、途中の更新は None / Conflict になる
+
+use std::thread;
+
+use stm_rust::tl2::{self, ApplyOutcome, STM};
+use stm_rust::store;
+
+const A: usize = 0;
+const B: usize = 8;
+const THREADS: u64 = 4;
+const INCREMENTS: u64 = 500;
+
+#[test]
+fn promoted_transactions_commit_or_conflict() {
+    let stm = STM::new().with_read_heat();
+    stm.write_transaction(|tr| {
+        store!(tr, A, 5u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+
+    // 1. 読んだ値から B を書き込む
+    let mut tx = stm.read_transaction(|tr| {
+        let Some(a) = tr.load(A) else {
+            return tl2::STMResult::Retry;
+        };
+        assert!(u64::from_le_bytes(a) < 10);
+        match tr.promote(&stm) {
+            Some(tx) => tl2::STMResult::Ok(tx),
+            None => tl2::STMResult::Retry,
+        }
+    }).unwrap();
+    let a = u64::from_le_bytes(tx.load(A).unwrap());
+    tx.store(B, (a * 2).to_le_bytes());
+    assert!(matches!(tx.finish(), ApplyOutcome::Committed(_)));
+    assert_eq!(stm.read_heat(), [(A, 1)], "a promoted load must not re-read the stripe");
+    assert_eq!(u64::from_le_bytes(stm.read_raw(B)), 10);
+
+    // 2. promote の前に更新された場合は None (読み直す)
+    let promoted = stm.read_transaction(|tr| {
+        let _ = tr.load(A);
+        stm.write_transaction(|other| {
+            store!(other, A, 6u64.to_le_bytes());
+            tl2::STMResult::Ok(())
+        });
+        tl2::STMResult::Ok(tr.promote(&stm).is_some())
+    }).unwrap();
+    assert!(!promoted, "a stale snapshot must not be promoted");
+
+    //    promote の後に更新された場合は、引き継いだ read_set の検証で Conflict となる
+    let mut tx = stm.read_transaction(|tr| {
+        let _ = tr.load(A);
+        tl2::STMResult::Ok(tr.promote(&stm).unwrap())
+    }).unwrap();
+    tx.store(B, 0u64.to_le_bytes());
+    stm.write_transaction(|other| {
+        store!(other, A, 7u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+    assert_eq!(tx.finish(), ApplyOutcome::Conflict { addr: Some(A) });
+    assert_eq!(u64::from_le_bytes(stm.read_raw(B)), 10);
+
+    // 3. 読み込みから promote して加算する (競合した場合は読み込みからやり直す)
+    let start = u64::from_le_bytes(stm.read_raw(A));
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                for _ in 0..INCREMENTS {
+                    loop {
+                        let promoted = stm.read_transaction(|tr| {
+                            let Some(_) = tr.load(A) else {
+                                return tl2::STMResult::Retry;
+                            };
+                            match tr.promote(&stm) {
+                                Some(tx) => tl2::STMResult::Ok(tx),
+                                None => tl2::STMResult::Retry,
+                            }
+                        });
+                        let Some(mut tx) = promoted else {
+                            continue;   // retry の上限に達した場合も読み込みからやり直す
+                        };
+                        let a = u64::from_le_bytes(tx.load(A).unwrap());
+                        tx.store(A, (a + 1).to_le_bytes());
+                        if let ApplyOutcome::Committed(_) = tx.finish() {
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    assert_eq!(u64::from_le_bytes(stm.read_raw(A)), start + THREADS * INCREMENTS, "promoted increments were lost");
+}
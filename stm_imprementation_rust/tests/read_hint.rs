@@ -0,0 +1,46 @@
+// ReadHint は global_clock の読み込みを省略し、古い hint は競合として読み直される
+
+use stm_rust::tl2::{self, WriteTrans, STM};
+use stm_rust::{load, store};
+
+const COUNTER: usize = 0;
+const ROUNDS: u64 = 100;
+
+fn increment(tr: &mut WriteTrans<'_>) -> tl2::STMResult<u64> {
+    let v = u64::from_le_bytes(load!(tr, COUNTER)) + 1;
+    store!(tr, COUNTER, v.to_le_bytes());
+    tl2::STMResult::Ok(v)
+}
+
+#[test]
+fn hints_skip_global_clock_reads() {
+    // 1. 新しい hint
+    let stm = STM::new().with_stats();
+    let mut hint = stm.read_hint();
+    for round in 1..=ROUNDS {
+        assert_eq!(stm.write_transaction_hinted(&mut hint, increment), Some(round));
+        assert_eq!(hint.version(), stm.global_version(), "the hint must follow this thread's commits");
+    }
+    let stats = stm.stats().unwrap();
+    assert_eq!((stats.read_hints, stats.stale_hints, stats.conflict_aborts), (ROUNDS, 0, 0));
+
+    // 2. 古い hint: 取得後に COUNTER が他のトランザクションから更新される
+    let stale = stm.read_hint();
+    stm.write_transaction(increment);
+    let mut hint = stale;
+    assert_eq!(stm.write_transaction_hinted(&mut hint, increment), Some(ROUNDS + 2), "a stale hint must not hide the newer commit");
+    let stats = stm.stats().unwrap();
+    assert_eq!((stats.read_hints, stats.stale_hints), (ROUNDS + 1, 1));
+    assert_eq!(hint.version(), stm.global_version());
+
+    // 3. 他の STM の hint (version が大きくても read_version にしない)
+    let other = STM::new();
+    for _ in 0..5 {
+        other.write_transaction(increment);
+    }
+    let fresh = STM::new().with_stats();
+    let mut foreign = other.read_hint();
+    assert_eq!(fresh.write_transaction_hinted(&mut foreign, increment), Some(1));
+    assert_eq!(fresh.stats().unwrap().read_hints, 0);
+    assert_eq!(foreign.version(), other.global_version(), "a foreign hint is left untouched");
+}
@@ -0,0 +1,84 @@
+// WriteTrans::scratch のバッファは再実行をまたいで使い回され、再確保されない
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::mem;
+
+use stm_rust::tl2::{self, ScratchBuf, WaitPolicy, WriteTrans, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+struct CountingAlloc;
+
+thread_local! {
+    // test harness の他のスレッドの確保を数えないよう、スレッドごとに数える
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const STRIPES: usize = 4;
+const RUNS: usize = 10;
+
+// 各実行の closure 内で起きた確保の回数を返す
+fn run(use_scratch: bool) -> Vec<usize> {
+    let stm = STM::builder().set_capacity(STRIPES, 1).build();
+    let allocations = Cell::new(Vec::with_capacity(RUNS));
+    let runs = Cell::new(0);
+
+    let gather = |tr: &mut WriteTrans<'_>| {
+        let before = ALLOCATIONS.with(Cell::get);
+
+        // 読み込みと交互に使うため、scratch は取り出してから使い、最後に戻す
+        let mut buf = if use_scratch { mem::take(tr.scratch()) } else { ScratchBuf::default() };
+        for i in 0..STRIPES {
+            let val = load!(tr, i * STRIPE_SIZE);
+            buf.extend_from_slice(&val);
+        }
+        let sum: u64 = buf.iter().map(|b| *b as u64).sum();
+        if use_scratch {
+            *tr.scratch() = buf;
+        }
+
+        let after = ALLOCATIONS.with(Cell::get);
+        let mut v = allocations.take();
+        v.push(after - before);
+        allocations.set(v);
+
+        runs.set(runs.get() + 1);
+        if runs.get() < RUNS {
+            return tl2::STMResult::RetryOk;     // 条件待ちの再実行を模擬する
+        }
+        store!(tr, STRIPES * STRIPE_SIZE, sum.to_le_bytes());
+        tl2::STMResult::Ok(())
+    };
+    stm.retry_until(gather, WaitPolicy::Spin).unwrap();
+    allocations.take()
+}
+
+#[test]
+fn retries_reuse_the_scratch_buffer() {
+    let with_scratch = run(true);
+    assert_eq!(with_scratch.len(), RUNS);
+    assert!(with_scratch[1..].iter().all(|n| *n == 0), "scratch allocated on retry: {:?}", with_scratch);
+
+    let without_scratch = run(false);
+    assert!(without_scratch.iter().all(|n| *n > 0), "expected allocations without scratch: {:?}", without_scratch);
+
+}
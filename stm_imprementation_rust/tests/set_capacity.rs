@@ -0,0 +1,58 @@
+// with_set_capacity で予め確保した数までのアドレスに触れても read_set / write_set は再確保されない (global allocator を使うため独立した test binary とする)
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use stm_rust::tl2::{self, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };    // このスレッドでの alloc / realloc の回数
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// addrs 個のストライプを読んでから書き込むトランザクション 1 回の間の確保の回数
+fn allocations(stm: &STM, addrs: usize) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    stm.write_transaction(|tr| {
+        for i in 0..addrs {
+            let v = u64::from_le_bytes(load!(tr, i * STRIPE_SIZE));
+            store!(tr, i * STRIPE_SIZE, (v + 1).to_le_bytes());
+        }
+        tl2::STMResult::Ok(())
+    }).unwrap();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn presized_sets_do_not_grow() {
+    const MAX: usize = 16;
+    let stm = STM::new().with_set_capacity(MAX, MAX);
+    let counts: Vec<usize> = (1..=MAX).map(|addrs| allocations(&stm, addrs)).collect();
+    // 確保は WriteTrans::new の初期確保のみで、触れるアドレスの数によらない
+    assert!(counts.iter().all(|n| *n == counts[0]), "presized sets reallocated: {:?}", counts);
+
+    // 容量を指定しない場合は、アドレスが増えると集合の再確保が起こる (上の検査が再確保を検出できることの確認)
+    let stm = STM::new();
+    assert!(allocations(&stm, MAX) > allocations(&stm, 1));
+}
@@ -0,0 +1,46 @@
+// shutdown は条件を待っているトランザクションを Err(ShuttingDown) で終わらせる
+
+use std::thread;
+use std::time::Duration;
+
+use stm_rust::tl2::{self, ShuttingDown, WaitPolicy, WriteTrans, STM};
+use stm_rust::{load, store};
+
+const SIGNAL: usize = 0;    // 0 のままにしておく
+
+fn wait_for_signal(tr: &mut WriteTrans<'_>) -> tl2::STMResult<u64> {
+    let signal = u64::from_le_bytes(load!(tr, SIGNAL));
+    if signal == 0 {
+        return tl2::STMResult::RetryOk;
+    }
+    tl2::STMResult::Ok(signal)
+}
+
+#[test]
+fn shutdown_wakes_waiting_transactions() {
+    let stm = STM::new();
+    assert!(!stm.is_shutting_down());
+
+    thread::scope(|s| {
+        let blocked = s.spawn(|| stm.try_retry_until(wait_for_signal, WaitPolicy::Block));
+        let spinning = s.spawn(|| stm.try_retry_until(wait_for_signal, WaitPolicy::default()));
+
+        thread::sleep(Duration::from_millis(50));   // 両方のスレッドが待機に入るまで待つ
+        assert!(!blocked.is_finished() && !spinning.is_finished(), "the condition is never met, so both must still wait");
+        stm.shutdown();
+
+        assert_eq!(blocked.join().unwrap(), Err(ShuttingDown));
+        assert_eq!(spinning.join().unwrap(), Err(ShuttingDown));
+    });
+    assert!(stm.is_shutting_down());
+
+    // shutdown 後に待機を始めたトランザクションも待たずに戻る
+    assert_eq!(stm.try_retry_until(wait_for_signal, WaitPolicy::Block), Err(ShuttingDown));
+
+    // 通常の commit と、条件を満たしているトランザクションは影響を受けない
+    stm.write_transaction(|tr| {
+        store!(tr, SIGNAL, 7u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+    assert_eq!(stm.try_retry_until(wait_for_signal, WaitPolicy::Block), Ok(Some(7)));
+}
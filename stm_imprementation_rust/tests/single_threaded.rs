@@ -0,0 +1,74 @@
+// STM::single_threaded は STM::new() と同じ結果を返し、競合も検出する
+
+use std::cell::Cell;
+use std::thread;
+
+use stm_rust::tl2::{self, MEM_SIZE, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const STRIPES: usize = MEM_SIZE / STRIPE_SIZE;
+const STEPS: u64 = 1000;
+
+// 簡単な線形合同法で決まるアドレスの間で値を移動し、読んだ合計を返す
+fn replay(stm: &STM) -> Vec<u64> {
+    let mut seed = 1u64;
+    let mut next = move || {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) as usize % STRIPES
+    };
+    let mut sums = Vec::new();
+    for step in 0..STEPS {
+        let (from, to) = (next() * STRIPE_SIZE, next() * STRIPE_SIZE);
+        stm.write_transaction(|tr| {
+            let a = u64::from_le_bytes(load!(tr, from));
+            store!(tr, from, (a + step).to_le_bytes());
+            let b = u64::from_le_bytes(load!(tr, to));
+            store!(tr, to, (b ^ a).to_le_bytes());
+            tl2::STMResult::Ok(())
+        });
+        let sum = stm.read_transaction(|tr| {
+            let mut sum = 0u64;
+            for i in 0..STRIPES {
+                sum = sum.wrapping_add(u64::from_le_bytes(load!(tr, i * STRIPE_SIZE)));
+            }
+            tl2::STMResult::Ok(sum)
+        }).unwrap();
+        sums.push(sum);
+    }
+    sums
+}
+
+#[test]
+fn single_threaded_runs_replay_identically() {
+    let checked = STM::new();
+    let fast = STM::single_threaded();
+    assert_eq!(replay(&checked), replay(&fast), "single-threaded mode changed a transaction's result");
+    for i in 0..STRIPES {
+        assert_eq!(checked.read_raw(i * STRIPE_SIZE), fast.read_raw(i * STRIPE_SIZE));
+    }
+
+    // 入れ子に commit したトランザクションが読み込み前のストライプを更新した場合は、copy の前の検査で retry する
+    let runs = Cell::new(0);
+    fast.write_transaction(|tr| {
+        runs.set(runs.get() + 1);
+        if runs.get() == 1 {
+            fast.write_transaction(|inner| {
+                store!(inner, 0, 1u64.to_le_bytes());
+                tl2::STMResult::Ok(())
+            });
+        }
+        let v = u64::from_le_bytes(load!(tr, 0));
+        store!(tr, 8, v.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+    assert_eq!(runs.get(), 2);
+    assert_eq!(fast.read_raw(8), 1u64.to_le_bytes());
+
+    // デバッグビルドでは、別のスレッドからの load は panic する
+    if cfg!(debug_assertions) {
+        let result = thread::scope(|s| {
+            s.spawn(|| fast.read_transaction(|tr| tl2::STMResult::Ok(load!(tr, 0)))).join()
+        });
+        assert!(result.is_err(), "use from a second thread must be caught in debug builds");
+    }
+}
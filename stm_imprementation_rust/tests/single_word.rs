@@ -0,0 +1,90 @@
+// load_single_word の読み込みは tear-free で、一貫したスナップショットの値を返す
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::thread;
+
+use stm_rust::tl2::{self, MEM_SIZE, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const STRIPES: usize = MEM_SIZE / STRIPE_SIZE;
+const WRITERS: usize = 2;
+const OBSERVATIONS: usize = 200;
+const INITIAL: u64 = 100;
+
+#[test]
+fn single_word_loads_are_tear_free() {
+    let stm = STM::new();
+    stm.write_transaction(|tr| {
+        for i in 1..STRIPES {
+            store!(tr, i * STRIPE_SIZE, INITIAL.to_le_bytes());
+        }
+        tl2::STMResult::Ok(())
+    });
+    let expected = INITIAL * (STRIPES as u64 - 1);
+    let done = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        for w in 0..WRITERS {
+            let (stm, done) = (&stm, &done);
+            s.spawn(move || {
+                let mut i = 0usize;
+                while !done.load(Relaxed) {
+                    i += 1;
+                    stm.write_transaction(|tr| {
+                        store!(tr, 0, ((i % 256) as u64 * 0x0101010101010101).to_le_bytes());
+                        tl2::STMResult::Ok(())
+                    });
+                    let from = 1 + (i * 7 + w) % (STRIPES - 1);
+                    let to = 1 + (i * 13 + w + 1) % (STRIPES - 1);
+                    if from == to {
+                        continue;
+                    }
+                    stm.write_transaction(|tr| {
+                        let a = u64::from_le_bytes(load!(tr, from * STRIPE_SIZE));
+                        let b = u64::from_le_bytes(load!(tr, to * STRIPE_SIZE));
+                        let amount = a.min(5);
+                        store!(tr, from * STRIPE_SIZE, (a - amount).to_le_bytes());
+                        store!(tr, to * STRIPE_SIZE, (b + amount).to_le_bytes());
+                        tl2::STMResult::Ok(())
+                    });
+                }
+            });
+        }
+
+        for _ in 0..OBSERVATIONS {
+            let (pattern, total, again) = stm.read_transaction(|tr| {
+                let Some(pattern) = tr.load_single_word(0) else {
+                    return tl2::STMResult::Retry;
+                };
+                let mut total = 0;
+                for i in 1..STRIPES {
+                    let Some(v) = tr.load_single_word(i * STRIPE_SIZE) else {
+                        return tl2::STMResult::Retry;
+                    };
+                    total += v;
+                }
+                // cache に記録しないため再びメモリから読むが、値は同じスナップショットのもの
+                let Some(again) = tr.load_single_word(0) else {
+                    return tl2::STMResult::Retry;
+                };
+                tl2::STMResult::Ok((pattern, total, again))
+            }).unwrap();
+            let bytes = pattern.to_le_bytes();
+            assert!(bytes.iter().all(|b| *b == bytes[0]), "torn read: {:#x}", pattern);
+            assert_eq!(total, expected, "load_single_word observed an inconsistent snapshot");
+            assert_eq!(again, pattern);
+            thread::yield_now();    // writer に実行の機会を与える
+        }
+        done.store(true, Relaxed);
+    });
+
+    // ストライプの値は load と同じ
+    let (word, bytes) = stm.read_transaction(|tr| {
+        let Some(word) = tr.load_single_word(8) else {
+            return tl2::STMResult::Retry;
+        };
+        tl2::STMResult::Ok((word, u64::from_le_bytes(load!(tr, 8))))
+    }).unwrap();
+    assert_eq!(word, bytes);
+}
@@ -0,0 +1,60 @@
+// with_speculation_limit を超えた遅い実行は commit を試みずに retry され、AbortReason::Slow として数えられる
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+use std::thread;
+use std::time::Duration;
+
+use stm_rust::retry::FixedDelay;
+use stm_rust::tl2::{self, AbortReason, WriteTrans, STM};
+use stm_rust::{load, store};
+
+const COUNTER: usize = 0;
+const LIMIT: Duration = Duration::from_millis(5);
+const SLOW_RUNS: usize = 2;
+
+fn increment(tr: &mut WriteTrans<'_>) -> tl2::STMResult<()> {
+    let val = u64::from_le_bytes(load!(tr, COUNTER));
+    store!(tr, COUNTER, (val + 1).to_le_bytes());
+    tl2::STMResult::Ok(())
+}
+
+#[test]
+fn slow_runs_abort_without_committing() {
+    let stm = STM::builder()
+        .speculation_limit(LIMIT)
+        .retry_policy(FixedDelay::new(Duration::from_micros(10)))
+        .with_stats()
+        .build();
+    let done = AtomicBool::new(false);
+    let runs = AtomicUsize::new(0);
+    let slow_runs = AtomicUsize::new(0);
+
+    let increments = stm.scope(|s| {
+        let contender = s.spawn(|stm| {
+            let mut n = 0;
+            while !done.load(Relaxed) {
+                stm.write_transaction(increment).unwrap();
+                n += 1;
+            }
+            n
+        });
+        stm.write_transaction(|tr| {
+            runs.fetch_add(1, Relaxed);
+            let val = u64::from_le_bytes(load!(tr, COUNTER));
+            if slow_runs.load(Relaxed) < SLOW_RUNS {
+                slow_runs.fetch_add(1, Relaxed);
+                thread::sleep(LIMIT * 2);      // 重い計算を模擬する
+            }
+            store!(tr, COUNTER, (val + 1).to_le_bytes());
+            tl2::STMResult::Ok(())
+        }).unwrap();
+        done.store(true, Relaxed);
+        contender.join().unwrap()
+    });
+
+    let counter = u64::from_le_bytes(stm.read_raw(COUNTER));
+    assert_eq!(counter, increments + 1);
+    let stats = stm.stats().unwrap();
+    assert_eq!(stats.aborts(AbortReason::Slow), SLOW_RUNS as u64);
+    assert!(runs.load(Relaxed) > SLOW_RUNS);
+}
@@ -0,0 +1,59 @@
+// split_advisor は検証に最も多く失敗したアドレスを報告し、小さなトランザクションは報告しない
+
+use std::cell::Cell;
+use std::sync::mpsc::TryRecvError;
+
+use stm_rust::tl2::{self, SplitAdvice, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const HOT: usize = 0;
+const WIDE: usize = 16;
+const CONFLICTS: usize = 5;
+
+fn bump_hot(stm: &STM) {
+    stm.write_transaction(|tr| {
+        let v = u64::from_le_bytes(load!(tr, HOT));
+        store!(tr, HOT, (v + 1).to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+}
+
+#[test]
+fn advisor_reports_the_hot_address() {
+    let stm = STM::new();
+    let advice = stm.split_advisor(8, 3);
+
+    let runs = Cell::new(0);
+    stm.write_transaction(|tr| {
+        runs.set(runs.get() + 1);
+        let hot = u64::from_le_bytes(load!(tr, HOT));
+        if runs.get() <= CONFLICTS {
+            bump_hot(&stm);     // 読んだ後、commit する前に HOT が更新される
+        }
+        for i in 1..=WIDE {
+            store!(tr, i * STRIPE_SIZE, hot.to_le_bytes());
+        }
+        tl2::STMResult::Ok(())
+    }).unwrap();
+    assert_eq!(runs.get(), CONFLICTS + 1);
+
+    let SplitAdvice { write_set_len, aborts, offenders } = advice.try_recv().unwrap();
+    assert_eq!(write_set_len, WIDE);
+    assert_eq!(aborts, 3, "advice is sent once the threshold is reached");
+    assert_eq!(offenders[0], (HOT, 3));
+    assert_eq!(advice.try_recv(), Err(TryRecvError::Empty), "at most one advice per transaction");
+
+    // 書き込みが少ないトランザクションは、検証に繰り返し失敗しても報告しない
+    runs.set(0);
+    stm.write_transaction(|tr| {
+        runs.set(runs.get() + 1);
+        let hot = u64::from_le_bytes(load!(tr, HOT));
+        if runs.get() <= CONFLICTS {
+            bump_hot(&stm);
+        }
+        store!(tr, STRIPE_SIZE, hot.to_le_bytes());
+        store!(tr, 2 * STRIPE_SIZE, hot.to_le_bytes());
+        tl2::STMResult::Ok(())
+    }).unwrap();
+    assert_eq!(advice.try_recv(), Err(TryRecvError::Empty));
+}
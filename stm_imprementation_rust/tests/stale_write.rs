@@ -0,0 +1,61 @@
+// 古い値を読んで書き込むトランザクションは、検証のどの経路でも必ず retry される
+
+use std::cell::Cell;
+
+use stm_rust::tl2::{self, WriteTrans, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const A: usize = 0;
+const B: usize = STRIPE_SIZE;
+const OTHER: usize = 2 * STRIPE_SIZE;
+
+fn check(name: &str, write_b: bool, unrelated_commits: usize) {
+    let stm = STM::new();
+    let runs = Cell::new(0);
+
+    let increment = |tr: &mut WriteTrans<'_>| {
+        runs.set(runs.get() + 1);
+        let a = u64::from_le_bytes(load!(tr, A));
+
+        if runs.get() == 1 {
+            // A への commit を割り込ませる (closure の実行中は lock を保持していない)
+            stm.write_transaction(|tr| {
+                store!(tr, A, 100u64.to_le_bytes());
+                tl2::STMResult::Ok(())
+            }).unwrap();
+            for i in 0..unrelated_commits {
+                stm.write_transaction(|tr| {
+                    store!(tr, OTHER, (i as u64).to_le_bytes());
+                    tl2::STMResult::Ok(())
+                }).unwrap();
+            }
+        }
+
+        store!(tr, A, (a + 1).to_le_bytes());
+        if write_b {
+            store!(tr, B, (a + 1).to_le_bytes());
+        }
+        tl2::STMResult::Ok(())
+    };
+    let (_, timing) = stm.write_transaction_traced(increment).unwrap();
+
+    let a = stm.read_transaction(|tr| tl2::STMResult::Ok(u64::from_le_bytes(load!(tr, A)))).unwrap();
+    assert_eq!(a, 101, "{}: committed a value derived from a stale read", name);
+    assert_eq!(runs.get(), 2, "{}: expected exactly one retry", name);
+    assert_eq!(timing.last_conflict, Some(A), "{}: wrong conflicting address", name);
+}
+
+#[test]
+fn single_stripe() {
+    check("single stripe", false, 0);
+}
+
+#[test]
+fn multiple_stripes() {
+    check("multiple stripes", true, 0);
+}
+
+#[test]
+fn multiple_stripes_lagging_read_version() {
+    check("multiple stripes, lagging read version", true, 5);
+}
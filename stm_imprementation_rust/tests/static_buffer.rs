@@ -0,0 +1,57 @@
+// from_mut_slice で呼び出し側のバッファの上に作成した STM は、バッファの長さの範囲で正しく動く
+
+use std::thread;
+
+use stm_rust::tl2::{self, Memory, MemoryError, STM, STRIPE_SIZE};
+use stm_rust::{load, store};
+
+const BUFFER_SIZE: usize = 128;
+const THREADS: u64 = 4;
+const INCREMENTS: u64 = 2000;
+
+static mut BUFFER: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+
+#[test]
+fn counter_on_a_static_buffer() {
+    // 組み込み環境の static なバッファと同様に、1 度だけ &'static mut を取り出す
+    let buffer: &'static mut [u8] = unsafe { &mut *std::ptr::addr_of_mut!(BUFFER) };
+    buffer[8..16].copy_from_slice(&42u64.to_le_bytes());
+    let mem: Memory = Memory::from_mut_slice(buffer).unwrap();
+    assert_eq!(mem.size(), BUFFER_SIZE);
+    let stm = STM::from_memory(mem);
+    assert_eq!(stm.version_vector().len(), BUFFER_SIZE / STRIPE_SIZE);
+
+    // 初期値は commit 済みの値として読める
+    let initial = stm.read_transaction(|tr| tl2::STMResult::Ok(u64::from_le_bytes(load!(tr, 8)))).unwrap();
+    assert_eq!(initial, 42);
+
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                for _ in 0..INCREMENTS {
+                    stm.write_transaction(|tr| {
+                        let v = u64::from_le_bytes(load!(tr, 0)) + 1;
+                        store!(tr, 0, v.to_le_bytes());
+                        store!(tr, BUFFER_SIZE - STRIPE_SIZE, v.to_le_bytes());     // バッファの最後のストライプ
+                        tl2::STMResult::Ok(())
+                    });
+                }
+            });
+        }
+    });
+
+    let (count, last) = stm.read_transaction(|tr| {
+        let count = u64::from_le_bytes(load!(tr, 0));
+        let last = u64::from_le_bytes(load!(tr, BUFFER_SIZE - STRIPE_SIZE));
+        tl2::STMResult::Ok((count, last))
+    }).unwrap();
+    assert_eq!(count, THREADS * INCREMENTS);
+    assert_eq!(last, count);
+
+    // ストライプの大きさの倍数でないバッファは拒否する
+    let odd: &'static mut [u8] = Box::leak(Box::new([0u8; 12]));
+    assert_eq!(
+        Memory::<8>::from_mut_slice(odd).err(),
+        Some(MemoryError::InvalidBufferLength { len: 12, stripe_size: STRIPE_SIZE })
+    );
+}
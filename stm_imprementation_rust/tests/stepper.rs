@@ -0,0 +1,60 @@
+// steppable のステップは TL2 の commit の手順どおりに進み、検証の前の更新で失敗する
+
+use stm_rust::stepper::Phase;
+use stm_rust::tl2::{self, STM};
+use stm_rust::{load, store};
+
+#[test]
+fn steps_follow_the_commit_protocol() {
+    let stm = STM::new();
+    stm.write_transaction(|tr| {
+        store!(tr, 0, 5u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+
+    // 0 を読み、16 と 8 に書き込む
+    let mut tx = stm.steppable(|tr| {
+        let v = u64::from_le_bytes(load!(tr, 0));
+        store!(tr, 16, (v * 2).to_le_bytes());
+        store!(tr, 8, (v + 1).to_le_bytes());
+        tl2::STMResult::Ok(v)
+    });
+    let mut phases = Vec::new();
+    for phase in tx.by_ref() {
+        if let Phase::LockAcquire(addr) = phase {
+            assert!(stm.locked_stripes().contains(&addr));
+        }
+        phases.push(phase);
+    }
+    assert_eq!(phases, vec![
+        Phase::Read(0, 5u64.to_le_bytes()),
+        Phase::Write(16, 10u64.to_le_bytes()),
+        Phase::Write(8, 6u64.to_le_bytes()),
+        Phase::LockAcquire(8),
+        Phase::LockAcquire(16),
+        Phase::Validate(Ok(())),
+        Phase::Commit(2),
+    ]);
+    assert_eq!(tx.into_result(), Some(5));
+    assert_eq!(u64::from_le_bytes(stm.read_raw(8)), 6);
+    assert!(stm.locked_stripes().is_empty());
+
+    // lock の獲得後、検証の前に読んだストライプ (0) が更新される
+    let mut tx = stm.steppable(|tr| {
+        let v = u64::from_le_bytes(load!(tr, 0));
+        store!(tr, 8, v.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+    assert!(matches!(tx.next(), Some(Phase::Read(0, _))));
+    assert!(matches!(tx.next(), Some(Phase::Write(8, _))));
+    assert_eq!(tx.next(), Some(Phase::LockAcquire(8)));
+    stm.write_transaction(|tr| {
+        store!(tr, 0, 7u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+    assert_eq!(tx.next(), Some(Phase::Validate(Err(0))));
+    assert_eq!(tx.next(), None);
+    assert_eq!(tx.into_result(), None);
+    assert_eq!(u64::from_le_bytes(stm.read_raw(8)), 6);     // 書き込まれていない
+    assert!(stm.locked_stripes().is_empty());
+}
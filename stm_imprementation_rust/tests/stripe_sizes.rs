@@ -0,0 +1,86 @@
+// ストライプの大きさが異なる STM<8> と STM<16> を併用しても、どちらの送金の合計も保たれる
+
+use std::thread;
+
+use stm_rust::tl2::{self, STM};
+use stm_rust::{load, store};
+
+const THREADS: usize = 4;
+const TRANSFERS: u64 = 2000;
+const INITIAL: u64 = 1000;
+
+#[test]
+fn transfers_conserve_totals_in_both_sizes() {
+    let small: STM<8> = STM::new();
+    let large: STM<16> = STM::new_sized();
+
+    // 初期残高 (small: アドレス 0, 8 / large: アドレス 0, 16 の前半 8 byte)
+    small.write_transaction(|tr| {
+        store!(tr, 0, INITIAL.to_le_bytes());
+        store!(tr, 8, INITIAL.to_le_bytes());
+        tl2::STMResult::Ok(())
+    });
+    large.write_transaction(|tr| {
+        let mut account = [0; 16];
+        account[..8].copy_from_slice(&INITIAL.to_le_bytes());
+        store!(tr, 0, account);
+        store!(tr, 16, account);
+        tl2::STMResult::Ok(())
+    });
+
+    thread::scope(|s| {
+        for i in 0..THREADS {
+            let (small, large) = (&small, &large);
+            s.spawn(move || {
+                // スレッドごとに送金の向きを変える
+                let (from, to) = if i % 2 == 0 { (0, 1) } else { (1, 0) };
+                for _ in 0..TRANSFERS {
+                    small.write_transaction(|tr| {
+                        let a = u64::from_le_bytes(load!(tr, from * 8));
+                        let b = u64::from_le_bytes(load!(tr, to * 8));
+                        if a == 0 {
+                            return tl2::STMResult::Ok(());
+                        }
+                        store!(tr, from * 8, (a - 1).to_le_bytes());
+                        store!(tr, to * 8, (b + 1).to_le_bytes());
+                        tl2::STMResult::Ok(())
+                    });
+                    large.write_transaction(|tr| {
+                        let mut a = load!(tr, from * 16);
+                        let mut b = load!(tr, to * 16);
+                        let balance_a = u64::from_le_bytes(a[..8].try_into().unwrap());
+                        let balance_b = u64::from_le_bytes(b[..8].try_into().unwrap());
+                        let count = u64::from_le_bytes(a[8..].try_into().unwrap());
+                        if balance_a == 0 {
+                            return tl2::STMResult::Ok(());
+                        }
+                        a[..8].copy_from_slice(&(balance_a - 1).to_le_bytes());
+                        a[8..].copy_from_slice(&(count + 1).to_le_bytes());
+                        b[..8].copy_from_slice(&(balance_b + 1).to_le_bytes());
+                        store!(tr, from * 16, a);
+                        store!(tr, to * 16, b);
+                        tl2::STMResult::Ok(())
+                    });
+                }
+            });
+        }
+    });
+
+    let small_total = small.read_transaction(|tr| {
+        let a = u64::from_le_bytes(load!(tr, 0));
+        let b = u64::from_le_bytes(load!(tr, 8));
+        tl2::STMResult::Ok(a + b)
+    }).unwrap();
+    let (large_total, large_count) = large.read_transaction(|tr| {
+        let a = load!(tr, 0);
+        let b = load!(tr, 16);
+        let total = u64::from_le_bytes(a[..8].try_into().unwrap()) + u64::from_le_bytes(b[..8].try_into().unwrap());
+        let count = u64::from_le_bytes(a[8..].try_into().unwrap()) + u64::from_le_bytes(b[8..].try_into().unwrap());
+        tl2::STMResult::Ok((total, count))
+    }).unwrap();
+
+    assert_eq!(small_total, 2 * INITIAL);
+    assert_eq!(large_total, 2 * INITIAL);
+    // 残高が 0 になると送金しないため、送金回数は上限以下
+    assert!(large_count <= THREADS as u64 * TRANSFERS);
+}
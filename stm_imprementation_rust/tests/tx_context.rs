@@ -0,0 +1,84 @@
+// 使い回した TxContext には前のトランザクションの書き込み・読み込み・lock が残らない
+
+use std::cell::Cell;
+use std::thread;
+
+use stm_rust::tl2::{self, TxContext, STM};
+use stm_rust::{load, store};
+
+const THREADS: u64 = 4;
+const INCREMENTS: u64 = 2000;
+
+#[test]
+fn reused_context_carries_no_state() {
+    let stm = STM::new();
+    let mut ctx = TxContext::new();
+
+    // abort したトランザクションの書き込みは残らない
+    let aborted: Option<()> = stm.write_transaction_in(&mut ctx, |tr| {
+        store!(tr, 0, 99u64.to_le_bytes());
+        tl2::STMResult::Abort
+    });
+    assert_eq!(aborted, None);
+    stm.write_transaction_in(&mut ctx, |tr| {
+        assert_eq!(tr.write_addresses().count(), 0, "a reused context must start with an empty write set");
+        store!(tr, 8, 1u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    }).unwrap();
+    assert_eq!(stm.read_raw(0), [0; 8]);
+
+    // 前のトランザクションの read_set は残らない
+    stm.write_transaction_in(&mut ctx, |tr| {
+        let _ = load!(tr, 16);
+        store!(tr, 24, 1u64.to_le_bytes());
+        tl2::STMResult::Ok(())
+    }).unwrap();
+    let runs = Cell::new(0);
+    stm.write_transaction_in(&mut ctx, |tr| {
+        runs.set(runs.get() + 1);
+        if runs.get() == 1 {
+            stm.write_transaction(|other| {     // 前のトランザクションだけが読んだストライプに commit する
+                store!(other, 16, 5u64.to_le_bytes());
+                tl2::STMResult::Ok(())
+            });
+        }
+        let v = u64::from_le_bytes(load!(tr, 24));
+        store!(tr, 24, (v + 1).to_le_bytes());
+        tl2::STMResult::Ok(())
+    }).unwrap();
+    assert_eq!(runs.get(), 1, "reads of a previous transaction must not cause conflicts");
+
+    // 競合して retry した実行の lock は解放されている
+    runs.set(0);
+    stm.write_transaction_in(&mut ctx, |tr| {
+        runs.set(runs.get() + 1);
+        let v = u64::from_le_bytes(load!(tr, 32));
+        if runs.get() == 1 {
+            stm.write_transaction(|other| {
+                store!(other, 32, 7u64.to_le_bytes());
+                tl2::STMResult::Ok(())
+            });
+        }
+        store!(tr, 32, (v + 1).to_le_bytes());
+        tl2::STMResult::Ok(())
+    }).unwrap();
+    assert_eq!(runs.get(), 2);
+    assert!(stm.locked_stripes().is_empty());
+    assert_eq!(u64::from_le_bytes(stm.read_raw(32)), 8);
+
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                let mut ctx = TxContext::new();
+                for _ in 0..INCREMENTS {
+                    stm.write_transaction_in(&mut ctx, |tr| {
+                        let v = u64::from_le_bytes(load!(tr, 40)) + 1;
+                        store!(tr, 40, v.to_le_bytes());
+                        tl2::STMResult::Ok(())
+                    }).unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(u64::from_le_bytes(stm.read_raw(40)), THREADS * INCREMENTS);
+}
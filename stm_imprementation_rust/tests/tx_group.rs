@@ -0,0 +1,81 @@
+// Retry した TxGroup の member の書き込みと on_commit の登録は破棄される
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use stm_rust::tl2::{self, TxGroup, STM};
+use stm_rust::{load, store};
+
+const A: usize = 0;
+const B: usize = 8;
+const C: usize = 16;
+const FALLBACK: usize = 24;
+
+#[test]
+fn retried_members_discard_writes_and_callbacks() {
+    let stm: &'static STM = Box::leak(Box::new(STM::new()));
+    let log = Rc::new(RefCell::new(Vec::new()));    // on_commit で実行された処理の記録
+
+    let member = |addr: usize, name: &'static str| {
+        let log = log.clone();
+        move |tr: &mut tl2::WriteTrans<'_>| {
+            store!(tr, addr, 1u64.to_le_bytes());
+            let log = log.clone();
+            tr.on_commit(move || log.borrow_mut().push(name));
+            tl2::STMResult::Ok(())
+        }
+    };
+
+    // 1. 3 つ目の member が条件を満たさず Retry を返す
+    let group = TxGroup::new()
+        .member(member(A, "a"))
+        .member(member(B, "b"))
+        .member(|tr| {
+            if u64::from_le_bytes(load!(tr, C)) == 0 {
+                return tl2::STMResult::Retry;
+            }
+            tl2::STMResult::Ok(())
+        });
+    assert_eq!(group.len(), 3);
+    stm.write_transaction(|tr| {
+        let log = log.clone();
+        tr.on_commit(move || log.borrow_mut().push("outer"));
+        match group.run_in(tr) {
+            tl2::STMResult::Retry => {
+                assert_eq!(tr.pending_callbacks(), 1, "callbacks queued by the group must be discarded");
+                assert_eq!(load!(tr, A), [0; 8], "stores staged by the group must be discarded");
+                store!(tr, FALLBACK, 1u64.to_le_bytes());
+                tl2::STMResult::Ok(())
+            }
+            other => other,
+        }
+    }).unwrap();
+    assert_eq!(*log.borrow(), ["outer"]);
+    assert_eq!((stm.read_raw(A), stm.read_raw(B)), ([0; 8], [0; 8]));
+    assert_eq!(u64::from_le_bytes(stm.read_raw(FALLBACK)), 1);
+
+    // 2. 3 つ目の member が初回の実行で競合する (実行中に他のトランザクションが C に commit する)
+    log.borrow_mut().clear();
+    let runs = Rc::new(Cell::new(0));
+    let third = {
+        let runs = runs.clone();
+        move |tr: &mut tl2::WriteTrans<'_>| {
+            runs.set(runs.get() + 1);
+            if runs.get() == 1 {
+                stm.write_transaction(|other| {
+                    store!(other, C, 5u64.to_le_bytes());
+                    tl2::STMResult::Ok(())
+                });
+            }
+            let c = u64::from_le_bytes(load!(tr, C));
+            store!(tr, C, (c + 1).to_le_bytes());
+            tl2::STMResult::Ok(())
+        }
+    };
+    let group = TxGroup::new().member(member(A, "a")).member(member(B, "b")).member(third);
+    assert_eq!(stm.run_group(&group), Some(()));
+    assert_eq!(runs.get(), 2);
+    assert_eq!(*log.borrow(), ["a", "b"], "callbacks of the conflicted attempt must not run");
+    assert_eq!(u64::from_le_bytes(stm.read_raw(C)), 6);
+    assert_eq!(u64::from_le_bytes(stm.read_raw(A)), 1);
+}
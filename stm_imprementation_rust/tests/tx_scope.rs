@@ -0,0 +1,52 @@
+// scope_write は finish した場合にのみ commit し、後から finish した競合する scope は Conflict になる
+
+use stm_rust::tl2::{ApplyOutcome, TxScope, STM};
+
+fn read(stm: &STM, addr: usize) -> u64 {
+    u64::from_le_bytes(stm.read_raw(addr))
+}
+
+// 値を 1 増やす操作を scope に積む (競合した load は None)
+fn increment(tx: &mut TxScope<'_>, addr: usize) -> Option<u64> {
+    let v = u64::from_le_bytes(tx.load(addr)?) + 1;
+    tx.store(addr, v.to_le_bytes());
+    Some(v)
+}
+
+#[test]
+fn scopes_commit_only_when_finished() {
+    let stm = STM::new();
+
+    // finish しなければ abort する
+    {
+        let mut tx = stm.scope_write();
+        increment(&mut tx, 0).unwrap();
+        increment(&mut tx, 8).unwrap();
+    }
+    assert_eq!((read(&stm, 0), read(&stm, 8)), (0, 0));
+    assert_eq!(stm.global_version(), 0);
+    assert!(stm.locked_stripes().is_empty());
+
+    // finish すると commit する
+    let mut tx = stm.scope_write();
+    increment(&mut tx, 0).unwrap();
+    increment(&mut tx, 8).unwrap();
+    assert_eq!(tx.finish(), ApplyOutcome::Committed(1));
+    assert_eq!((read(&stm, 0), read(&stm, 8)), (1, 1));
+
+    // 同じストライプを読み書きする 2 つの scope: 先に finish した方のみ commit される
+    let mut first = stm.scope_write();
+    let mut second = stm.scope_write();
+    increment(&mut first, 0).unwrap();
+    increment(&mut second, 0).unwrap();
+    assert_eq!(first.finish(), ApplyOutcome::Committed(2));
+    assert_eq!(second.finish(), ApplyOutcome::Conflict { addr: Some(0) });
+    assert_eq!(read(&stm, 0), 2);
+
+    // request_abort した scope は finish しても書き込まない
+    let mut tx = stm.scope_write();
+    increment(&mut tx, 0).unwrap();
+    tx.request_abort();
+    assert_eq!(tx.finish(), ApplyOutcome::Aborted);
+    assert_eq!(read(&stm, 0), 2);
+}